@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    fs,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+use crate::GameState;
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<AnimationLibrary>()
+
+            .add_startup_system(load_animations)
+            .add_system(attach_player_animator)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(play_split)
+                    .with_system(
+                        drive_animators
+                        .after(play_split)
+                    )
+                    .with_system(
+                        animate_sprites
+                        .after(drive_animators)
+                    )
+            );
+    }
+}
+
+pub const ANIMATION_FILE: &str = "assets/data/animations.json";
+// pixels per second an entity has to move before it counts as walking
+const WALK_THRESHOLD: f32 = 4.;
+
+// ANIMATION DATA
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum AnimState {
+    Idle,
+    WalkUp,
+    WalkDown,
+    WalkLeft,
+    WalkRight,
+    Interact,
+    Split,
+}
+
+impl Default for AnimState {
+    fn default() -> Self {
+        AnimState::Idle
+    }
+}
+
+impl AnimState {
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        match identifier {
+            "Idle" => Some(AnimState::Idle),
+            "WalkUp" => Some(AnimState::WalkUp),
+            "WalkDown" => Some(AnimState::WalkDown),
+            "WalkLeft" => Some(AnimState::WalkLeft),
+            "WalkRight" => Some(AnimState::WalkRight),
+            "Interact" => Some(AnimState::Interact),
+            "Split" => Some(AnimState::Split),
+            _ => None,
+        }
+    }
+
+    // states that play through once before anything else can take over
+    pub fn is_one_shot(&self) -> bool {
+        matches!(self, AnimState::Split)
+    }
+}
+
+/// A run of frames on a sprite sheet, first and last inclusive.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationClip {
+    pub first: usize,
+    pub last: usize,
+    pub fps: f32,
+    #[serde(default = "default_looping")]
+    pub looping: bool,
+    // lets WalkLeft reuse the WalkRight frames, left alone when not set
+    #[serde(default)]
+    pub flip_x: Option<bool>,
+}
+
+fn default_looping() -> bool {
+    true
+}
+
+impl AnimationClip {
+    pub fn len(&self) -> usize {
+        if self.is_empty() { 0 } else { self.last - self.first + 1 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last < self.first
+    }
+
+    // sheet index shown `elapsed` seconds into the clip
+    pub fn frame_at(&self, elapsed: f32) -> usize {
+        let frame = (elapsed * self.fps).max(0.) as usize;
+        let frame = if self.looping {
+            frame % self.len()
+        } else {
+            frame.min(self.len() - 1)
+        };
+        self.first + frame
+    }
+
+    pub fn finished_at(&self, elapsed: f32) -> bool {
+        !self.looping && elapsed * self.fps >= self.len() as f32
+    }
+}
+
+/// The clips one sprite sheet offers. Missing states fall back to `Idle`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnimationSet(pub HashMap<AnimState, AnimationClip>);
+
+impl AnimationSet {
+    pub fn clip(&self, state: AnimState) -> Option<&AnimationClip> {
+        self.0.get(&state).or_else(|| self.0.get(&AnimState::Idle))
+    }
+}
+
+// RESOURCES
+
+#[derive(Clone, Debug, Default)]
+pub struct AnimationLibrary(pub HashMap<String, AnimationSet>);
+
+// ANIMATION COMPONENTS
+
+/// Per entity state machine picking a clip from the named `AnimationSet`.
+#[derive(Clone, Debug, Default, Component)]
+pub struct Animator {
+    pub set: String,
+    pub state: AnimState,
+    // seconds since the current state began
+    pub elapsed: f32,
+    pub last_position: Option<Vec2>,
+}
+
+impl Animator {
+    pub fn new(set: &str) -> Self {
+        Animator {
+            set: set.to_string(),
+            ..default()
+        }
+    }
+
+    // switching to the state already playing carries on where it was
+    pub fn set_state(&mut self, state: AnimState) {
+        if self.state != state {
+            self.state = state;
+            self.elapsed = 0.;
+        }
+    }
+}
+
+// SYSTEMS
+
+fn load_animations(
+    mut library: ResMut<AnimationLibrary>,
+) {
+    let string_data = match fs::read_to_string(ANIMATION_FILE) {
+        Ok(string_data) => string_data,
+        Err(error) => {
+            error!("Could not read {}: {}", ANIMATION_FILE, error);
+            return;
+        }
+    };
+
+    match serde_json::from_str::<HashMap<String, AnimationSet>>(&string_data) {
+        Ok(sets) => library.0.extend(sets),
+        Err(error) => error!("Could not parse {}: {}", ANIMATION_FILE, error),
+    }
+}
+
+// Only the body comes from LDtk, limbs are single frames off the body sheet
+fn attach_player_animator(
+    mut commands: Commands,
+    body_parts: Query<(Entity, &BodyPart), (Added<BodyPart>, Without<Animator>)>,
+) {
+    for (entity, part) in body_parts.iter() {
+        if *part == BodyPart::Body {
+            commands.entity(entity).insert(Animator::new("player"));
+        }
+    }
+}
+
+fn play_split(
+    mut detached: EventReader<LimbDetached>,
+    mut body_parts: Query<(&BodyPart, &mut Animator)>,
+) {
+    if detached.iter().last().is_none() {
+        return;
+    }
+
+    for (part, mut animator) in body_parts.iter_mut() {
+        if *part == BodyPart::Body {
+            animator.set_state(AnimState::Split);
+        }
+    }
+}
+
+// Walks follow how far the entity moved since last frame, whatever moved it.
+// The selected body part plays Interact while the player is busy talking.
+fn drive_animators(
+    time: Res<Time>,
+    library: Res<AnimationLibrary>,
+    player: Query<(&Player, &BodyParts)>,
+    mut animators: Query<(&mut Animator, &GlobalTransform, Option<&BodyPart>)>,
+) {
+    let (player, selected) = match player.get_single() {
+        Ok((player, body_parts)) => (Some(*player), Some(body_parts.current())),
+        Err(_) => (None, None),
+    };
+    let delta = time.delta_seconds();
+
+    for (mut animator, transform, part) in animators.iter_mut() {
+        let position = transform.translation().truncate();
+        let velocity = match animator.last_position {
+            Some(last) if delta > 0. => (position - last) / delta,
+            _ => Vec2::ZERO,
+        };
+        animator.last_position = Some(position);
+
+        // let a one shot finish first, unless the set has no clip for it
+        if animator.state.is_one_shot() {
+            let finished = library.0
+                .get(&animator.set)
+                .and_then(|set| set.0.get(&animator.state))
+                .map_or(true, |clip| clip.finished_at(animator.elapsed));
+            if !finished {
+                continue;
+            }
+        }
+
+        let interacting = part.is_some()
+            && part.copied() == selected
+            && player == Some(Player::Interacting);
+
+        let state = if interacting {
+            AnimState::Interact
+        } else if velocity.length() < WALK_THRESHOLD {
+            AnimState::Idle
+        } else if velocity.x.abs() > velocity.y.abs() {
+            if velocity.x < 0. { AnimState::WalkLeft } else { AnimState::WalkRight }
+        } else if velocity.y < 0. {
+            AnimState::WalkDown
+        } else {
+            AnimState::WalkUp
+        };
+        animator.set_state(state);
+    }
+}
+
+fn animate_sprites(
+    time: Res<Time>,
+    library: Res<AnimationLibrary>,
+    mut animators: Query<(&mut Animator, &mut TextureAtlasSprite)>,
+) {
+    for (mut animator, mut sprite) in animators.iter_mut() {
+        animator.elapsed += time.delta_seconds();
+
+        let clip = match library.0.get(&animator.set).and_then(|set| set.clip(animator.state)) {
+            Some(clip) if !clip.is_empty() => clip,
+            _ => continue,
+        };
+
+        let index = clip.frame_at(animator.elapsed);
+        if sprite.index != index {
+            sprite.index = index;
+        }
+        if let Some(flip_x) = clip.flip_x {
+            if sprite.flip_x != flip_x {
+                sprite.flip_x = flip_x;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(first: usize, last: usize, fps: f32, looping: bool) -> AnimationClip {
+        AnimationClip {
+            first,
+            last,
+            fps,
+            looping,
+            flip_x: None,
+        }
+    }
+
+    #[test]
+    fn looping_clips_wrap_around() {
+        let walk = clip(4, 7, 10., true);
+        assert_eq!(walk.frame_at(0.), 4);
+        assert_eq!(walk.frame_at(0.35), 7);
+        assert_eq!(walk.frame_at(0.45), 4);
+        assert_eq!(walk.frame_at(0.65), 6);
+        assert!(!walk.finished_at(100.));
+    }
+
+    #[test]
+    fn one_shots_hold_their_last_frame() {
+        let split = clip(2, 4, 10., false);
+        assert_eq!(split.frame_at(0.15), 3);
+        assert_eq!(split.frame_at(0.25), 4);
+        assert_eq!(split.frame_at(5.), 4);
+        assert!(!split.finished_at(0.25));
+        assert!(split.finished_at(0.3));
+    }
+
+    #[test]
+    fn frames_change_on_whole_frame_boundaries() {
+        // a frame shows for its whole 1 / fps seconds, never rounded up early
+        let idle = clip(0, 3, 2.5, true);
+        assert_eq!(idle.frame_at(0.39), 0);
+        assert_eq!(idle.frame_at(0.41), 1);
+        assert_eq!(idle.frame_at(0.79), 1);
+        assert_eq!(idle.frame_at(0.81), 2);
+        // time before the clip started shows the first frame
+        assert_eq!(idle.frame_at(-1.), 0);
+    }
+
+    #[test]
+    fn every_set_has_a_clip_for_every_state() {
+        let string_data = fs::read_to_string(ANIMATION_FILE).unwrap();
+        let sets: HashMap<String, AnimationSet> = serde_json::from_str(&string_data).unwrap();
+        let states = [
+            AnimState::Idle,
+            AnimState::WalkUp,
+            AnimState::WalkDown,
+            AnimState::WalkLeft,
+            AnimState::WalkRight,
+            AnimState::Interact,
+            AnimState::Split,
+        ];
+
+        for (name, set) in sets.iter() {
+            for state in states {
+                let clip = set.0.get(&state).unwrap_or_else(|| panic!("{} has no {:?} clip", name, state));
+                assert!(!clip.is_empty(), "{} {:?} has no frames", name, state);
+                assert_eq!(clip.looping, !state.is_one_shot(), "{} {:?}", name, state);
+            }
+        }
+        assert!(sets.contains_key("player"));
+    }
+}
@@ -0,0 +1,924 @@
+use std::{
+    error::Error,
+    fmt,
+    io::Read,
+};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    sprite::Anchor,
+    utils::BoxedFuture,
+};
+use bevy_rapier2d::prelude::*;
+use flate2::read::ZlibDecoder;
+
+use crate::animation::{AnimState, AnimationClip, AnimationLibrary, AnimationSet};
+
+pub struct AsepritePlugin;
+
+impl Plugin for AsepritePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_asset::<Aseprite>()
+            .init_asset_loader::<AsepriteLoader>()
+
+            .add_startup_system(load_aseprites)
+            // runs in every state so saved files are picked up anywhere
+            .add_system(register_aseprite_animations)
+            .add_system(apply_slice_hitboxes);
+    }
+}
+
+// loaded up front so their tags reach the AnimationLibrary and saves are watched
+pub const ASEPRITE_FILES: [&str; 12] = [
+    "visual/2high_props.aseprite",
+    "visual/NPCS.ase",
+    "visual/barstool.aseprite",
+    "visual/doors.aseprite",
+    "visual/floor.aseprite",
+    "visual/magic_mirror.aseprite",
+    "visual/props.aseprite",
+    "visual/rocks.aseprite",
+    "visual/special_autotile.aseprite",
+    "visual/stone-counter.aseprite",
+    "visual/walls.ase",
+    "visual/wisps.aseprite",
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct AsepriteFiles(pub Vec<Handle<Aseprite>>);
+
+// ASEPRITE ASSETS
+
+/// A loaded `.aseprite` or `.ase` file. The frames are laid side by side in
+/// `image` and `atlas` indexes them in order, so frame `n` is sprite index `n`.
+/// Both are labeled assets too, `"visual/NPCS.ase#atlas"` loads the atlas alone.
+#[derive(Clone, Debug, Default, TypeUuid)]
+#[uuid = "3b6f2e9a-5c1d-4f8b-a7e2-91d04c6b8f35"]
+pub struct Aseprite {
+    // file name without the extension, the key its tags go under in the AnimationLibrary
+    pub name: String,
+    pub image: Handle<Image>,
+    pub atlas: Handle<TextureAtlas>,
+    pub frame_size: Vec2,
+    // milliseconds per frame
+    pub durations: Vec<u32>,
+    pub tags: Vec<AsepriteTag>,
+    pub slices: Vec<AsepriteSlice>,
+}
+
+impl Aseprite {
+    pub fn tag(&self, name: &str) -> Option<&AsepriteTag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    pub fn slice(&self, name: &str) -> Option<&AsepriteSlice> {
+        self.slices.iter().find(|slice| slice.name == name)
+    }
+
+    /// A clip for every tag named after an `AnimState`, like `WalkLeft`. Tags
+    /// with other names are left for whoever asks for them with `tag`.
+    pub fn animation_set(&self) -> AnimationSet {
+        let mut set = AnimationSet::default();
+
+        for tag in self.tags.iter() {
+            let state = match AnimState::from_identifier(&tag.name) {
+                Some(state) => state,
+                None => continue,
+            };
+            if tag.direction != TagDirection::Forward {
+                warn!("{} tag {} plays {:?}, clips only play forward", self.name, tag.name, tag.direction);
+            }
+
+            let frames = tag.to.saturating_sub(tag.from) + 1;
+            let milliseconds: u32 = self.durations
+                .get(tag.from..=tag.to)
+                .map_or(0, |durations| durations.iter().sum());
+            let fps = if milliseconds > 0 {
+                frames as f32 * 1000. / milliseconds as f32
+            } else {
+                DEFAULT_FPS
+            };
+
+            set.0.insert(state, AnimationClip {
+                first: tag.from,
+                last: tag.to,
+                fps,
+                // older files always write a repeat of 0
+                looping: tag.repeat == 0 && !state.is_one_shot(),
+                flip_x: None,
+            });
+        }
+        set
+    }
+
+    /// Center and half extents of a slice on `frame`, in pixels from the
+    /// middle of the frame with y up, the way a `Collider` on the sprite sees it.
+    pub fn hitbox(&self, name: &str, frame: usize) -> Option<(Vec2, Vec2)> {
+        let key = self.slice(name)?.key_at(frame)?;
+        let half_size = key.size.as_vec2() / 2.;
+        Some((self.to_local(key.position.as_vec2() + half_size), half_size))
+    }
+
+    /// The pivot of a slice on `frame`, in the same space as `hitbox`.
+    pub fn pivot(&self, name: &str, frame: usize) -> Option<Vec2> {
+        let key = self.slice(name)?.key_at(frame)?;
+        Some(self.to_local((key.position + key.pivot?).as_vec2()))
+    }
+
+    // file pixels count down from the top left corner
+    fn to_local(&self, pixel: Vec2) -> Vec2 {
+        Vec2::new(pixel.x - self.frame_size.x / 2., self.frame_size.y / 2. - pixel.y)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TagDirection {
+    Forward,
+    Reverse,
+    PingPong,
+}
+
+/// A named run of frames, first and last inclusive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsepriteTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    pub direction: TagDirection,
+    // 0 repeats forever
+    pub repeat: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsepriteSlice {
+    pub name: String,
+    // sorted by frame, a key holds until the next one starts
+    pub keys: Vec<SliceKey>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SliceKey {
+    pub frame: usize,
+    // top left corner, in file pixels
+    pub position: IVec2,
+    pub size: UVec2,
+    // relative to position
+    pub pivot: Option<IVec2>,
+}
+
+impl AsepriteSlice {
+    pub fn key_at(&self, frame: usize) -> Option<&SliceKey> {
+        self.keys
+            .iter()
+            .filter(|key| key.frame <= frame)
+            .last()
+            .or_else(|| self.keys.first())
+    }
+}
+
+#[derive(Default)]
+pub struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let file = parse_aseprite(bytes)?;
+            let name = load_context
+                .path()
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            let image = load_context.set_labeled_asset("image", LoadedAsset::new(file.strip()));
+            let frame_size = Vec2::new(file.width as f32, file.height as f32);
+            let atlas = TextureAtlas::from_grid(image.clone(), frame_size, file.frames.len(), 1);
+            let atlas = load_context.set_labeled_asset("atlas", LoadedAsset::new(atlas));
+
+            load_context.set_default_asset(LoadedAsset::new(Aseprite {
+                name,
+                image,
+                atlas,
+                frame_size,
+                durations: file.durations,
+                tags: file.tags,
+                slices: file.slices,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["aseprite", "ase"]
+    }
+}
+
+// ASEPRITE FORMAT
+
+const HEADER_SIZE: usize = 128;
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const LAYER_CHUNK: u16 = 0x2004;
+const CEL_CHUNK: u16 = 0x2005;
+const TAGS_CHUNK: u16 = 0x2018;
+const PALETTE_CHUNK: u16 = 0x2019;
+const SLICE_CHUNK: u16 = 0x2022;
+
+// header flag, without it layer opacity is garbage and counts as opaque
+const LAYER_OPACITY_VALID: u32 = 1;
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_BACKGROUND: u16 = 8;
+const SLICE_NINE_PATCH: u32 = 1;
+const SLICE_PIVOT: u32 = 2;
+
+// used when a tag's frames have no duration
+const DEFAULT_FPS: f32 = 10.;
+
+/// What `parse_aseprite` reads out of a file, with every frame already
+/// flattened to RGBA.
+#[derive(Clone, Debug, Default)]
+pub struct AsepriteFile {
+    pub width: u32,
+    pub height: u32,
+    // width * height * 4 bytes each
+    pub frames: Vec<Vec<u8>>,
+    // milliseconds
+    pub durations: Vec<u32>,
+    pub tags: Vec<AsepriteTag>,
+    pub slices: Vec<AsepriteSlice>,
+}
+
+impl AsepriteFile {
+    // every frame side by side, left to right
+    pub fn strip(&self) -> Image {
+        let row_bytes = self.width as usize * 4;
+        let strip_width = self.width * self.frames.len() as u32;
+        let mut data = vec![0; strip_width as usize * self.height as usize * 4];
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            for row in 0..self.height as usize {
+                let to = (row * strip_width as usize * 4) + index * row_bytes;
+                data[to..to + row_bytes].copy_from_slice(&frame[row * row_bytes..(row + 1) * row_bytes]);
+            }
+        }
+
+        Image::new(
+            Extent3d {
+                width: strip_width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct AsepriteError {
+    // byte offset into the file
+    pub offset: usize,
+    pub message: String,
+}
+
+impl AsepriteError {
+    fn new(offset: usize, message: impl Into<String>) -> Self {
+        AsepriteError { offset, message: message.into() }
+    }
+}
+
+impl fmt::Display for AsepriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl Error for AsepriteError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ColorDepth {
+    Rgba,
+    Grayscale,
+    Indexed,
+}
+
+impl ColorDepth {
+    fn bytes_per_pixel(&self) -> usize {
+        match self {
+            ColorDepth::Rgba => 4,
+            ColorDepth::Grayscale => 2,
+            ColorDepth::Indexed => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Layer {
+    // hidden layers, and layers inside hidden groups, are left out
+    visible: bool,
+    // groups and tilemaps hold no pixels of their own
+    is_image: bool,
+    background: bool,
+    opacity: u8,
+}
+
+#[derive(Clone, Debug)]
+struct Cel {
+    layer: usize,
+    position: IVec2,
+    opacity: u8,
+    content: CelContent,
+}
+
+#[derive(Clone, Debug)]
+enum CelContent {
+    Pixels { width: usize, height: usize, data: Vec<u8> },
+    // reuses the cel on the same layer of another frame
+    Linked(usize),
+}
+
+/// Reads an Aseprite file, following
+/// <https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md>.
+///
+/// Visible image layers are flattened bottom to top with normal blending,
+/// whatever blend mode they were given. Tilemap layers and cels are skipped.
+pub fn parse_aseprite(bytes: &[u8]) -> Result<AsepriteFile, AsepriteError> {
+    let mut reader = Reader::new(bytes);
+
+    let mut header = reader.chunk(HEADER_SIZE)?;
+    header.skip(4)?;
+    if header.u16()? != HEADER_MAGIC {
+        return Err(AsepriteError::new(4, "not an Aseprite file"));
+    }
+    let frame_count = header.u16()? as usize;
+    let width = header.u16()? as u32;
+    let height = header.u16()? as u32;
+    let depth = match header.u16()? {
+        32 => ColorDepth::Rgba,
+        16 => ColorDepth::Grayscale,
+        8 => ColorDepth::Indexed,
+        bits => return Err(AsepriteError::new(12, format!("unknown color depth {}", bits))),
+    };
+    let flags = header.u32()?;
+    // speed, then two reserved words
+    header.skip(10)?;
+    let transparent_index = header.u8()?;
+
+    if frame_count == 0 || width == 0 || height == 0 {
+        return Err(AsepriteError::new(6, "file has no pixels"));
+    }
+
+    let mut file = AsepriteFile {
+        width,
+        height,
+        ..default()
+    };
+    let mut layers: Vec<Layer> = Vec::new();
+    // visibility of the innermost group at each child level
+    let mut groups: Vec<bool> = Vec::new();
+    let mut palette = vec![[0, 0, 0, 255]; 256];
+    let mut cels: Vec<Vec<Cel>> = Vec::with_capacity(frame_count);
+
+    for frame in 0..frame_count {
+        let frame_start = reader.position;
+        let frame_size = reader.u32()? as usize;
+        let mut frame_reader = reader.chunk(frame_size.saturating_sub(4))?;
+
+        if frame_reader.u16()? != FRAME_MAGIC {
+            return Err(AsepriteError::new(frame_start + 4, format!("frame {} is corrupt", frame)));
+        }
+        let old_chunk_count = frame_reader.u16()? as usize;
+        file.durations.push(frame_reader.u16()? as u32);
+        frame_reader.skip(2)?;
+        let chunk_count = match frame_reader.u32()? as usize {
+            0 => old_chunk_count,
+            count => count,
+        };
+
+        let mut frame_cels = Vec::new();
+        for _ in 0..chunk_count {
+            let chunk_size = frame_reader.u32()? as usize;
+            let chunk_type = frame_reader.u16()?;
+            let mut chunk = frame_reader.chunk(chunk_size.saturating_sub(6))?;
+
+            match chunk_type {
+                LAYER_CHUNK => {
+                    let layer_flags = chunk.u16()?;
+                    let layer_type = chunk.u16()?;
+                    let level = chunk.u16()? as usize;
+                    // default width and height, then blend mode
+                    chunk.skip(6)?;
+                    let opacity = chunk.u8()?;
+
+                    groups.truncate(level);
+                    let parent_visible = level == 0 || groups.last().copied().unwrap_or(true);
+                    let visible = parent_visible && layer_flags & LAYER_VISIBLE != 0;
+                    groups.push(visible);
+
+                    layers.push(Layer {
+                        visible,
+                        is_image: layer_type == 0,
+                        background: layer_flags & LAYER_BACKGROUND != 0,
+                        opacity: if flags & LAYER_OPACITY_VALID != 0 { opacity } else { 255 },
+                    });
+                }
+
+                CEL_CHUNK => {
+                    let start = chunk.position;
+                    let layer = chunk.u16()? as usize;
+                    let position = IVec2::new(chunk.i16()? as i32, chunk.i16()? as i32);
+                    let opacity = chunk.u8()?;
+                    let cel_type = chunk.u16()?;
+                    chunk.skip(7)?;
+
+                    let content = match cel_type {
+                        0 | 2 => {
+                            let width = chunk.u16()? as usize;
+                            let height = chunk.u16()? as usize;
+                            let data = if cel_type == 0 {
+                                chunk.rest().to_vec()
+                            } else {
+                                let mut data = Vec::with_capacity(width * height * depth.bytes_per_pixel());
+                                ZlibDecoder::new(chunk.rest())
+                                    .read_to_end(&mut data)
+                                    .map_err(|error| AsepriteError::new(start, format!("cel data is corrupt: {}", error)))?;
+                                data
+                            };
+                            if data.len() < width * height * depth.bytes_per_pixel() {
+                                return Err(AsepriteError::new(start, "cel is missing pixels"));
+                            }
+                            CelContent::Pixels { width, height, data }
+                        }
+                        1 => CelContent::Linked(chunk.u16()? as usize),
+                        // tilemap cel
+                        _ => continue,
+                    };
+                    frame_cels.push(Cel { layer, position, opacity, content });
+                }
+
+                PALETTE_CHUNK => {
+                    chunk.skip(4)?;
+                    let first = chunk.u32()? as usize;
+                    let last = chunk.u32()? as usize;
+                    chunk.skip(8)?;
+                    for index in first..=last {
+                        let entry_flags = chunk.u16()?;
+                        let color = chunk.take(4)?;
+                        if index >= palette.len() {
+                            palette.resize(index + 1, [0, 0, 0, 255]);
+                        }
+                        palette[index] = [color[0], color[1], color[2], color[3]];
+                        if entry_flags & 1 != 0 {
+                            chunk.string()?;
+                        }
+                    }
+                }
+
+                TAGS_CHUNK => {
+                    let count = chunk.u16()?;
+                    chunk.skip(8)?;
+                    for _ in 0..count {
+                        let from = chunk.u16()? as usize;
+                        let to = chunk.u16()? as usize;
+                        let direction = match chunk.u8()? {
+                            1 => TagDirection::Reverse,
+                            2 | 3 => TagDirection::PingPong,
+                            _ => TagDirection::Forward,
+                        };
+                        let repeat = chunk.u16()?;
+                        // reserved, then the tag color
+                        chunk.skip(10)?;
+                        let name = chunk.string()?;
+                        file.tags.push(AsepriteTag { name, from, to, direction, repeat });
+                    }
+                }
+
+                SLICE_CHUNK => {
+                    let key_count = chunk.u32()?;
+                    let slice_flags = chunk.u32()?;
+                    chunk.skip(4)?;
+                    let name = chunk.string()?;
+
+                    let mut keys = Vec::new();
+                    for _ in 0..key_count {
+                        let frame = chunk.u32()? as usize;
+                        let position = IVec2::new(chunk.i32()?, chunk.i32()?);
+                        let size = UVec2::new(chunk.u32()?, chunk.u32()?);
+                        if slice_flags & SLICE_NINE_PATCH != 0 {
+                            chunk.skip(16)?;
+                        }
+                        let pivot = if slice_flags & SLICE_PIVOT != 0 {
+                            Some(IVec2::new(chunk.i32()?, chunk.i32()?))
+                        } else {
+                            None
+                        };
+                        keys.push(SliceKey { frame, position, size, pivot });
+                    }
+                    keys.sort_by_key(|key| key.frame);
+                    file.slices.push(AsepriteSlice { name, keys });
+                }
+
+                _ => {}
+            }
+        }
+        cels.push(frame_cels);
+    }
+
+    for frame_cels in cels.iter() {
+        let mut image = vec![0; width as usize * height as usize * 4];
+
+        let mut visible: Vec<&Cel> = frame_cels
+            .iter()
+            .filter_map(|cel| match cel.content {
+                CelContent::Linked(frame) => cels
+                    .get(frame)?
+                    .iter()
+                    .find(|linked| linked.layer == cel.layer && matches!(linked.content, CelContent::Pixels { .. })),
+                CelContent::Pixels { .. } => Some(cel),
+            })
+            .filter(|cel| layers.get(cel.layer).map_or(false, |layer| layer.visible && layer.is_image))
+            .collect();
+        // layers are stored bottom to top
+        visible.sort_by_key(|cel| cel.layer);
+
+        for cel in visible {
+            let layer = &layers[cel.layer];
+            let opacity = cel.opacity as u32 * layer.opacity as u32 / 255;
+            let (cel_width, cel_height, data) = match &cel.content {
+                CelContent::Pixels { width, height, data } => (*width, *height, data),
+                CelContent::Linked(_) => continue,
+            };
+
+            for y in 0..cel_height {
+                for x in 0..cel_width {
+                    let target = cel.position + IVec2::new(x as i32, y as i32);
+                    if target.x < 0 || target.y < 0 || target.x >= width as i32 || target.y >= height as i32 {
+                        continue;
+                    }
+
+                    let source = y * cel_width + x;
+                    let color = match depth {
+                        ColorDepth::Rgba => {
+                            let pixel = &data[source * 4..source * 4 + 4];
+                            [pixel[0], pixel[1], pixel[2], pixel[3]]
+                        }
+                        ColorDepth::Grayscale => {
+                            let pixel = &data[source * 2..source * 2 + 2];
+                            [pixel[0], pixel[0], pixel[0], pixel[1]]
+                        }
+                        ColorDepth::Indexed => {
+                            let index = data[source];
+                            // the background layer is the one place the transparent index is drawn
+                            if index == transparent_index && !layer.background {
+                                continue;
+                            }
+                            palette.get(index as usize).copied().unwrap_or([0, 0, 0, 0])
+                        }
+                    };
+
+                    let offset = (target.y as usize * width as usize + target.x as usize) * 4;
+                    blend(&mut image[offset..offset + 4], color, opacity);
+                }
+            }
+        }
+        file.frames.push(image);
+    }
+
+    Ok(file)
+}
+
+// normal blend mode, `color` drawn over `below` at `opacity` out of 255
+fn blend(below: &mut [u8], color: [u8; 4], opacity: u32) {
+    let alpha = color[3] as u32 * opacity / 255;
+    if alpha == 0 {
+        return;
+    }
+
+    let below_alpha = below[3] as u32 * (255 - alpha) / 255;
+    let out_alpha = alpha + below_alpha;
+    for (channel, color) in below.iter_mut().zip(color).take(3) {
+        *channel = ((color as u32 * alpha + *channel as u32 * below_alpha) / out_alpha) as u8;
+    }
+    below[3] = out_alpha as u8;
+}
+
+// Little endian reader that remembers where it is in the whole file, so
+// errors can point at the byte that broke.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], AsepriteError> {
+        let end = self.position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| AsepriteError::new(self.position, "file ends early"))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), AsepriteError> {
+        self.take(len).map(|_| ())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.position..];
+        self.position = self.bytes.len();
+        rest
+    }
+
+    // the next len bytes as a reader of their own
+    fn chunk(&mut self, len: usize) -> Result<Reader<'a>, AsepriteError> {
+        let start = self.position;
+        self.skip(len)?;
+        Ok(Reader {
+            bytes: &self.bytes[..self.position],
+            position: start,
+        })
+    }
+
+    fn u8(&mut self) -> Result<u8, AsepriteError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, AsepriteError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, AsepriteError> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, AsepriteError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, AsepriteError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn string(&mut self) -> Result<String, AsepriteError> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+// ASEPRITE COMPONENTS
+
+/// Gives the entity a collider from a slice of an Aseprite file, and moves the
+/// sprite's anchor to the slice's pivot when it has one. Saving the file
+/// updates both.
+#[derive(Clone, Debug, Default, Component)]
+pub struct SliceHitbox {
+    pub aseprite: Handle<Aseprite>,
+    pub slice: String,
+}
+
+// SYSTEMS
+
+fn load_aseprites(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let handles = ASEPRITE_FILES
+        .iter()
+        .map(|path| asset_server.load(*path))
+        .collect();
+
+    commands.insert_resource(AsepriteFiles(handles));
+    info!("Began loading aseprite files");
+}
+
+// Tag clips are merged over the set of the same name, so animations.json can
+// still fill in states a file has no tag for.
+fn register_aseprite_animations(
+    mut aseprite_events: EventReader<AssetEvent<Aseprite>>,
+    aseprites: Res<Assets<Aseprite>>,
+    mut library: ResMut<AnimationLibrary>,
+) {
+    for event in aseprite_events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        let aseprite = match aseprites.get(handle) {
+            Some(aseprite) => aseprite,
+            None => continue,
+        };
+
+        let set = aseprite.animation_set();
+        if set.0.is_empty() {
+            continue;
+        }
+        info!("Loaded {} animations from {}", set.0.len(), aseprite.name);
+        library.0.entry(aseprite.name.clone()).or_default().0.extend(set.0);
+    }
+}
+
+fn apply_slice_hitboxes(
+    mut commands: Commands,
+    mut aseprite_events: EventReader<AssetEvent<Aseprite>>,
+    aseprites: Res<Assets<Aseprite>>,
+    mut hitboxes: Query<(Entity, &SliceHitbox, ChangeTrackers<SliceHitbox>, Option<&mut TextureAtlasSprite>)>,
+) {
+    let changed: Vec<Handle<Aseprite>> = aseprite_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle.clone()),
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect();
+
+    for (entity, hitbox, tracker, sprite) in hitboxes.iter_mut() {
+        if !tracker.is_changed() && !changed.contains(&hitbox.aseprite) {
+            continue;
+        }
+        // not loaded yet, its Created event will bring us back
+        let aseprite = match aseprites.get(&hitbox.aseprite) {
+            Some(aseprite) => aseprite,
+            None => continue,
+        };
+
+        let frame = sprite.as_ref().map_or(0, |sprite| sprite.index);
+        let (center, half_size) = match aseprite.hitbox(&hitbox.slice, frame) {
+            Some(hitbox) => hitbox,
+            None => {
+                warn!("{} has no slice {}", aseprite.name, hitbox.slice);
+                continue;
+            }
+        };
+
+        // with the anchor on the pivot, the entity's origin is the pivot
+        let pivot = aseprite.pivot(&hitbox.slice, frame);
+        if let (Some(pivot), Some(mut sprite)) = (pivot, sprite) {
+            sprite.anchor = Anchor::Custom(pivot / aseprite.frame_size);
+        }
+
+        commands.entity(entity).insert(Collider::compound(vec![
+            (center - pivot.unwrap_or(Vec2::ZERO), 0., Collider::cuboid(half_size.x, half_size.y)),
+        ]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    // two 4x4 frames: a 2x2 zlib cel on a visible layer, a raw cel covering
+    // everything on a hidden one, and a second frame linking back to the
+    // first. Tagged WalkLeft and Blink, with a pivoted slice keyed twice.
+    const FIXTURE: &str = "tests/fixtures/tagged.aseprite";
+
+    fn pixel(file: &AsepriteFile, frame: usize, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * file.width as usize + x) * 4;
+        let pixel = &file.frames[frame][offset..offset + 4];
+        [pixel[0], pixel[1], pixel[2], pixel[3]]
+    }
+
+    fn fixture() -> AsepriteFile {
+        parse_aseprite(&fs::read(FIXTURE).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_npc_sheet_parses() {
+        let file = parse_aseprite(&fs::read("assets/visual/NPCS.ase").unwrap()).unwrap();
+
+        assert_eq!((file.width, file.height), (32, 32));
+        assert_eq!(file.frames.len(), 9);
+        assert!(file.frames.iter().all(|frame| frame.len() == 32 * 32 * 4));
+        assert_eq!(file.durations, vec![100; 9]);
+        assert!(file.tags.is_empty());
+        assert!(file.slices.is_empty());
+
+        let drawn: Vec<usize> = file.frames
+            .iter()
+            .map(|frame| frame.chunks(4).filter(|pixel| pixel[3] > 0).count())
+            .collect();
+        assert_eq!(drawn, vec![110, 164, 152, 127, 127, 130, 130, 146, 154]);
+        assert_eq!(pixel(&file, 0, 14, 3), [255, 239, 204, 255]);
+        assert_eq!(pixel(&file, 8, 10, 12), [170, 0, 204, 255]);
+
+        let strip = file.strip();
+        assert_eq!(strip.texture_descriptor.size.width, 32 * 9);
+        assert_eq!(strip.texture_descriptor.size.height, 32);
+    }
+
+    #[test]
+    fn visible_cels_are_flattened_and_hidden_layers_left_out() {
+        let file = fixture();
+
+        assert_eq!((file.width, file.height), (4, 4));
+        assert_eq!(file.durations, vec![100, 300]);
+        assert_eq!(pixel(&file, 0, 1, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(&file, 0, 2, 1), [0, 0, 255, 128]);
+        assert_eq!(pixel(&file, 0, 1, 2), [255, 255, 255, 255]);
+        // the hidden layer is solid green
+        assert_eq!(pixel(&file, 0, 0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&file, 0, 2, 2), [0, 0, 0, 0]);
+        // linked to the first frame
+        assert_eq!(file.frames[1], file.frames[0]);
+    }
+
+    #[test]
+    fn tags_are_read() {
+        assert_eq!(fixture().tags, vec![
+            AsepriteTag {
+                name: "WalkLeft".to_string(),
+                from: 0,
+                to: 1,
+                direction: TagDirection::Forward,
+                repeat: 0,
+            },
+            AsepriteTag {
+                name: "Blink".to_string(),
+                from: 1,
+                to: 1,
+                direction: TagDirection::PingPong,
+                repeat: 2,
+            },
+        ]);
+    }
+
+    #[test]
+    fn slice_keys_are_read_in_frame_order() {
+        assert_eq!(fixture().slices, vec![AsepriteSlice {
+            name: "hitbox".to_string(),
+            keys: vec![
+                SliceKey {
+                    frame: 0,
+                    position: IVec2::new(0, 1),
+                    size: UVec2::new(4, 3),
+                    pivot: Some(IVec2::new(2, 3)),
+                },
+                SliceKey {
+                    frame: 1,
+                    position: IVec2::new(2, 0),
+                    size: UVec2::new(2, 2),
+                    pivot: Some(IVec2::new(1, 2)),
+                },
+            ],
+        }]);
+    }
+
+    #[test]
+    fn tags_and_slices_reach_the_asset() {
+        let file = fixture();
+        let aseprite = Aseprite {
+            name: "tagged".to_string(),
+            frame_size: Vec2::new(file.width as f32, file.height as f32),
+            durations: file.durations,
+            tags: file.tags,
+            slices: file.slices,
+            ..default()
+        };
+
+        // two frames over 400ms
+        let set = aseprite.animation_set();
+        assert_eq!(set.0.len(), 1);
+        assert_eq!(set.0.get(&AnimState::WalkLeft), Some(&AnimationClip {
+            first: 0,
+            last: 1,
+            fps: 5.,
+            looping: true,
+            flip_x: None,
+        }));
+
+        assert_eq!(aseprite.hitbox("hitbox", 0), Some((Vec2::new(0., -0.5), Vec2::new(2., 1.5))));
+        assert_eq!(aseprite.pivot("hitbox", 0), Some(Vec2::new(0., -2.)));
+        // the last key holds for every later frame
+        assert_eq!(aseprite.hitbox("hitbox", 5), Some((Vec2::new(1., 1.), Vec2::new(1., 1.))));
+        assert_eq!(aseprite.pivot("hitbox", 5), Some(Vec2::new(1., 0.)));
+        assert_eq!(aseprite.hitbox("missing", 0), None);
+    }
+
+    #[test]
+    fn broken_files_are_reported() {
+        let mut bytes = fs::read(FIXTURE).unwrap();
+
+        let error = parse_aseprite(&bytes[..200]).unwrap_err();
+        assert_eq!(error.message, "file ends early");
+
+        bytes[4] = 0;
+        let error = parse_aseprite(&bytes).unwrap_err();
+        assert_eq!(error.offset, 4);
+    }
+}
@@ -0,0 +1,517 @@
+use std::fs;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages},
+        texture::ImageSampler,
+    },
+};
+use bevy_ecs_ldtk::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+use crate::movement::player_movement;
+use crate::GameState;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraConfig>()
+            .init_resource::<Cursor>()
+
+            .add_startup_system(load_camera_config)
+            .add_system(scale_canvas)
+            // before any gameplay system looks at it, and before a replay
+            // swaps in the recorded one
+            .add_system_to_stage(CoreStage::PreUpdate, track_cursor)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(zoom_camera)
+                    .with_system(
+                        follow_camera
+                        .after(player_movement)
+                        .after(zoom_camera)
+                    )
+            );
+    }
+}
+
+pub const CAMERA_FILE: &str = "assets/data/camera.json";
+
+// RESOURCES
+
+/// Tuning for every `CameraRig`, read from camera.json. Missing keys keep
+/// their default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    // how quickly the camera closes the gap to where it wants to be, per second
+    pub smoothing: f32,
+    // half extents of the box the followed part can move in without the camera moving
+    pub dead_zone: Vec2,
+    // pixels the camera leads a moving part by
+    pub look_ahead: f32,
+    // like smoothing, for the look ahead turning and settling
+    pub look_ahead_smoothing: f32,
+    // seconds to pan over to a newly selected part
+    pub pan_duration: f32,
+    // keeps the view inside the level the followed part is in
+    pub clamp_to_level: bool,
+    // size of the canvas the world is drawn to, in screen pixels before scaling
+    pub resolution: UVec2,
+    // how many canvas pixels a world pixel covers at each zoom step, smallest
+    // first. Whole numbers only, anything else drops or doubles texels.
+    pub zoom_levels: Vec<f32>,
+    pub default_zoom: f32,
+    // pixels kept clear around the body and limbs when zoomed to fit them
+    pub fit_margin: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            smoothing: 8.,
+            dead_zone: Vec2::new(12., 8.),
+            look_ahead: 16.,
+            look_ahead_smoothing: 3.,
+            pan_duration: 0.4,
+            clamp_to_level: true,
+            // a third of 1080p, room to see the limbs at 1x
+            resolution: UVec2::new(640, 360),
+            zoom_levels: vec![1., 2., 3.],
+            default_zoom: 1.,
+            fit_margin: 24.,
+        }
+    }
+}
+
+fn is_whole_zoom(zoom: f32) -> bool {
+    zoom >= 1. && zoom.fract() == 0.
+}
+
+impl CameraConfig {
+    // Leaves out zoom levels that are not whole numbers and moves a bad
+    // default_zoom to the nearest level that is left
+    pub fn check_zoom(&mut self) {
+        if !self.zoom_levels.iter().all(|level| is_whole_zoom(*level)) {
+            warn!("{} has zoom levels that are not whole numbers, leaving them out", CAMERA_FILE);
+            self.zoom_levels.retain(|level| is_whole_zoom(*level));
+        }
+
+        if !is_whole_zoom(self.default_zoom) {
+            let default_zoom = self.default_zoom;
+            let nearest = self.zoom_levels
+                .iter()
+                .copied()
+                .min_by(|a, b| (a - default_zoom).abs().total_cmp(&(b - default_zoom).abs()))
+                .unwrap_or_else(|| default_zoom.round().max(1.));
+            warn!("{} has a default_zoom of {}, using {} instead", CAMERA_FILE, default_zoom, nearest);
+            self.default_zoom = nearest;
+        }
+    }
+
+    pub fn zoom_in(&self, zoom: f32) -> f32 {
+        self.zoom_levels.iter().copied().find(|level| *level > zoom).unwrap_or(zoom)
+    }
+
+    pub fn zoom_out(&self, zoom: f32) -> f32 {
+        self.zoom_levels.iter().copied().rev().find(|level| *level < zoom).unwrap_or(zoom)
+    }
+
+    // the closest zoom step that still shows `size` pixels of the world
+    pub fn zoom_to_fit(&self, size: Vec2) -> f32 {
+        let size = size + Vec2::splat(self.fit_margin * 2.);
+        let most = (self.resolution.as_vec2() / size).min_element();
+        self.zoom_levels
+            .iter()
+            .copied()
+            .rev()
+            .find(|level| *level <= most)
+            .or_else(|| self.zoom_levels.first().copied())
+            .unwrap_or(self.default_zoom)
+    }
+}
+
+/// The offscreen image the world camera draws to. It is shown in the middle
+/// of the window, scaled up by the largest whole number that fits, with black
+/// bars around it.
+#[derive(Clone, Debug, Default)]
+pub struct PixelCanvas {
+    pub image: Handle<Image>,
+    pub resolution: UVec2,
+    // logical window pixels per canvas pixel
+    pub scale: f32,
+}
+
+impl PixelCanvas {
+    pub fn image(resolution: UVec2) -> Image {
+        let size = Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            ..default()
+        };
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Bgra8UnormSrgb,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_DST
+                    | TextureUsages::RENDER_ATTACHMENT,
+            },
+            sampler_descriptor: ImageSampler::nearest(),
+            ..default()
+        };
+        image.resize(size);
+        image
+    }
+}
+
+/// Where the mouse points in the world, None off the canvas or without a
+/// window. Kept in world space so a recording of it replays the same at any
+/// window size.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Cursor(pub Option<Vec2>);
+
+// the canvas and the camera showing it live on their own render layer
+pub const CANVAS_LAYER: u8 = 1;
+
+// CAMERA COMPONENTS
+
+/// Makes a camera follow the current body part. The part can wander inside
+/// the dead zone around `focus` before the camera follows, and the camera
+/// leads it in the direction it moves.
+#[derive(Clone, Debug, Default, Component)]
+pub struct CameraRig {
+    // where the camera really is, its Transform is snapped to the pixel grid
+    pub position: Vec2,
+    pub focus: Vec2,
+    pub look_ahead: Vec2,
+    pub zoom: f32,
+    // frames the body and every detached limb instead of the current part
+    pub fit_limbs: bool,
+    // the part being followed and where it was last frame
+    pub followed: Option<BodyPart>,
+    pub last_target: Option<Vec2>,
+    pub pan: Option<CameraPan>,
+}
+
+/// The sprite showing the `PixelCanvas` on the window.
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct CanvasSprite;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraPan {
+    pub from: Vec2,
+    pub elapsed: f32,
+}
+
+impl CameraRig {
+    // the box of `dead_zone` half extents around focus is dragged along by the target
+    pub fn update_focus(&mut self, target: Vec2, dead_zone: Vec2) {
+        let offset = target - self.focus;
+        let excess = offset.abs() - dead_zone;
+        if excess.x > 0. {
+            self.focus.x += excess.x * offset.x.signum();
+        }
+        if excess.y > 0. {
+            self.focus.y += excess.y * offset.y.signum();
+        }
+    }
+}
+
+// fraction of the way to close this frame, so smoothing is the same at any frame rate
+fn smoothing_factor(rate: f32, delta: f32) -> f32 {
+    1. - (-rate * delta).exp()
+}
+
+fn ease_in_out(t: f32) -> f32 {
+    let t = t.clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Moves `center` so a view of `half_view` half extents stays inside
+/// `min`..`max`, centering it on any axis where the view is the bigger one.
+pub fn clamp_view(center: Vec2, half_view: Vec2, min: Vec2, max: Vec2) -> Vec2 {
+    let clamp_axis = |center: f32, half: f32, min: f32, max: f32| {
+        if max - min <= half * 2. {
+            (min + max) / 2.
+        } else {
+            center.clamp(min + half, max - half)
+        }
+    };
+    Vec2::new(
+        clamp_axis(center.x, half_view.x, min.x, max.x),
+        clamp_axis(center.y, half_view.y, min.y, max.y),
+    )
+}
+
+// Bevy 0.8 has no viewport_to_world, so the cursor goes back through the
+// canvas and the camera's projection by hand. The letterbox is off the map.
+pub fn cursor_to_world(
+    window: &Window,
+    canvas: &PixelCanvas,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let window_center = Vec2::new(window.width(), window.height()) / 2.;
+    let canvas_half = canvas.resolution.as_vec2() * canvas.scale / 2.;
+    let ndc = (cursor - window_center) / canvas_half;
+    if ndc.abs().max_element() > 1. {
+        return None;
+    }
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    Some(ndc_to_world.project_point3(ndc.extend(-1.)).truncate())
+}
+
+// SYSTEMS
+
+fn load_camera_config(
+    mut config: ResMut<CameraConfig>,
+) {
+    let string_data = match fs::read_to_string(CAMERA_FILE) {
+        Ok(string_data) => string_data,
+        Err(error) => {
+            error!("Could not read {}: {}", CAMERA_FILE, error);
+            return;
+        }
+    };
+
+    match serde_json::from_str::<CameraConfig>(&string_data) {
+        Ok(mut loaded) => {
+            loaded.check_zoom();
+            *config = loaded;
+        }
+        Err(error) => error!("Could not parse {}: {}", CAMERA_FILE, error),
+    }
+}
+
+// Pans over to a newly selected part, then follows it through the dead zone
+// with look ahead. Levels are placed by their bottom left corner.
+fn follow_camera(
+    time: Res<Time>,
+    config: Res<CameraConfig>,
+    level_assets: Res<Assets<LdtkLevel>>,
+    player: Query<&BodyParts, With<Player>>,
+    body_parts: Query<(&Transform, &BodyPart), Without<CameraRig>>,
+    levels: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    mut camera: Query<(&mut CameraRig, &mut Transform, &mut OrthographicProjection)>,
+) {
+    let player = player.single();
+    let (mut rig, mut transform, mut projection) = camera.single_mut();
+    let delta = time.delta_seconds();
+
+    let current = player.current();
+    let mut target = match body_parts.iter().find(|(_, part)| **part == current) {
+        Some((part_transform, _)) => part_transform.translation.truncate(),
+        None => return,
+    };
+
+    let mut zoom = rig.zoom;
+    if rig.fit_limbs {
+        let (min, max) = body_parts.iter().fold((target, target), |(min, max), (part_transform, _)| {
+            let position = part_transform.translation.truncate();
+            (min.min(position), max.max(position))
+        });
+        target = (min + max) / 2.;
+        zoom = config.zoom_to_fit(max - min);
+    }
+    if zoom > 0. && projection.scale != 1. / zoom {
+        projection.scale = 1. / zoom;
+    }
+
+    match rig.followed {
+        // the first part followed is snapped to
+        None => {
+            rig.focus = target;
+            rig.position = target;
+        }
+        Some(followed) if followed != current => {
+            rig.pan = Some(CameraPan {
+                from: rig.position,
+                elapsed: 0.,
+            });
+            rig.focus = target;
+            rig.look_ahead = Vec2::ZERO;
+        }
+        Some(_) => {}
+    }
+    rig.followed = Some(current);
+    let position = rig.position;
+
+    let velocity = match rig.last_target {
+        Some(last) if delta > 0. => (target - last) / delta,
+        _ => Vec2::ZERO,
+    };
+    rig.last_target = Some(target);
+
+    rig.update_focus(target, config.dead_zone);
+    let lead = velocity.normalize_or_zero() * config.look_ahead;
+    rig.look_ahead = rig.look_ahead.lerp(lead, smoothing_factor(config.look_ahead_smoothing, delta));
+    let goal = rig.focus + rig.look_ahead;
+
+    let mut next = match rig.pan.as_mut() {
+        Some(pan) => {
+            pan.elapsed += delta;
+            let t = if config.pan_duration > 0. { pan.elapsed / config.pan_duration } else { 1. };
+            let from = pan.from;
+            if t >= 1. {
+                rig.pan = None;
+            }
+            from.lerp(goal, ease_in_out(t))
+        }
+        None => position.lerp(goal, smoothing_factor(config.smoothing, delta)),
+    };
+
+    if config.clamp_to_level {
+        let level = levels.iter().find_map(|(handle, level_transform)| {
+            let level = &level_assets.get(handle)?.level;
+            let min = level_transform.translation().truncate();
+            let max = min + Vec2::new(level.px_wid as f32, level.px_hei as f32);
+            if target.cmpge(min).all() && target.cmple(max).all() {
+                Some((min, max))
+            } else {
+                None
+            }
+        });
+
+        if let Some((min, max)) = level {
+            let half_view = Vec2::new(
+                projection.right - projection.left,
+                projection.top - projection.bottom,
+            ) * projection.scale / 2.;
+            next = clamp_view(next, half_view, min, max);
+        }
+    }
+
+    rig.position = next;
+    // whole canvas pixels, so the world does not shimmer as the camera glides
+    let snapped = if zoom > 0. { (next * zoom).round() / zoom } else { next };
+    transform.translation.x = snapped.x;
+    transform.translation.y = snapped.y;
+}
+
+// Zooming by hand leaves the fit mode
+fn zoom_camera(
+    config: Res<CameraConfig>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    mut camera: Query<&mut CameraRig>,
+) {
+    let action_state = action_state.single();
+    let mut rig = camera.single_mut();
+
+    if action_state.just_pressed(Action::ZoomIn) {
+        rig.zoom = config.zoom_in(rig.zoom);
+        rig.fit_limbs = false;
+    }
+    if action_state.just_pressed(Action::ZoomOut) {
+        rig.zoom = config.zoom_out(rig.zoom);
+        rig.fit_limbs = false;
+    }
+    if action_state.just_pressed(Action::ZoomToFit) {
+        rig.fit_limbs = !rig.fit_limbs;
+        info!("Zoom to fit limbs: {}", rig.fit_limbs);
+    }
+}
+
+fn track_cursor(
+    windows: Res<Windows>,
+    canvas: Option<Res<PixelCanvas>>,
+    camera: Query<(&Camera, &GlobalTransform), With<CameraRig>>,
+    mut cursor: ResMut<Cursor>,
+) {
+    let position = match (windows.get_primary(), canvas, camera.get_single()) {
+        (Some(window), Some(canvas), Ok((camera, camera_transform))) => cursor_to_world(window, &canvas, camera, camera_transform),
+        _ => None,
+    };
+    if cursor.0 != position {
+        cursor.0 = position;
+    }
+}
+
+// Physical pixels decide the whole number, so the canvas stays sharp on
+// scaled displays too.
+fn scale_canvas(
+    windows: Res<Windows>,
+    canvas: Option<ResMut<PixelCanvas>>,
+    mut sprites: Query<&mut Transform, With<CanvasSprite>>,
+) {
+    let (window, mut canvas) = match (windows.get_primary(), canvas) {
+        (Some(window), Some(canvas)) => (window, canvas),
+        _ => return,
+    };
+
+    let physical = Vec2::new(window.physical_width() as f32, window.physical_height() as f32);
+    let whole = (physical / canvas.resolution.as_vec2()).min_element().floor().max(1.);
+    let scale = whole / window.scale_factor() as f32;
+    if canvas.scale == scale {
+        return;
+    }
+
+    canvas.scale = scale;
+    for mut transform in sprites.iter_mut() {
+        transform.scale = Vec3::new(scale, scale, 1.);
+    }
+    info!("Canvas scaled {}x", whole);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zooming_steps_through_the_levels_and_stops_at_the_ends() {
+        let config = CameraConfig::default();
+        assert_eq!(config.zoom_in(1.), 2.);
+        assert_eq!(config.zoom_in(2.), 3.);
+        assert_eq!(config.zoom_in(3.), 3.);
+        assert_eq!(config.zoom_out(3.), 2.);
+        assert_eq!(config.zoom_out(1.), 1.);
+    }
+
+    #[test]
+    fn zoom_to_fit_picks_the_closest_step_that_shows_everything() {
+        let config = CameraConfig::default();
+        let margin = Vec2::splat(config.fit_margin * 2.);
+        // 640x360 at 3x shows 213x120 world pixels
+        assert_eq!(config.zoom_to_fit(Vec2::ZERO), 3.);
+        assert_eq!(config.zoom_to_fit(Vec2::new(200., 100.) - margin), 3.);
+        assert_eq!(config.zoom_to_fit(Vec2::new(300., 100.) - margin), 2.);
+        assert_eq!(config.zoom_to_fit(Vec2::new(600., 300.) - margin), 1.);
+    }
+
+    #[test]
+    fn zoom_to_fit_never_goes_below_a_whole_pixel() {
+        let config = CameraConfig::default();
+        assert_eq!(config.zoom_to_fit(Vec2::new(5000., 5000.)), 1.);
+    }
+
+    #[test]
+    fn a_fractional_default_zoom_moves_to_the_nearest_level() {
+        let mut config = CameraConfig {
+            zoom_levels: vec![1., 1.5, 2., 4.],
+            default_zoom: 2.9,
+            ..default()
+        };
+        config.check_zoom();
+        assert_eq!(config.zoom_levels, vec![1., 2., 4.]);
+        assert_eq!(config.default_zoom, 2.);
+
+        config.default_zoom = 0.5;
+        config.check_zoom();
+        assert_eq!(config.default_zoom, 1.);
+
+        // a whole default is kept as it is
+        config.default_zoom = 3.;
+        config.check_zoom();
+        assert_eq!(config.default_zoom, 3.);
+    }
+}
@@ -1,6 +1,13 @@
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use leafwing_input_manager::{Actionlike, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::flags::{field_condition, Condition};
+use crate::npc::NpcKind;
+use crate::puzzle::{Puzzle, PuzzleInput};
+use crate::triggers::BodyPartTrigger;
+use crate::ysort::SortAnchor;
 
 // PLAYER BUNDLES
 
@@ -9,12 +16,14 @@ pub struct PlayerParentBundle {
     pub player: Player,
     pub inventory: Inventory,
     pub body_parts: BodyParts,
-    pub arm_vec: ArmVec,
+    pub arm_aim: ArmAim,
 }
 
 #[derive(Default, Bundle, LdtkEntity)]
 pub struct PlayerBodyPartBundle {
     pub body_part: BodyPart,
+    #[from_entity_instance]
+    pub sort_anchor: SortAnchor,
     #[sprite_sheet_bundle]
     #[bundle]
     pub sprite_bundle: SpriteSheetBundle,
@@ -24,7 +33,7 @@ pub struct PlayerBodyPartBundle {
 
 // PLAYER COMPONENTS
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component, Serialize, Deserialize)]
 pub enum Player {
     Moving,
     Interacting
@@ -38,7 +47,7 @@ impl Default for Player {
 }
 
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component, Serialize, Deserialize)]
 pub enum BodyPart {
     Body,
     LeftArm,
@@ -54,32 +63,171 @@ impl Default for BodyPart {
     }
 }
 
+impl BodyPart {
+    // how hard the part presses down on weight plates
+    pub fn weight(&self) -> u32 {
+        match self {
+            BodyPart::Body => 6,
+            BodyPart::LeftLeg | BodyPart::RightLeg => 3,
+            BodyPart::LeftArm | BodyPart::RightArm => 1,
+            BodyPart::Head => 2,
+        }
+    }
+
+    // names as written in LDtk fields
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        match identifier {
+            "Body" => Some(BodyPart::Body),
+            "LeftArm" => Some(BodyPart::LeftArm),
+            "RightArm" => Some(BodyPart::RightArm),
+            "LeftLeg" => Some(BodyPart::LeftLeg),
+            "RightLeg" => Some(BodyPart::RightLeg),
+            "Head" => Some(BodyPart::Head),
+            _ => None,
+        }
+    }
+}
+
 
+/// The parts the player can switch between: the body first, then detached
+/// limbs in the order they came off. The body can never be removed, no part
+/// is listed twice, and `index` always points at the selected part.
 #[derive(Clone, Eq, PartialEq, Debug, Component)]
 pub struct BodyParts {
-    pub body_parts: Vec<BodyPart>,
-    pub current_part: BodyPart,
-    pub index: usize,
+    body_parts: Vec<BodyPart>,
+    index: usize,
 }
 
 impl Default for BodyParts {
     fn default() -> Self {
-        let mut new = BodyParts {
-            body_parts: Vec::new(),
-            current_part: BodyPart::Body,
+        BodyParts {
+            body_parts: vec![BodyPart::Body],
             index: 0,
+        }
+    }
+}
+
+impl BodyParts {
+    pub fn current(&self) -> BodyPart {
+        self.body_parts[self.index]
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn parts(&self) -> &[BodyPart] {
+        &self.body_parts
+    }
+
+    pub fn contains(&self, part: BodyPart) -> bool {
+        self.body_parts.contains(&part)
+    }
+
+    // adds a newly detached part and selects it, false if it is already listed
+    pub fn push(&mut self, part: BodyPart) -> bool {
+        if self.contains(part) {
+            return false;
+        }
+
+        self.body_parts.push(part);
+        self.index = self.body_parts.len() - 1;
+        self.check();
+        true
+    }
+
+    // keeps the same part selected, or falls back to the body if that was the
+    // part removed. False for the body and for parts that aren't listed.
+    pub fn remove(&mut self, part: BodyPart) -> bool {
+        if part == BodyPart::Body {
+            return false;
+        }
+        let position = match self.body_parts.iter().position(|listed| *listed == part) {
+            Some(position) => position,
+            None => return false,
         };
-        new.body_parts.push(new.current_part.clone());
-        new
+
+        let current = self.current();
+        self.body_parts.remove(position);
+        self.index = self.body_parts
+            .iter()
+            .position(|listed| *listed == current)
+            .unwrap_or(0);
+        self.check();
+        true
+    }
+
+    pub fn next(&mut self) -> BodyPart {
+        self.index = (self.index + 1) % self.body_parts.len();
+        self.current()
+    }
+
+    pub fn prev(&mut self) -> BodyPart {
+        self.index = (self.index + self.body_parts.len() - 1) % self.body_parts.len();
+        self.current()
+    }
+
+    // false, leaving the selection alone, if the part isn't listed
+    pub fn select(&mut self, part: BodyPart) -> bool {
+        match self.body_parts.iter().position(|listed| *listed == part) {
+            Some(position) => {
+                self.index = position;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn check(&self) {
+        debug_assert_eq!(self.body_parts.first(), Some(&BodyPart::Body));
+        debug_assert!(self.index < self.body_parts.len());
+        debug_assert!(self
+            .body_parts
+            .iter()
+            .enumerate()
+            .all(|(i, part)| !self.body_parts[..i].contains(part)));
     }
 }
 
+// Slingshot aim for the selected arm, in world space
 #[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
-pub struct ArmVec(pub Vec2);
+pub struct ArmAim {
+    // where the mouse drag began
+    pub drag_start: Option<Vec2>,
+    // how far the arm is pulled back, it flies the opposite way
+    pub pull: Option<Vec2>,
+    pub stick_held: bool,
+}
 
 #[derive(Component)]
 pub struct Inventory(pub [Option<Item>; 8]);
 
+impl Inventory {
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.iter().flatten().any(|item| item.id == id)
+    }
+
+    // returns false if every slot is taken
+    pub fn give(&mut self, item: Item) -> bool {
+        for slot in self.0.iter_mut() {
+            if slot.is_none() {
+                *slot = Some(item);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn take(&mut self, id: &str) -> Option<Item> {
+        for slot in self.0.iter_mut() {
+            if slot.as_ref().map_or(false, |item| item.id == id) {
+                return slot.take();
+            }
+        }
+        None
+    }
+}
+
 impl Default for Inventory {
     fn default() -> Self {
         let new = Inventory([
@@ -98,6 +246,8 @@ impl Default for Inventory {
 pub struct InteractableBundle {
     #[from_entity_instance]
     pub interactable: Interactable,
+    #[from_entity_instance]
+    pub sort_anchor: SortAnchor,
     #[sprite_sheet_bundle]
     #[bundle]
     pub sprite_bundle: SpriteSheetBundle,
@@ -109,6 +259,10 @@ pub struct InteractableBundle {
 pub struct NpcBundle {
     #[from_entity_instance]
     pub interactable: Interactable,
+    #[from_entity_instance]
+    pub kind: NpcKind,
+    #[from_entity_instance]
+    pub sort_anchor: SortAnchor,
     #[sprite_sheet_bundle]
     #[bundle]
     pub sprite_bundle: SpriteSheetBundle,
@@ -116,21 +270,6 @@ pub struct NpcBundle {
     pub worldly: Worldly,
 }
 
-impl Default for NpcBundle {
-    fn default() -> Self {
-        info!("Made NPC");
-        NpcBundle {
-            interactable: Interactable {
-                can_interact: false,
-                interaction_type: InteractionType::Person,
-                person: Some(default()),
-                ..default()
-            },
-            ..default()
-        }
-    }
-}
-
 #[derive(Bundle, Clone, LdtkEntity)]
 pub struct InteractableObject {
     pub interactable: Interactable,
@@ -159,6 +298,8 @@ impl Default for InteractableObject {
 pub struct DoorObject {
     #[from_entity_instance]
     pub interactable: Interactable,
+    #[from_entity_instance]
+    pub sort_anchor: SortAnchor,
     #[sprite_sheet_bundle]
     #[bundle]
     pub sprite_bundle: SpriteSheetBundle,
@@ -188,18 +329,22 @@ pub struct Interactable {
     pub can_interact: bool,
     pub interaction_type: InteractionType,
     pub item: Option<Item>,
-    pub puzzle: Option<Puzzle>,
+    // item sources only, a reusable one never runs out
+    pub reusable: bool,
+    pub puzzle: Option<PuzzleSwitch>,
     pub person: Option<Person>,
     pub door: Option<Door>,
+    // from the optional LDtk `Condition` field, nothing happens while it fails
+    pub condition: Option<Condition>,
 }
 
 impl From<EntityInstance> for Interactable {
     fn from(entity_instance: EntityInstance) -> Self {
-        match entity_instance.identifier.as_ref() {
+        let interactable = match entity_instance.identifier.as_ref() {
             "NPC_spawn" => Interactable {
                 can_interact: false,
                 interaction_type: InteractionType::Person,
-                person: Some(default()),
+                person: Some(Person(field_string(&entity_instance, "Dialogue"))),
                 ..default()
             },
         
@@ -209,9 +354,27 @@ impl From<EntityInstance> for Interactable {
                 item: Some(Item {
                     id: "Gumball".to_string(),
                 }), 
+                reusable: true,
                 ..default()
             },
             
+            "Switch" => Interactable {
+                can_interact: false,
+                interaction_type: InteractionType::Puzzle,
+                puzzle: Some(PuzzleSwitch {
+                    puzzle: field_string(&entity_instance, "Puzzle").unwrap_or_default(),
+                    element: field_string(&entity_instance, "Element").unwrap_or_default(),
+                }),
+                ..default()
+            },
+
+            "WoodDoor" => Interactable {
+                can_interact: false,
+                interaction_type: InteractionType::Door,
+                door: Some(default()),
+                ..default()
+            },
+
             "Vendor" => Interactable {
                 can_interact: false,
                 interaction_type: InteractionType::Item,  
@@ -220,10 +383,47 @@ impl From<EntityInstance> for Interactable {
             },
 
             _ => Interactable::default(),
+        };
+
+        Interactable {
+            condition: field_condition(&entity_instance, "Condition"),
+            ..interactable
         }
     }
 }
 
+// Reads an optional String field off an LDtk entity
+pub fn field_string(entity_instance: &EntityInstance, identifier: &str) -> Option<String> {
+    entity_instance.field_instances
+        .iter()
+        .find(|field| field.identifier == identifier)
+        .and_then(|field| match &field.value {
+            FieldValue::String(value) => value.clone(),
+            _ => None,
+        })
+}
+
+pub fn field_int(entity_instance: &EntityInstance, identifier: &str) -> Option<i32> {
+    entity_instance.field_instances
+        .iter()
+        .find(|field| field.identifier == identifier)
+        .and_then(|field| match &field.value {
+            FieldValue::Int(value) => *value,
+            _ => None,
+        })
+}
+
+// Reads the value name of an LDtk enum field
+pub fn field_enum(entity_instance: &EntityInstance, identifier: &str) -> Option<String> {
+    entity_instance.field_instances
+        .iter()
+        .find(|field| field.identifier == identifier)
+        .and_then(|field| match &field.value {
+            FieldValue::Enum(value) => value.clone(),
+            _ => None,
+        })
+}
+
 #[derive(Clone)]
 pub struct Item {
     pub id: String,
@@ -236,31 +436,20 @@ impl Default for Item {
     }
 }
 
+// index into the visible choices of the current dialogue node
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
-pub struct DialogueChoice;
-
-#[derive(Copy, Clone, Default, Component)]
-pub struct Puzzle;
-
-#[derive(Clone)]
-pub struct Dialogue {
-    pub prompt: String,
-    pub finished: bool,
+pub struct DialogueChoice(pub usize);
 
-    pub choice1: Option<(String, usize)>,
-    pub choice2: Option<(String, usize)>,
-    pub choice3: Option<(String, usize)>,
+// ties a switch to the Puzzle entity with the same id
+#[derive(Clone, Debug, Default)]
+pub struct PuzzleSwitch {
+    pub puzzle: String,
+    pub element: String,
 }
 
-#[derive(Clone)]
-pub struct Person(pub Vec<Dialogue>);
-
-impl Default for Person {
-    fn default() -> Self {
-        let new = Person(Vec::new());
-        new
-    }
-}
+// id of the dialogue node the conversation starts at
+#[derive(Clone, Default)]
+pub struct Person(pub Option<String>);
 
 #[derive(Clone)]
 pub struct Door {
@@ -293,8 +482,51 @@ impl Default for InteractionType {
     }
 }
 
+#[derive(Bundle, Clone, Default, LdtkEntity)]
+pub struct PuzzleBundle {
+    #[from_entity_instance]
+    pub puzzle: Puzzle,
+}
+
+#[derive(Bundle, Clone, LdtkEntity)]
+pub struct TriggerBundle {
+    #[from_entity_instance]
+    pub trigger: BodyPartTrigger,
+    #[from_entity_instance]
+    pub sort_anchor: SortAnchor,
+    #[sprite_sheet_bundle]
+    #[bundle]
+    pub sprite_bundle: SpriteSheetBundle,
+    #[worldly]
+    pub worldly: Worldly,
+}
+
+#[derive(Bundle, Clone, Default, LdtkEntity)]
+pub struct WaypointBundle {
+    #[from_entity_instance]
+    pub waypoint: Waypoint,
+}
+
+// one stop of an NPC patrol route, placed in LDtk
+#[derive(Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Waypoint {
+    pub route: String,
+    pub order: i32,
+}
+
+impl From<EntityInstance> for Waypoint {
+    fn from(entity_instance: EntityInstance) -> Self {
+        Waypoint {
+            route: field_string(&entity_instance, "Route").unwrap_or_default(),
+            order: field_int(&entity_instance, "Order").unwrap_or_default(),
+        }
+    }
+}
+
 #[derive(Bundle, Clone, Default, LdtkEntity)]
 pub struct BackgroundObject {
+    #[from_entity_instance]
+    pub sort_anchor: SortAnchor,
     #[sprite_sheet_bundle]
     #[bundle]
     pub sprite_bundle: SpriteSheetBundle,
@@ -315,18 +547,247 @@ pub enum Action {
 
     Unused,
     Split,
+    Merge,
+    Recall,
+
+    SelectBody,
+    SelectHead,
+    SelectLeftArm,
+    SelectRightArm,
+    SelectLeftLeg,
+    SelectRightLeg,
+
+    Aim,
+
+    ZoomIn,
+    ZoomOut,
+    ZoomToFit,
+
+    Journal,
+
+    // menus
+    Confirm,
+    Back,
+    Pause,
 }
 
 // EVENTS
 
 #[derive(Clone)]
 pub struct Hotkeys(pub InputMap<Action>);
-pub struct ItemInteraction(pub Item);
-pub struct PuzzleInteraction(pub Puzzle);
-pub struct PersonInteraction(pub Person, pub (usize, usize));
+pub struct ItemInteraction(pub Item, pub Entity);
+pub struct PuzzleInteraction(pub PuzzleSwitch, pub Entity);
+pub struct PersonInteraction(pub Person, pub Entity);
 
 pub struct DoorInteraction(pub Door, pub Entity);
 pub struct InteractionWrapper(pub Entity, pub InteractionType);
 
 pub struct RoomChange(pub usize);
-pub struct CameraSetupEvent;
\ No newline at end of file
+
+// puzzle id and what happened to it
+pub struct PuzzleSignal(pub String, pub PuzzleInput);
+pub struct PuzzleSolved(pub String);
+pub struct PuzzleFailed(pub String);
+
+// quest id, and the index of the step that was finished
+pub struct QuestStarted(pub String);
+pub struct QuestStepCompleted(pub String, pub usize);
+pub struct QuestCompleted(pub String);
+
+// trigger entity, body part entity, and which part it is
+pub struct BodyPartEntered(pub Entity, pub Entity, pub BodyPart);
+pub struct BodyPartExited(pub Entity, pub Entity, pub BodyPart);
+
+// limb entity and part, for animation and audio to hook into
+pub struct LimbDetached(pub Entity, pub BodyPart);
+pub struct LimbRecalled(pub Entity, pub BodyPart);
+pub struct LimbReattached(pub BodyPart);
+pub struct CameraSetupEvent;
+// New Game was picked on the main menu, throws away the session played before
+pub struct NewGame;
+// Continue was picked on the main menu, loads the newest save
+pub struct ContinueGame;
+// save slot to write or read
+pub struct SaveGame(pub usize);
+pub struct LoadGame(pub usize);
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+
+    const ALL_PARTS: [BodyPart; 6] = [
+        BodyPart::Body,
+        BodyPart::LeftArm,
+        BodyPart::RightArm,
+        BodyPart::LeftLeg,
+        BodyPart::RightLeg,
+        BodyPart::Head,
+    ];
+
+    #[derive(Copy, Clone, Debug)]
+    enum Op {
+        Push(BodyPart),
+        Remove(BodyPart),
+        Select(BodyPart),
+        Next,
+        Prev,
+    }
+
+    // the same rules written as plainly as possible, to check BodyParts against
+    #[derive(Debug)]
+    struct Model {
+        parts: Vec<BodyPart>,
+        current: BodyPart,
+    }
+
+    impl Model {
+        fn apply(&mut self, op: Op) -> Option<bool> {
+            let position = |parts: &[BodyPart], part| parts.iter().position(|listed| *listed == part);
+            match op {
+                Op::Push(part) => {
+                    if self.parts.contains(&part) {
+                        return Some(false);
+                    }
+                    self.parts.push(part);
+                    self.current = part;
+                    Some(true)
+                }
+                Op::Remove(part) => {
+                    if part == BodyPart::Body || !self.parts.contains(&part) {
+                        return Some(false);
+                    }
+                    self.parts.retain(|listed| *listed != part);
+                    if self.current == part {
+                        self.current = BodyPart::Body;
+                    }
+                    Some(true)
+                }
+                Op::Select(part) => {
+                    let found = self.parts.contains(&part);
+                    if found {
+                        self.current = part;
+                    }
+                    Some(found)
+                }
+                Op::Next => {
+                    let index = position(&self.parts, self.current).unwrap();
+                    self.current = self.parts[(index + 1) % self.parts.len()];
+                    None
+                }
+                Op::Prev => {
+                    let index = position(&self.parts, self.current).unwrap();
+                    self.current = self.parts[(index + self.parts.len() - 1) % self.parts.len()];
+                    None
+                }
+            }
+        }
+    }
+
+    fn apply(body_parts: &mut BodyParts, op: Op) -> Option<bool> {
+        match op {
+            Op::Push(part) => Some(body_parts.push(part)),
+            Op::Remove(part) => Some(body_parts.remove(part)),
+            Op::Select(part) => Some(body_parts.select(part)),
+            Op::Next => {
+                body_parts.next();
+                None
+            }
+            Op::Prev => {
+                body_parts.prev();
+                None
+            }
+        }
+    }
+
+    fn random_op(rng: &mut StdRng) -> Op {
+        let part = *ALL_PARTS.choose(rng).unwrap();
+        match rng.gen_range(0..5) {
+            0 => Op::Push(part),
+            1 => Op::Remove(part),
+            2 => Op::Select(part),
+            3 => Op::Next,
+            _ => Op::Prev,
+        }
+    }
+
+    #[test]
+    fn body_parts_follow_the_model_for_any_sequence() {
+        for seed in 0..500 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut body_parts = BodyParts::default();
+            let mut model = Model {
+                parts: vec![BodyPart::Body],
+                current: BodyPart::Body,
+            };
+
+            for step in 0..rng.gen_range(1..60) {
+                let op = random_op(&mut rng);
+                let context = format!("seed {}, step {}, {:?}", seed, step, op);
+
+                assert_eq!(apply(&mut body_parts, op), model.apply(op), "{}", context);
+                assert_eq!(body_parts.parts(), model.parts.as_slice(), "{}", context);
+                assert_eq!(body_parts.current(), model.current, "{}", context);
+
+                // the invariants hold on their own, whatever the model says
+                assert_eq!(body_parts.parts()[0], BodyPart::Body, "{}", context);
+                assert_eq!(body_parts.parts()[body_parts.index()], body_parts.current(), "{}", context);
+                for (index, part) in body_parts.parts().iter().enumerate() {
+                    assert!(!body_parts.parts()[..index].contains(part), "{:?} is listed twice, {}", part, context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pushed_parts_keep_the_order_they_came_off_in() {
+        let mut body_parts = BodyParts::default();
+        assert!(body_parts.push(BodyPart::Head));
+        assert!(body_parts.push(BodyPart::LeftLeg));
+        assert!(body_parts.push(BodyPart::RightArm));
+        assert_eq!(body_parts.parts(), &[BodyPart::Body, BodyPart::Head, BodyPart::LeftLeg, BodyPart::RightArm]);
+        assert_eq!(body_parts.current(), BodyPart::RightArm);
+    }
+
+    #[test]
+    fn duplicates_and_the_body_are_never_pushed_twice() {
+        let mut body_parts = BodyParts::default();
+        body_parts.push(BodyPart::Head);
+        body_parts.push(BodyPart::LeftArm);
+        body_parts.select(BodyPart::Body);
+        let before = body_parts.clone();
+
+        assert!(!body_parts.push(BodyPart::Head));
+        assert!(!body_parts.push(BodyPart::Body));
+        assert_eq!(body_parts, before);
+    }
+
+    #[test]
+    fn selecting_a_part_that_is_not_attached_changes_nothing() {
+        let mut body_parts = BodyParts::default();
+        body_parts.push(BodyPart::Head);
+        let before = body_parts.clone();
+
+        assert!(!body_parts.select(BodyPart::LeftLeg));
+        assert_eq!(body_parts, before);
+        assert_eq!(body_parts.current(), BodyPart::Head);
+    }
+
+    #[test]
+    fn the_body_stays_first_and_can_not_be_removed() {
+        let mut body_parts = BodyParts::default();
+        body_parts.push(BodyPart::Head);
+        body_parts.push(BodyPart::LeftArm);
+
+        assert!(!body_parts.remove(BodyPart::Body));
+        assert!(body_parts.remove(BodyPart::Head));
+        assert_eq!(body_parts.parts(), &[BodyPart::Body, BodyPart::LeftArm]);
+        // the selected part stays selected, even though its index moved
+        assert_eq!(body_parts.current(), BodyPart::LeftArm);
+
+        assert!(body_parts.remove(BodyPart::LeftArm));
+        assert_eq!(body_parts.parts(), &[BodyPart::Body]);
+        assert_eq!(body_parts.current(), BodyPart::Body);
+    }
+}
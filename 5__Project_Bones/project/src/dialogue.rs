@@ -0,0 +1,776 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+use crate::flags::{Condition, FlagValue, GameFlags};
+use crate::GameState;
+
+pub struct DialoguePlugin;
+
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_asset::<DialogueScript>()
+            .init_asset_loader::<DialogueScriptLoader>()
+            .init_resource::<DialogueLibrary>()
+            .init_resource::<DialogueRunner>()
+            .add_event::<DialogueEffect>()
+
+            .add_startup_system(load_dialogue)
+            // runs in every state so hot-reloaded scripts are picked up anywhere
+            .add_system(rebuild_dialogue_library)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(apply_dialogue_effects)
+                    .with_system(suspend_movement)
+            );
+    }
+}
+
+pub const DIALOGUE_FILES: [&str; 1] = [
+    "data/dialogue/reception.dlg",
+];
+
+// DIALOGUE DATA
+
+/// A single node of a conversation graph. Nodes are looked up by `id`, so ids
+/// must be unique across every loaded dialogue file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Dialogue {
+    pub id: String,
+    #[serde(default)]
+    pub speaker: Option<String>,
+    pub prompt: String,
+    #[serde(default)]
+    pub choices: Vec<DialogueOption>,
+    // followed when the node has no visible choices, None ends the conversation
+    #[serde(default)]
+    pub next: Option<String>,
+    // applied every time the node is entered
+    #[serde(default)]
+    pub effects: Vec<DialogueEffect>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DialogueOption {
+    pub text: String,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    #[serde(default)]
+    pub effects: Vec<DialogueEffect>,
+}
+
+// Also used as an event, the runner hands these out and apply_dialogue_effects
+// makes them happen in the world.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DialogueEffect {
+    GiveItem(String),
+    TakeItem(String),
+    SetFlag(String, FlagValue),
+    // iid of the door's LDtk entity
+    OpenDoor(String),
+}
+
+// RESOURCES
+
+#[derive(Clone, Debug, Default)]
+pub struct DialogueLibrary(pub HashMap<String, Dialogue>);
+
+impl DialogueLibrary {
+    pub fn insert(&mut self, dialogue: Dialogue) {
+        if self.0.contains_key(&dialogue.id) {
+            warn!("Dialogue node {} was defined twice", dialogue.id);
+        }
+        self.0.insert(dialogue.id.clone(), dialogue);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Dialogue> {
+        self.0.get(id)
+    }
+}
+
+/// Walks a conversation through the `DialogueLibrary`. The runner only tracks
+/// where the conversation is, everything it needs from the world is passed in,
+/// so it can be driven without a window or any rendering.
+#[derive(Clone, Debug, Default)]
+pub struct DialogueRunner {
+    pub speaker: Option<Entity>,
+    pub current: Option<String>,
+    pub selected: usize,
+    // nodes entered so far, tells a node entered again apart from staying on it
+    pub visits: usize,
+}
+
+impl DialogueRunner {
+    pub fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn current<'a>(&self, library: &'a DialogueLibrary) -> Option<&'a Dialogue> {
+        self.current.as_ref().and_then(|id| library.get(id))
+    }
+
+    pub fn start(
+        &mut self,
+        library: &DialogueLibrary,
+        node: &str,
+        speaker: Option<Entity>,
+    ) -> Vec<DialogueEffect> {
+        self.speaker = speaker;
+        self.enter(library, Some(node.to_string()))
+    }
+
+    pub fn end(&mut self) {
+        *self = DialogueRunner {
+            visits: self.visits,
+            ..default()
+        };
+    }
+
+    /// Choices of the current node whose conditions currently hold.
+    pub fn choices<'a>(
+        &self,
+        library: &'a DialogueLibrary,
+        flags: &GameFlags,
+        inventory: &Inventory,
+    ) -> Vec<&'a DialogueOption> {
+        match self.current(library) {
+            Some(dialogue) => dialogue.choices
+                .iter()
+                .filter(|choice| match &choice.condition {
+                    Some(condition) => condition.evaluate(flags, inventory),
+                    None => true,
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn select_next(&mut self, choice_count: usize) {
+        if choice_count > 0 {
+            self.selected = (self.selected + 1) % choice_count;
+        }
+    }
+
+    pub fn select_prev(&mut self, choice_count: usize) {
+        if choice_count > 0 {
+            self.selected = (self.selected + choice_count - 1) % choice_count;
+        }
+    }
+
+    /// Takes the selected choice, or follows `next` if there is nothing to
+    /// choose, and returns the effects that should be applied.
+    pub fn advance(
+        &mut self,
+        library: &DialogueLibrary,
+        flags: &GameFlags,
+        inventory: &Inventory,
+    ) -> Vec<DialogueEffect> {
+        let dialogue = match self.current(library) {
+            Some(dialogue) => dialogue,
+            None => return Vec::new(),
+        };
+        let choices = self.choices(library, flags, inventory);
+
+        if choices.is_empty() {
+            self.enter(library, dialogue.next.clone())
+        } else {
+            let choice = choices[self.selected.min(choices.len() - 1)];
+            let mut effects = choice.effects.clone();
+            effects.extend(self.enter(library, choice.target.clone()));
+            effects
+        }
+    }
+
+    fn enter(&mut self, library: &DialogueLibrary, node: Option<String>) -> Vec<DialogueEffect> {
+        self.selected = 0;
+        match node.as_deref().and_then(|id| library.get(id)) {
+            Some(dialogue) => {
+                self.current = Some(dialogue.id.clone());
+                self.visits += 1;
+                dialogue.effects.clone()
+            }
+            None => {
+                if let Some(id) = node {
+                    warn!("Dialogue node {} does not exist", id);
+                }
+                self.end();
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DialogueScripts(pub Vec<Handle<DialogueScript>>);
+
+// DIALOGUE SCRIPTS
+
+#[derive(Clone, Debug, Default, TypeUuid)]
+#[uuid = "8d0f5a3c-62a4-4d7e-9a55-2f1c7b3e6a41"]
+pub struct DialogueScript {
+    pub nodes: Vec<Dialogue>,
+}
+
+#[derive(Default)]
+pub struct DialogueScriptLoader;
+
+impl AssetLoader for DialogueScriptLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let source = std::str::from_utf8(bytes)?;
+            let nodes = parse_dialogue(source)?;
+            load_context.set_default_asset(LoadedAsset::new(DialogueScript { nodes }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dlg"]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DialogueParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl DialogueParseError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        DialogueParseError { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for DialogueParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for DialogueParseError {}
+
+/// Parses a `.dlg` script. The format is a small subset of Yarn:
+///
+/// ```text
+/// // comments start with two slashes
+/// title: receptionist.start
+/// speaker: Receptionist
+/// ---
+/// Receptionist: Welcome to the tower.
+/// <<set met_receptionist>>
+/// -> I do. [[receptionist.appointment]]
+/// -> Here's a gumball. [[receptionist.gumball]] <<if has_item Gumball and not flag gave_gumball>>
+///     <<take Gumball>>
+///     <<set gave_gumball true>>
+/// -> Never mind.
+/// ===
+/// ```
+///
+/// Headers (`title:`, optional `speaker:`) come before `---` and `===` closes
+/// the node. Text lines may start with a `Speaker:` tag, which names the node's
+/// speaker if the header didn't. A node has one speaker, so a line tagged with
+/// anyone else is an error. Several text lines are joined into one prompt.
+/// Commands are `<<set flag [value]>>`, `<<give item>>`, `<<take item>>`,
+/// `<<open door_iid>>` and `<<jump node>>`. A flag set without a value is
+/// `true`, see `FlagValue::parse` for the rest.
+/// Indented commands under a `->` choice only run when that choice is taken.
+/// `<<if>>` takes a `Condition`.
+pub fn parse_dialogue(source: &str) -> Result<Vec<Dialogue>, DialogueParseError> {
+    let mut nodes = Vec::new();
+    let mut node: Option<(usize, Dialogue)> = None;
+    let mut in_body = false;
+    let mut prompt: Vec<String> = Vec::new();
+
+    for (index, raw_line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        if !in_body {
+            if line == "---" {
+                match &node {
+                    Some((_, dialogue)) if !dialogue.id.is_empty() => in_body = true,
+                    _ => return Err(DialogueParseError::new(line_number, "node body started before a title")),
+                }
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| DialogueParseError::new(line_number, format!("expected a header, found \"{}\"", line)))?;
+            let dialogue = &mut node.get_or_insert_with(|| (line_number, Dialogue::default())).1;
+
+            match key.trim() {
+                "title" => dialogue.id = value.trim().to_string(),
+                "speaker" => dialogue.speaker = Some(value.trim().to_string()),
+                other => return Err(DialogueParseError::new(line_number, format!("unknown header \"{}\"", other))),
+            }
+            continue;
+        }
+
+        let (_, dialogue) = node.as_mut().unwrap();
+
+        if line == "===" {
+            dialogue.prompt = prompt.join("\n");
+            prompt.clear();
+            nodes.push(node.take().unwrap().1);
+            in_body = false;
+            continue;
+        }
+
+        if let Some(choice) = line.strip_prefix("->") {
+            dialogue.choices.push(parse_choice(choice, line_number)?);
+            continue;
+        }
+
+        if line.starts_with("<<") {
+            let indented = raw_line.starts_with(char::is_whitespace);
+            match parse_command(line, line_number)? {
+                Command::Jump(target) => dialogue.next = Some(target),
+                Command::Effect(effect) => match dialogue.choices.last_mut() {
+                    Some(choice) if indented => choice.effects.push(effect),
+                    _ => dialogue.effects.push(effect),
+                },
+            }
+            continue;
+        }
+
+        if !dialogue.choices.is_empty() {
+            return Err(DialogueParseError::new(line_number, "text lines must come before the choices"));
+        }
+
+        match line.split_once(':') {
+            Some((speaker, text)) if !speaker.is_empty() && !speaker.contains(char::is_whitespace) => {
+                match &dialogue.speaker {
+                    Some(node_speaker) if node_speaker != speaker => {
+                        return Err(DialogueParseError::new(
+                            line_number,
+                            format!("{} speaks in a node spoken by {}, give them a node of their own", speaker, node_speaker),
+                        ));
+                    }
+                    Some(_) => {}
+                    None => dialogue.speaker = Some(speaker.to_string()),
+                }
+                prompt.push(text.trim().to_string());
+            }
+            _ => prompt.push(line.to_string()),
+        }
+    }
+
+    match node {
+        Some((line_number, dialogue)) => Err(DialogueParseError::new(
+            line_number,
+            format!("node \"{}\" is missing its closing ===", dialogue.id),
+        )),
+        None => Ok(nodes),
+    }
+}
+
+enum Command {
+    Jump(String),
+    Effect(DialogueEffect),
+}
+
+fn parse_command(line: &str, line_number: usize) -> Result<Command, DialogueParseError> {
+    let inner = line
+        .strip_prefix("<<")
+        .and_then(|line| line.strip_suffix(">>"))
+        .ok_or_else(|| DialogueParseError::new(line_number, "commands must be wrapped in << >>"))?;
+    let words: Vec<&str> = inner.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["set", flag] => Ok(Command::Effect(DialogueEffect::SetFlag(flag.to_string(), FlagValue::Bool(true)))),
+        ["set", flag, value @ ..] => {
+            Ok(Command::Effect(DialogueEffect::SetFlag(flag.to_string(), FlagValue::parse(&value.join(" ")))))
+        }
+        ["give", item] => Ok(Command::Effect(DialogueEffect::GiveItem(item.to_string()))),
+        ["take", item] => Ok(Command::Effect(DialogueEffect::TakeItem(item.to_string()))),
+        ["open", door] => Ok(Command::Effect(DialogueEffect::OpenDoor(door.to_string()))),
+        ["jump", node] => Ok(Command::Jump(node.to_string())),
+        ["if", ..] => Err(DialogueParseError::new(line_number, "<<if>> can only follow a choice")),
+        _ => Err(DialogueParseError::new(line_number, format!("unknown command <<{}>>", inner))),
+    }
+}
+
+fn parse_choice(line: &str, line_number: usize) -> Result<DialogueOption, DialogueParseError> {
+    let mut rest = line.trim();
+    let mut choice = DialogueOption::default();
+
+    if let Some(start) = rest.find("<<if") {
+        let condition = rest[start..]
+            .strip_prefix("<<if")
+            .and_then(|condition| condition.strip_suffix(">>"))
+            .ok_or_else(|| DialogueParseError::new(line_number, "<<if>> must end the choice line"))?;
+        let condition = Condition::parse(condition)
+            .map_err(|error| DialogueParseError::new(line_number, format!("condition {}", error)))?;
+        choice.condition = Some(condition);
+        rest = rest[..start].trim();
+    }
+
+    if let Some(start) = rest.find("[[") {
+        let target = rest[start..]
+            .strip_prefix("[[")
+            .and_then(|target| target.strip_suffix("]]"))
+            .ok_or_else(|| DialogueParseError::new(line_number, "choice target must look like [[node]]"))?;
+        choice.target = Some(target.trim().to_string());
+        rest = rest[..start].trim();
+    }
+
+    if rest.is_empty() {
+        return Err(DialogueParseError::new(line_number, "choice has no text"));
+    }
+    choice.text = rest.to_string();
+    Ok(choice)
+}
+
+// SYSTEMS
+
+fn load_dialogue(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let handles = DIALOGUE_FILES
+        .iter()
+        .map(|path| asset_server.load(*path))
+        .collect();
+
+    commands.insert_resource(DialogueScripts(handles));
+    info!("Began loading dialogue");
+}
+
+// Rebuilds the whole library whenever a script loads or is saved, any
+// conversation pointing at a node that no longer exists is closed.
+fn rebuild_dialogue_library(
+    mut script_events: EventReader<AssetEvent<DialogueScript>>,
+    scripts: Option<Res<DialogueScripts>>,
+    script_assets: Res<Assets<DialogueScript>>,
+    mut library: ResMut<DialogueLibrary>,
+    mut runner: ResMut<DialogueRunner>,
+) {
+    let scripts = match scripts {
+        Some(scripts) => scripts,
+        None => return,
+    };
+
+    if script_events.iter().count() == 0 {
+        return;
+    }
+
+    library.0.clear();
+    for handle in scripts.0.iter() {
+        if let Some(script) = script_assets.get(handle) {
+            for dialogue in script.nodes.iter() {
+                library.insert(dialogue.clone());
+            }
+        }
+    }
+
+    if runner.is_active() && runner.current(&library).is_none() {
+        runner.end();
+    }
+    info!("Rebuilt dialogue library, {} nodes", library.0.len());
+}
+
+// Player movement only runs while the player is Moving
+fn suspend_movement(
+    runner: Res<DialogueRunner>,
+    mut player: Query<&mut Player>,
+) {
+    if !runner.is_changed() {
+        return;
+    }
+
+    let state = if runner.is_active() { Player::Interacting } else { Player::Moving };
+    for mut player in player.iter_mut() {
+        if *player != state {
+            *player = state;
+        }
+    }
+}
+
+pub fn apply_dialogue_effects(
+    mut effects: EventReader<DialogueEffect>,
+    mut flags: ResMut<GameFlags>,
+    mut inventory: Query<&mut Inventory>,
+    mut doors: Query<(&EntityInstance, &mut Interactable)>,
+) {
+    for effect in effects.iter() {
+        match effect {
+            DialogueEffect::GiveItem(id) => {
+                let mut inventory = inventory.single_mut();
+                if !inventory.give(Item { id: id.clone() }) {
+                    info!("Inventory is full, could not take {}", id);
+                }
+            }
+
+            DialogueEffect::TakeItem(id) => {
+                let mut inventory = inventory.single_mut();
+                inventory.take(id);
+            }
+
+            DialogueEffect::SetFlag(flag, value) => {
+                flags.set(flag.clone(), value.clone());
+            }
+
+            DialogueEffect::OpenDoor(iid) => {
+                for (instance, mut interactable) in doors.iter_mut() {
+                    if instance.iid == *iid {
+                        if let Some(door) = interactable.door.as_mut() {
+                            door.requirements = None;
+                            door.closed = false;
+                            info!("Dialogue opened a door!");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(source: &str) -> usize {
+        parse_dialogue(source).unwrap_err().line
+    }
+
+    #[test]
+    fn headers_text_and_commands_build_a_node() {
+        let nodes = parse_dialogue(
+            "// a comment\n\
+             title: guard.start\n\
+             speaker: Guard\n\
+             ---\n\
+             Guard: Halt.\n\
+             Who goes there?\n\
+             <<set met_guard>>\n\
+             <<set coins 3>>\n\
+             <<give Badge>>\n\
+             <<take Gumball>>\n\
+             <<open door_1>>\n\
+             <<jump guard.end>>\n\
+             ===\n",
+        )
+        .unwrap();
+
+        assert_eq!(nodes.len(), 1);
+        let node = &nodes[0];
+        assert_eq!(node.id, "guard.start");
+        assert_eq!(node.speaker.as_deref(), Some("Guard"));
+        assert_eq!(node.prompt, "Halt.\nWho goes there?");
+        assert_eq!(node.next.as_deref(), Some("guard.end"));
+        assert_eq!(node.effects, vec![
+            DialogueEffect::SetFlag("met_guard".to_string(), FlagValue::Bool(true)),
+            DialogueEffect::SetFlag("coins".to_string(), FlagValue::Int(3)),
+            DialogueEffect::GiveItem("Badge".to_string()),
+            DialogueEffect::TakeItem("Gumball".to_string()),
+            DialogueEffect::OpenDoor("door_1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn a_speaker_tag_names_the_node_speaker() {
+        let nodes = parse_dialogue("title: a\n---\nGuard: Halt.\nGuard: Really.\n===\n").unwrap();
+        assert_eq!(nodes[0].speaker.as_deref(), Some("Guard"));
+        assert_eq!(nodes[0].prompt, "Halt.\nReally.");
+    }
+
+    #[test]
+    fn choices_keep_their_targets_conditions_and_effects() {
+        let nodes = parse_dialogue(
+            "title: a\n\
+             ---\n\
+             Pick one.\n\
+             -> Go on. [[b]]\n\
+             -> Trade. [[c]] <<if has_item Gumball and not flag traded>>\n\
+             \x20   <<take Gumball>>\n\
+             <<set asked>>\n\
+             -> Leave.\n\
+             ===\n",
+        )
+        .unwrap();
+
+        let node = &nodes[0];
+        assert_eq!(node.choices.len(), 3);
+        assert_eq!(node.choices[0].text, "Go on.");
+        assert_eq!(node.choices[0].target.as_deref(), Some("b"));
+        assert_eq!(node.choices[0].condition, None);
+
+        assert_eq!(node.choices[1].text, "Trade.");
+        assert_eq!(node.choices[1].target.as_deref(), Some("c"));
+        assert_eq!(
+            node.choices[1].condition,
+            Some(Condition::parse("has_item(\"Gumball\") && !flag(\"traded\")").unwrap()),
+        );
+        // only the indented command belongs to the choice
+        assert_eq!(node.choices[1].effects, vec![DialogueEffect::TakeItem("Gumball".to_string())]);
+        assert_eq!(node.effects, vec![DialogueEffect::SetFlag("asked".to_string(), FlagValue::Bool(true))]);
+
+        assert_eq!(node.choices[2].text, "Leave.");
+        assert_eq!(node.choices[2].target, None);
+    }
+
+    #[test]
+    fn the_shipped_script_parses() {
+        let source = std::fs::read_to_string("assets/data/dialogue/reception.dlg").unwrap();
+        let nodes = parse_dialogue(&source).unwrap();
+        assert!(nodes.iter().any(|node| node.id == "receptionist.start"));
+    }
+
+    #[test]
+    fn header_errors_point_at_their_line() {
+        assert_eq!(error_line("---\n"), 1);
+        assert_eq!(error_line("speaker: Guard\n---\n"), 2);
+        assert_eq!(error_line("title: a\njust text\n"), 2);
+        assert_eq!(error_line("title: a\nmood: grim\n"), 2);
+    }
+
+    #[test]
+    fn an_unclosed_node_points_at_its_first_header() {
+        assert_eq!(error_line("title: a\n---\n===\n\n// b\ntitle: b\n---\nHello.\n"), 6);
+    }
+
+    #[test]
+    fn body_errors_point_at_their_line() {
+        // text after the choices
+        assert_eq!(error_line("title: a\n---\n-> Go.\nHello.\n===\n"), 4);
+        // a second speaker in one node
+        assert_eq!(error_line("title: a\nspeaker: Guard\n---\nGuard: Halt.\nThief: Run!\n===\n"), 5);
+        assert_eq!(error_line("title: a\n---\nGuard: Halt.\nThief: Run!\n===\n"), 4);
+    }
+
+    #[test]
+    fn command_errors_point_at_their_line() {
+        assert_eq!(error_line("title: a\n---\n<<set a\n===\n"), 3);
+        assert_eq!(error_line("title: a\n---\nHi.\n<<if flag a>>\n===\n"), 4);
+        assert_eq!(error_line("title: a\n---\n<<dance>>\n===\n"), 3);
+        assert_eq!(error_line("title: a\n---\n<<give>>\n===\n"), 3);
+    }
+
+    #[test]
+    fn choice_errors_point_at_their_line() {
+        // <<if>> not at the end
+        assert_eq!(error_line("title: a\n---\n-> Go. <<if flag a>> [[b]]\n===\n"), 3);
+        // a condition that doesn't parse
+        assert_eq!(error_line("title: a\n---\nHi.\n-> Go. <<if flag(>>\n===\n"), 4);
+        assert_eq!(error_line("title: a\n---\n-> Go. [[b\n===\n"), 3);
+        assert_eq!(error_line("title: a\n---\n-> [[b]]\n===\n"), 3);
+    }
+
+    const CONVERSATION: &str = "\
+title: start
+---
+Hello.
+<<set greeted>>
+-> Ask. [[answer]]
+-> Trade. [[answer]] <<if has_item Gumball>>
+    <<take Gumball>>
+-> Wander off. [[nowhere]]
+===
+
+title: answer
+---
+Fine.
+<<jump farewell>>
+===
+
+title: farewell
+---
+Bye.
+===
+";
+
+    fn library() -> DialogueLibrary {
+        let mut library = DialogueLibrary::default();
+        for dialogue in parse_dialogue(CONVERSATION).unwrap() {
+            library.insert(dialogue);
+        }
+        library
+    }
+
+    fn texts(choices: Vec<&DialogueOption>) -> Vec<&str> {
+        choices.iter().map(|choice| choice.text.as_str()).collect()
+    }
+
+    #[test]
+    fn the_runner_hides_choices_whose_condition_fails() {
+        let library = library();
+        let flags = GameFlags::default();
+        let mut inventory = Inventory::default();
+        let mut runner = DialogueRunner::default();
+
+        let effects = runner.start(&library, "start", None);
+        assert_eq!(effects, vec![DialogueEffect::SetFlag("greeted".to_string(), FlagValue::Bool(true))]);
+        assert_eq!(texts(runner.choices(&library, &flags, &inventory)), vec!["Ask.", "Wander off."]);
+
+        inventory.give(Item { id: "Gumball".to_string() });
+        assert_eq!(texts(runner.choices(&library, &flags, &inventory)), vec!["Ask.", "Trade.", "Wander off."]);
+
+        // entering the same node again still counts as a new visit
+        let visits = runner.visits;
+        runner.start(&library, "start", None);
+        assert_eq!(runner.visits, visits + 1);
+    }
+
+    #[test]
+    fn the_runner_takes_the_selected_choice_then_follows_next() {
+        let library = library();
+        let flags = GameFlags::default();
+        let mut inventory = Inventory::default();
+        inventory.give(Item { id: "Gumball".to_string() });
+        let mut runner = DialogueRunner::default();
+        runner.start(&library, "start", None);
+
+        runner.select_prev(3);
+        assert_eq!(runner.selected, 2);
+        runner.select_next(3);
+        runner.select_next(3);
+        assert_eq!(runner.selected, 1);
+
+        let effects = runner.advance(&library, &flags, &inventory);
+        assert_eq!(effects, vec![DialogueEffect::TakeItem("Gumball".to_string())]);
+        assert_eq!(runner.current.as_deref(), Some("answer"));
+        assert_eq!(runner.selected, 0);
+
+        runner.advance(&library, &flags, &inventory);
+        assert_eq!(runner.current.as_deref(), Some("farewell"));
+        runner.advance(&library, &flags, &inventory);
+        assert!(!runner.is_active());
+    }
+
+    #[test]
+    fn the_runner_ends_at_a_missing_node() {
+        let library = library();
+        let mut runner = DialogueRunner::default();
+        runner.start(&library, "start", None);
+        runner.selected = 1;
+
+        runner.advance(&library, &GameFlags::default(), &Inventory::default());
+        assert!(!runner.is_active());
+        assert!(runner.start(&library, "missing", None).is_empty());
+        assert!(!runner.is_active());
+    }
+}
@@ -0,0 +1,322 @@
+use bevy::prelude::*;
+
+use crate::components_events::*;
+use crate::dialogue::*;
+use crate::flags::GameFlags;
+use crate::GameState;
+
+pub struct DialogueUiPlugin;
+
+impl Plugin for DialogueUiPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Typewriter>()
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(spawn_dialogue_box)
+                    .with_system(
+                        typewriter_reveal
+                        .after(spawn_dialogue_box)
+                    )
+                    .with_system(
+                        highlight_choices
+                        .after(spawn_dialogue_box)
+                    )
+            );
+    }
+}
+
+pub const MAX_CHOICES: usize = 4;
+
+pub const FONT: &str = "fonts/DejaVuSans.ttf";
+const PORTRAIT_SHEET: &str = "visual/NPCs.png";
+const PORTRAIT_COLUMNS: usize = 3;
+const PORTRAIT_ROWS: usize = 3;
+// 32px portraits drawn at 3x
+const PORTRAIT_SIZE: f32 = 96.;
+
+const BOX_HEIGHT: f32 = 200.;
+const TEXT_WIDTH: f32 = 900.;
+const CHARS_PER_SECOND: f32 = 40.;
+
+const BOX_COLOR: Color = Color::rgba(0.08, 0.08, 0.12, 0.9);
+const NAME_COLOR: Color = Color::rgb(0.95, 0.8, 0.45);
+const TEXT_COLOR: Color = Color::WHITE;
+const CHOICE_COLOR: Color = Color::rgb(0.2, 0.2, 0.28);
+const SELECTED_COLOR: Color = Color::rgb(0.45, 0.3, 0.55);
+
+// UI COMPONENTS
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct DialogueBox;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct DialogueText;
+
+// what the box was last built for
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+struct ShownDialogue {
+    node: Option<String>,
+    visits: usize,
+    choices: Vec<String>,
+}
+
+// RESOURCES
+
+/// Reveals the current prompt a few characters at a time. Interact skips to
+/// the full text before it can be used to pick a choice.
+#[derive(Clone, Debug, Default)]
+pub struct Typewriter {
+    pub text: String,
+    pub revealed: f32,
+}
+
+impl Typewriter {
+    pub fn reset(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.revealed = 0.;
+    }
+
+    pub fn skip(&mut self) {
+        self.revealed = self.text.chars().count() as f32;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.revealed as usize >= self.text.chars().count()
+    }
+
+    pub fn visible(&self) -> String {
+        self.text.chars().take(self.revealed as usize).collect()
+    }
+}
+
+// SYSTEMS
+
+// Rebuilds the box whenever the conversation enters a node, even the one it
+// was already on, or effects change which choices are available. Bevy's UI
+// has y pointing up, so `top` offsets are measured from the bottom of the
+// window and columns are reversed to read top to bottom.
+fn spawn_dialogue_box(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    runner: Res<DialogueRunner>,
+    library: Res<DialogueLibrary>,
+    flags: Res<GameFlags>,
+    inventory: Query<&Inventory>,
+    speakers: Query<&TextureAtlasSprite>,
+    dialogue_box: Query<Entity, With<DialogueBox>>,
+    mut typewriter: ResMut<Typewriter>,
+    mut shown: Local<ShownDialogue>,
+) {
+    let inventory = match inventory.get_single() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+    let choices = runner.choices(&library, &flags, inventory);
+
+    let now = ShownDialogue {
+        node: runner.current.clone(),
+        visits: runner.visits,
+        choices: choices.iter().take(MAX_CHOICES).map(|choice| choice.text.clone()).collect(),
+    };
+    if *shown == now {
+        return;
+    }
+    let same_visit = shown.node == now.node && shown.visits == now.visits;
+    *shown = now;
+
+    for entity in dialogue_box.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let dialogue = match runner.current(&library) {
+        Some(dialogue) => dialogue,
+        None => {
+            typewriter.reset("");
+            return;
+        }
+    };
+    if same_visit {
+        // only the choices changed, keep the prompt where it was and let the
+        // typewriter fill in the new text and show the buttons when it's done
+        typewriter.set_changed();
+    } else {
+        typewriter.reset(&dialogue.prompt);
+    }
+
+    let portrait = runner.speaker
+        .and_then(|speaker| speakers.get(speaker).ok())
+        .map(|sprite| sprite.index);
+
+    let font: Handle<Font> = asset_server.load(FONT);
+    let text_style = TextStyle {
+        font: font.clone(),
+        font_size: 24.,
+        color: TEXT_COLOR,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(0.),
+                    top: Val::Px(0.),
+                    ..default()
+                },
+                size: Size::new(Val::Percent(100.), Val::Px(BOX_HEIGHT)),
+                padding: UiRect::all(Val::Px(16.)),
+                align_items: AlignItems::FlexEnd,
+                ..default()
+            },
+            color: BOX_COLOR.into(),
+            ..default()
+        })
+        .insert(DialogueBox)
+        .with_children(|parent| {
+            if let Some(index) = portrait {
+                let column = (index % PORTRAIT_COLUMNS) as f32;
+                let row = (PORTRAIT_ROWS - 1 - index / PORTRAIT_COLUMNS) as f32;
+
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(PORTRAIT_SIZE), Val::Px(PORTRAIT_SIZE)),
+                            margin: UiRect {
+                                right: Val::Px(16.),
+                                ..default()
+                            },
+                            overflow: Overflow::Hidden,
+                            ..default()
+                        },
+                        color: Color::NONE.into(),
+                        ..default()
+                    })
+                    .with_children(|frame| {
+                        // the whole sheet, shifted so only the speaker's cell shows
+                        frame.spawn_bundle(ImageBundle {
+                            style: Style {
+                                position_type: PositionType::Absolute,
+                                position: UiRect {
+                                    left: Val::Px(-column * PORTRAIT_SIZE),
+                                    top: Val::Px(-row * PORTRAIT_SIZE),
+                                    ..default()
+                                },
+                                size: Size::new(
+                                    Val::Px(PORTRAIT_SIZE * PORTRAIT_COLUMNS as f32),
+                                    Val::Px(PORTRAIT_SIZE * PORTRAIT_ROWS as f32),
+                                ),
+                                ..default()
+                            },
+                            image: asset_server.load(PORTRAIT_SHEET).into(),
+                            ..default()
+                        });
+                    });
+            }
+
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::ColumnReverse,
+                        flex_grow: 1.,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|column| {
+                    if let Some(speaker) = &dialogue.speaker {
+                        column.spawn_bundle(TextBundle {
+                            text: Text::from_section(
+                                speaker.replace('_', " "),
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 26.,
+                                    color: NAME_COLOR,
+                                },
+                            ),
+                            ..default()
+                        });
+                    }
+
+                    column
+                        .spawn_bundle(TextBundle {
+                            text: Text::from_section("", text_style.clone()),
+                            style: Style {
+                                max_size: Size::new(Val::Px(TEXT_WIDTH), Val::Undefined),
+                                margin: UiRect {
+                                    bottom: Val::Px(8.),
+                                    ..default()
+                                },
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .insert(DialogueText);
+
+                    for (index, choice) in choices.iter().take(MAX_CHOICES).enumerate() {
+                        column
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    // hidden until the prompt is fully revealed
+                                    display: Display::None,
+                                    padding: UiRect::all(Val::Px(4.)),
+                                    margin: UiRect {
+                                        bottom: Val::Px(4.),
+                                        ..default()
+                                    },
+                                    ..default()
+                                },
+                                color: CHOICE_COLOR.into(),
+                                ..default()
+                            })
+                            .insert(DialogueChoice(index))
+                            .with_children(|button| {
+                                button.spawn_bundle(TextBundle {
+                                    text: Text::from_section(choice.text.clone(), text_style.clone()),
+                                    ..default()
+                                });
+                            });
+                    }
+                });
+        });
+}
+
+fn typewriter_reveal(
+    time: Res<Time>,
+    mut typewriter: ResMut<Typewriter>,
+    mut text: Query<&mut Text, With<DialogueText>>,
+    mut choices: Query<&mut Style, With<DialogueChoice>>,
+) {
+    if !typewriter.finished() {
+        typewriter.revealed += CHARS_PER_SECOND * time.delta_seconds();
+    }
+
+    if !typewriter.is_changed() {
+        return;
+    }
+
+    for mut text in text.iter_mut() {
+        text.sections[0].value = typewriter.visible();
+    }
+
+    if typewriter.finished() {
+        for mut style in choices.iter_mut() {
+            style.display = Display::Flex;
+        }
+    }
+}
+
+fn highlight_choices(
+    runner: Res<DialogueRunner>,
+    mut choices: Query<(&DialogueChoice, &mut UiColor)>,
+) {
+    for (choice, mut color) in choices.iter_mut() {
+        *color = if choice.0 == runner.selected {
+            SELECTED_COLOR.into()
+        } else {
+            CHOICE_COLOR.into()
+        };
+    }
+}
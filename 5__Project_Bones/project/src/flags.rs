@@ -0,0 +1,727 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::EntityInstance;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+
+pub struct FlagsPlugin;
+
+impl Plugin for FlagsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<GameFlags>()
+            .add_event::<FlagChanged>()
+
+            // in every state, loading a save on the main menu changes flags too
+            .add_system_to_stage(CoreStage::PostUpdate, send_flag_changes);
+    }
+}
+
+// FLAG DATA
+
+/// A story value. Saves write them as plain JSON, `true`, `3` or `"text"`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FlagValue {
+    Bool(bool),
+    Int(i64),
+    Text(String),
+}
+
+impl FlagValue {
+    // false, 0 and "" count as unset
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            FlagValue::Bool(value) => *value,
+            FlagValue::Int(value) => *value != 0,
+            FlagValue::Text(value) => !value.is_empty(),
+        }
+    }
+
+    /// Reads `true`, `false`, whole numbers and quoted or bare text.
+    pub fn parse(source: &str) -> Self {
+        let source = source.trim();
+        match source {
+            "true" => FlagValue::Bool(true),
+            "false" => FlagValue::Bool(false),
+            _ => match source.parse() {
+                Ok(value) => FlagValue::Int(value),
+                Err(_) => {
+                    let text = source
+                        .strip_prefix('"')
+                        .and_then(|text| text.strip_suffix('"'))
+                        .unwrap_or(source);
+                    FlagValue::Text(text.to_string())
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for FlagValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlagValue::Bool(value) => write!(f, "{}", value),
+            FlagValue::Int(value) => write!(f, "{}", value),
+            FlagValue::Text(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl From<bool> for FlagValue {
+    fn from(value: bool) -> Self {
+        FlagValue::Bool(value)
+    }
+}
+
+impl From<i64> for FlagValue {
+    fn from(value: i64) -> Self {
+        FlagValue::Int(value)
+    }
+}
+
+impl From<&str> for FlagValue {
+    fn from(value: &str) -> Self {
+        FlagValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for FlagValue {
+    fn from(value: String) -> Self {
+        FlagValue::Text(value)
+    }
+}
+
+// EVENTS
+
+// key, value before and value after, None when unset
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlagChanged {
+    pub key: String,
+    pub old: Option<FlagValue>,
+    pub new: Option<FlagValue>,
+}
+
+// RESOURCES
+
+/// Story state shared by doors, dialogue, puzzles and items. Every change is
+/// remembered and sent out as a `FlagChanged` at the end of the frame.
+#[derive(Clone, Debug, Default)]
+pub struct GameFlags {
+    values: HashMap<String, FlagValue>,
+    changes: Vec<FlagChanged>,
+}
+
+impl GameFlags {
+    pub fn get(&self, key: &str) -> Option<&FlagValue> {
+        self.values.get(key)
+    }
+
+    pub fn is_set(&self, key: &str) -> bool {
+        self.get(key).map_or(false, FlagValue::is_truthy)
+    }
+
+    // setting a flag to the value it already has is not a change
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<FlagValue>) {
+        let key = key.into();
+        let value = value.into();
+        if self.values.get(&key) == Some(&value) {
+            return;
+        }
+
+        let old = self.values.insert(key.clone(), value.clone());
+        self.changes.push(FlagChanged { key, old, new: Some(value) });
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some(old) = self.values.remove(key) {
+            self.changes.push(FlagChanged {
+                key: key.to_string(),
+                old: Some(old),
+                new: None,
+            });
+        }
+    }
+
+    /// Swaps every flag for `values`, as loading a save does.
+    pub fn replace(&mut self, values: impl IntoIterator<Item = (String, FlagValue)>) {
+        let values: HashMap<String, FlagValue> = values.into_iter().collect();
+        let removed: Vec<String> = self.values
+            .keys()
+            .filter(|key| !values.contains_key(*key))
+            .cloned()
+            .collect();
+
+        for key in removed {
+            self.remove(&key);
+        }
+        for (key, value) in values {
+            self.set(key, value);
+        }
+    }
+
+    // sorted, so saves come out the same every time
+    pub fn to_map(&self) -> BTreeMap<String, FlagValue> {
+        self.values.iter().map(|(key, value)| (key.clone(), value.clone())).collect()
+    }
+
+    pub fn drain_changes(&mut self) -> Vec<FlagChanged> {
+        std::mem::take(&mut self.changes)
+    }
+}
+
+// CONDITIONS
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    // ordering only means something between numbers
+    fn holds(&self, value: Option<&FlagValue>, against: &FlagValue) -> bool {
+        match (self, value, against) {
+            (Comparison::Equal, value, against) => value == Some(against),
+            (Comparison::NotEqual, value, against) => value != Some(against),
+            (_, Some(FlagValue::Int(value)), FlagValue::Int(against)) => match self {
+                Comparison::Less => value < against,
+                Comparison::LessOrEqual => value <= against,
+                Comparison::Greater => value > against,
+                _ => value >= against,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// A test on the story state, written as a small expression:
+///
+/// ```text
+/// has_item("Gumball") && !flag("met_receptionist")
+/// flag("coins") >= 3 || (flag("door") == "open" and not flag(locked))
+/// ```
+///
+/// `flag(name)` holds when the flag is set to anything but false, 0 or "",
+/// and can be compared with `== != < <= > >=`. `has_item(item)` looks in the
+/// inventory. `&&`, `||` and `!` may also be written `and`, `or` and `not`,
+/// and the quotes and brackets around a single word may be left out, so
+/// `has_item Gumball and not flag gave_gumball` reads the same.
+///
+/// Conditions serialize back to a string in the first form.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Condition {
+    Literal(bool),
+    Flag(String),
+    HasItem(String),
+    Compare(String, Comparison, FlagValue),
+    Not(Box<Condition>),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, index: 0, end: source.len() };
+        let condition = parser.any()?;
+        match parser.tokens.get(parser.index) {
+            Some((position, token)) => Err(ConditionError::new(*position, format!("unexpected {}", token))),
+            None => Ok(condition),
+        }
+    }
+
+    pub fn evaluate(&self, flags: &GameFlags, inventory: &Inventory) -> bool {
+        match self {
+            Condition::Literal(value) => *value,
+            Condition::Flag(key) => flags.is_set(key),
+            Condition::HasItem(id) => inventory.contains(id),
+            Condition::Compare(key, comparison, against) => comparison.holds(flags.get(key), against),
+            Condition::Not(condition) => !condition.evaluate(flags, inventory),
+            Condition::All(conditions) => conditions.iter().all(|c| c.evaluate(flags, inventory)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.evaluate(flags, inventory)),
+        }
+    }
+
+    // bracketed when it would otherwise bind differently next to its
+    // neighbours, or run into a list of the same kind around it
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parent: &Condition) -> fmt::Result {
+        match (self, parent) {
+            (Condition::Any(_), _) => write!(f, "({})", self),
+            (Condition::All(_), Condition::Any(_)) => write!(f, "{}", self),
+            (Condition::All(_), _) | (Condition::Compare(..), Condition::Not(_)) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Literal(value) => write!(f, "{}", value),
+            Condition::Flag(key) => write!(f, "flag({:?})", key),
+            Condition::HasItem(id) => write!(f, "has_item({:?})", id),
+            Condition::Compare(key, comparison, against) => {
+                write!(f, "flag({:?}) {} {}", key, comparison.symbol(), against)
+            }
+            Condition::Not(condition) => {
+                write!(f, "!")?;
+                condition.fmt_operand(f, self)
+            }
+            Condition::All(conditions) => {
+                for (index, condition) in conditions.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " && ")?;
+                    }
+                    condition.fmt_operand(f, self)?;
+                }
+                Ok(())
+            }
+            Condition::Any(conditions) => {
+                for (index, condition) in conditions.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " || ")?;
+                    }
+                    condition.fmt_operand(f, self)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = ConditionError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Condition::parse(&source)
+    }
+}
+
+impl From<Condition> for String {
+    fn from(condition: Condition) -> Self {
+        condition.to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConditionError {
+    // byte offset into the source
+    pub position: usize,
+    pub message: String,
+}
+
+impl ConditionError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        ConditionError { position, message: message.into() }
+    }
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for ConditionError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Int(i64),
+    Open,
+    Close,
+    Not,
+    And,
+    Or,
+    Compare(Comparison),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "\"{}\"", word),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Int(value) => write!(f, "{}", value),
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+            Token::Not => write!(f, "\"!\""),
+            Token::And => write!(f, "\"&&\""),
+            Token::Or => write!(f, "\"||\""),
+            Token::Compare(comparison) => write!(f, "\"{}\"", comparison.symbol()),
+        }
+    }
+}
+
+// Words start and run on letters, digits, `_`, `.` and `-`, so item ids,
+// dotted or dashed flag names and negative numbers stay whole.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '-'
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((position, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        let token = match (c, next) {
+            _ if c.is_whitespace() => continue,
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('&', Some('&')) | ('|', Some('|')) | ('=', Some('=')) | ('!', Some('=')) | ('<', Some('=')) | ('>', Some('=')) => {
+                chars.next();
+                match c {
+                    '&' => Token::And,
+                    '|' => Token::Or,
+                    '=' => Token::Compare(Comparison::Equal),
+                    '!' => Token::Compare(Comparison::NotEqual),
+                    '<' => Token::Compare(Comparison::LessOrEqual),
+                    _ => Token::Compare(Comparison::GreaterOrEqual),
+                }
+            }
+            ('!', _) => Token::Not,
+            ('<', _) => Token::Compare(Comparison::Less),
+            ('>', _) => Token::Compare(Comparison::Greater),
+            ('"', _) => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => return Err(ConditionError::new(position, "unclosed quote")),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(ConditionError::new(position, "unclosed quote")),
+                    }
+                }
+                Token::Text(text)
+            }
+            _ if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => match word.parse() {
+                        Ok(value) => Token::Int(value),
+                        Err(_) => Token::Word(word),
+                    },
+                }
+            }
+            _ => return Err(ConditionError::new(position, format!("unexpected \"{}\"", c))),
+        };
+        tokens.push((position, token));
+    }
+
+    Ok(tokens)
+}
+
+// `||` binds loosest, then `&&`, then `!`
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    // reported for errors at the end of the source
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn next(&mut self) -> Result<Token, ConditionError> {
+        let token = self.tokens
+            .get(self.index)
+            .map(|(_, token)| token.clone())
+            .ok_or_else(|| ConditionError::new(self.end, "condition ended early"))?;
+        self.index += 1;
+        Ok(token)
+    }
+
+    fn any(&mut self) -> Result<Condition, ConditionError> {
+        let mut any = vec![self.all()?];
+        while self.peek() == Some(&Token::Or) {
+            self.index += 1;
+            any.push(self.all()?);
+        }
+        Ok(if any.len() == 1 { any.remove(0) } else { Condition::Any(any) })
+    }
+
+    fn all(&mut self) -> Result<Condition, ConditionError> {
+        let mut all = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.index += 1;
+            all.push(self.unary()?);
+        }
+        Ok(if all.len() == 1 { all.remove(0) } else { Condition::All(all) })
+    }
+
+    fn unary(&mut self) -> Result<Condition, ConditionError> {
+        if self.peek() == Some(&Token::Not) {
+            self.index += 1;
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Condition, ConditionError> {
+        let position = self.position();
+        match self.next()? {
+            Token::Open => {
+                let condition = self.any()?;
+                match self.next()? {
+                    Token::Close => Ok(condition),
+                    token => Err(ConditionError::new(position, format!("expected \")\", found {}", token))),
+                }
+            }
+            Token::Word(word) if word == "true" => Ok(Condition::Literal(true)),
+            Token::Word(word) if word == "false" => Ok(Condition::Literal(false)),
+            Token::Word(function) => {
+                let argument = self.argument()?;
+                match function.as_str() {
+                    "flag" => match self.peek() {
+                        Some(Token::Compare(comparison)) => {
+                            let comparison = *comparison;
+                            self.index += 1;
+                            Ok(Condition::Compare(argument, comparison, self.value()?))
+                        }
+                        _ => Ok(Condition::Flag(argument)),
+                    },
+                    "has_item" => Ok(Condition::HasItem(argument)),
+                    _ => Err(ConditionError::new(position, format!("unknown function \"{}\"", function))),
+                }
+            }
+            token => Err(ConditionError::new(position, format!("unexpected {}", token))),
+        }
+    }
+
+    // ("name"), (name) or a bare name
+    fn argument(&mut self) -> Result<String, ConditionError> {
+        let position = self.position();
+        let bracketed = self.peek() == Some(&Token::Open);
+        if bracketed {
+            self.index += 1;
+        }
+
+        let argument = match self.next()? {
+            Token::Word(word) | Token::Text(word) => word,
+            Token::Int(value) => value.to_string(),
+            token => return Err(ConditionError::new(position, format!("expected a name, found {}", token))),
+        };
+
+        if bracketed && self.next()? != Token::Close {
+            return Err(ConditionError::new(position, "expected \")\" after the name"));
+        }
+        Ok(argument)
+    }
+
+    fn value(&mut self) -> Result<FlagValue, ConditionError> {
+        let position = self.position();
+        match self.next()? {
+            Token::Int(value) => Ok(FlagValue::Int(value)),
+            Token::Text(text) => Ok(FlagValue::Text(text)),
+            Token::Word(word) => Ok(FlagValue::parse(&word)),
+            token => Err(ConditionError::new(position, format!("expected a value, found {}", token))),
+        }
+    }
+}
+
+/// Reads an optional condition String field off an LDtk entity. One that
+/// does not parse is reported and left out.
+pub fn field_condition(entity_instance: &EntityInstance, identifier: &str) -> Option<Condition> {
+    let source = field_string(entity_instance, identifier)?;
+    match Condition::parse(&source) {
+        Ok(condition) => Some(condition),
+        Err(error) => {
+            warn!("{} field of {} could not be read, {}", identifier, entity_instance.identifier, error);
+            None
+        }
+    }
+}
+
+// SYSTEMS
+
+fn send_flag_changes(
+    mut flags: ResMut<GameFlags>,
+    mut changed: EventWriter<FlagChanged>,
+) {
+    // only reaching in through DerefMut marks the resource changed
+    if flags.changes.is_empty() {
+        return;
+    }
+
+    for change in flags.drain_changes() {
+        info!("Flag {} is now {:?}", change.key, change.new);
+        changed.send(change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Condition {
+        Condition::parse(source).unwrap_or_else(|error| panic!("{:?} did not parse, {}", source, error))
+    }
+
+    fn flag(key: &str) -> Condition {
+        Condition::Flag(key.to_string())
+    }
+
+    fn error(source: &str) -> ConditionError {
+        Condition::parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_not_tightest() {
+        let expected = Condition::Any(vec![
+            flag("a"),
+            Condition::All(vec![flag("b"), Condition::Not(Box::new(flag("c")))]),
+        ]);
+        assert_eq!(parse("flag(a) || flag(b) && !flag(c)"), expected);
+        assert_eq!(parse("flag a or flag b and not flag c"), expected);
+
+        assert_eq!(
+            parse("(flag a || flag b) && flag c"),
+            Condition::All(vec![Condition::Any(vec![flag("a"), flag("b")]), flag("c")]),
+        );
+        assert_eq!(
+            parse("!!flag a"),
+            Condition::Not(Box::new(Condition::Not(Box::new(flag("a"))))),
+        );
+    }
+
+    #[test]
+    fn comparisons_read_every_operator_and_value() {
+        let compare = |comparison, value: FlagValue| Condition::Compare("coins".to_string(), comparison, value);
+
+        assert_eq!(parse("flag(coins) == 3"), compare(Comparison::Equal, FlagValue::Int(3)));
+        assert_eq!(parse("flag(coins) != 3"), compare(Comparison::NotEqual, FlagValue::Int(3)));
+        assert_eq!(parse("flag(coins) < 3"), compare(Comparison::Less, FlagValue::Int(3)));
+        assert_eq!(parse("flag(coins) <= 3"), compare(Comparison::LessOrEqual, FlagValue::Int(3)));
+        assert_eq!(parse("flag(coins) > -3"), compare(Comparison::Greater, FlagValue::Int(-3)));
+        assert_eq!(parse("flag coins>=3"), compare(Comparison::GreaterOrEqual, FlagValue::Int(3)));
+        assert_eq!(parse("flag coins == true"), compare(Comparison::Equal, FlagValue::Bool(true)));
+        assert_eq!(parse("flag coins == many"), compare(Comparison::Equal, FlagValue::Text("many".to_string())));
+        assert_eq!(parse("flag coins == \"3\""), compare(Comparison::Equal, FlagValue::Text("3".to_string())));
+
+        let mut flags = GameFlags::default();
+        flags.set("coins", 3);
+        flags.set("door", "open");
+        let inventory = Inventory::default();
+        let holds = |source| parse(source).evaluate(&flags, &inventory);
+
+        assert!(holds("flag coins >= 3"));
+        assert!(!holds("flag coins > 3"));
+        assert!(holds("flag coins != 4"));
+        assert!(holds("flag door == open"));
+        // ordering only holds between numbers
+        assert!(!holds("flag door > 1"));
+        assert!(!holds("flag missing < 1"));
+        assert!(holds("flag missing != 1"));
+    }
+
+    #[test]
+    fn arguments_may_be_quoted_bracketed_or_bare() {
+        for source in ["flag(\"met_guard\")", "flag(met_guard)", "flag \"met_guard\"", "flag met_guard"] {
+            assert_eq!(parse(source), flag("met_guard"), "{}", source);
+        }
+        assert_eq!(parse("has_item Gumball"), Condition::HasItem("Gumball".to_string()));
+        assert_eq!(parse("has_item(\"Red Key\")"), Condition::HasItem("Red Key".to_string()));
+        assert_eq!(parse("flag(\"say \\\"hi\\\"\")"), flag("say \"hi\""));
+        assert_eq!(parse("flag 7"), flag("7"));
+        assert_eq!(parse("true && !false"), Condition::All(vec![
+            Condition::Literal(true),
+            Condition::Not(Box::new(Condition::Literal(false))),
+        ]));
+    }
+
+    #[test]
+    fn words_keep_dots_and_dashes_anywhere() {
+        assert_eq!(parse("flag met-receptionist"), flag("met-receptionist"));
+        assert_eq!(parse("flag quest.check_in.step"), flag("quest.check_in.step"));
+        assert_eq!(parse("flag .hidden"), flag(".hidden"));
+        assert_eq!(parse("flag -dash"), flag("-dash"));
+        assert_eq!(parse("has_item old-key.2"), Condition::HasItem("old-key.2".to_string()));
+    }
+
+    #[test]
+    fn errors_point_at_where_reading_stopped() {
+        let cases = [
+            ("flag a &&", 9, "condition ended early"),
+            ("flag a @ flag b", 7, "unexpected \"@\""),
+            ("flag a flag b", 7, "unexpected \"flag\""),
+            ("flag a )", 7, "unexpected \")\""),
+            ("has_item \"Gum", 9, "unclosed quote"),
+            ("open(door)", 0, "unknown function \"open\""),
+            ("(flag a", 7, "condition ended early"),
+            ("(flag a flag b", 0, "expected \")\", found \"flag\""),
+            ("flag(a b)", 4, "expected \")\" after the name"),
+            ("flag &&", 5, "expected a name, found \"&&\""),
+            ("flag a == ||", 10, "expected a value, found \"||\""),
+            ("", 0, "condition ended early"),
+        ];
+        for (source, position, message) in cases {
+            assert_eq!(error(source), ConditionError::new(position, message), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_condition() {
+        let compare = Condition::Compare("coins".to_string(), Comparison::GreaterOrEqual, FlagValue::Int(-2));
+        let text = Condition::Compare("door".to_string(), Comparison::Equal, FlagValue::Text("true".to_string()));
+        let not = |condition: Condition| Condition::Not(Box::new(condition));
+
+        let conditions = [
+            flag("met-receptionist"),
+            Condition::HasItem("Red Key".to_string()),
+            compare.clone(),
+            not(compare.clone()),
+            not(not(flag("a"))),
+            Condition::Any(vec![flag("a"), Condition::All(vec![flag("b"), not(flag("c"))])]),
+            Condition::All(vec![Condition::Any(vec![flag("a"), flag("b")]), text.clone()]),
+            Condition::All(vec![Condition::All(vec![flag("a"), flag("b")]), flag("c")]),
+            Condition::Any(vec![Condition::Any(vec![flag("a"), flag("b")]), flag("c")]),
+            not(Condition::All(vec![flag("a"), Condition::Any(vec![compare.clone(), Condition::Literal(false)])])),
+            Condition::Any(vec![
+                not(Condition::Any(vec![text, flag("x.y")])),
+                Condition::All(vec![not(Condition::All(vec![flag("a"), flag("b")])), compare]),
+            ]),
+        ];
+        for condition in conditions {
+            let source = condition.to_string();
+            assert_eq!(parse(&source), condition, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+use crate::components_events::*;
+use crate::dialogue_ui::FONT;
+use crate::pause::toggle_pause;
+use crate::quest::{QuestBook, QuestLog};
+use crate::GameState;
+
+pub struct JournalPlugin;
+
+impl Plugin for JournalPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Journal>()
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    // Escape is Back and Pause both, pausing has to see the
+                    // journal still open to leave it alone
+                    .with_system(
+                        toggle_journal
+                        .after(toggle_pause)
+                    )
+                    .with_system(
+                        spawn_journal
+                        .after(toggle_journal)
+                    )
+            );
+    }
+}
+
+const PANEL_WIDTH: f32 = 380.;
+
+const PANEL_COLOR: Color = Color::rgba(0.08, 0.08, 0.12, 0.9);
+const HEADING_COLOR: Color = Color::rgb(0.95, 0.8, 0.45);
+const TEXT_COLOR: Color = Color::WHITE;
+const DONE_COLOR: Color = Color::rgb(0.55, 0.55, 0.6);
+
+// RESOURCES
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Journal {
+    pub open: bool,
+}
+
+// UI COMPONENTS
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct JournalPanel;
+
+// SYSTEMS
+
+fn toggle_journal(
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    mut journal: ResMut<Journal>,
+) {
+    let action_state = match action_state.get_single() {
+        Ok(action_state) => action_state,
+        Err(_) => return,
+    };
+
+    if action_state.just_pressed(Action::Journal) {
+        journal.open = !journal.open;
+    } else if journal.open && (action_state.just_pressed(Action::Back) || action_state.just_pressed(Action::Pause)) {
+        journal.open = false;
+    }
+}
+
+// Rebuilt whenever it opens or a quest moves. Active quests show the steps
+// done so far and the current one, later steps stay hidden. Bevy's UI has y
+// pointing up, so `bottom` keeps the panel at the top of the window.
+fn spawn_journal(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    journal: Res<Journal>,
+    book: Res<QuestBook>,
+    log: Res<QuestLog>,
+    panels: Query<Entity, With<JournalPanel>>,
+) {
+    if !journal.is_changed() && !log.is_changed() {
+        return;
+    }
+
+    for entity in panels.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !journal.open {
+        return;
+    }
+
+    let font: Handle<Font> = asset_server.load(FONT);
+    let style = |font_size: f32, color: Color| TextStyle {
+        font: font.clone(),
+        font_size,
+        color,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(16.),
+                    bottom: Val::Px(16.),
+                    ..default()
+                },
+                size: Size::new(Val::Px(PANEL_WIDTH), Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: UiRect::all(Val::Px(16.)),
+                ..default()
+            },
+            color: PANEL_COLOR.into(),
+            ..default()
+        })
+        .insert(JournalPanel)
+        .with_children(|column| {
+            line(column, "Journal", style(28., HEADING_COLOR), 12.);
+
+            if log.0.is_empty() {
+                line(column, "Nothing to do yet", style(20., DONE_COLOR), 0.);
+            }
+
+            for progress in log.active() {
+                let quest = match book.get(&progress.id) {
+                    Some(quest) => quest,
+                    None => continue,
+                };
+
+                line(column, &quest.title, style(24., HEADING_COLOR), 4.);
+                if !quest.description.is_empty() {
+                    line(column, &quest.description, style(18., DONE_COLOR), 4.);
+                }
+                for step in quest.steps.iter().take(progress.step) {
+                    line(column, &format!("\u{2713} {}", step.description), style(20., DONE_COLOR), 2.);
+                }
+                if let Some(step) = quest.steps.get(progress.step) {
+                    line(column, &format!("\u{2022} {}", step.description), style(20., TEXT_COLOR), 2.);
+                }
+                column.spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Auto, Val::Px(10.)),
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                });
+            }
+
+            if log.completed().next().is_some() {
+                line(column, "Completed", style(24., HEADING_COLOR), 4.);
+                for progress in log.completed() {
+                    let title = book
+                        .get(&progress.id)
+                        .map_or(progress.id.as_str(), |quest| quest.title.as_str());
+                    line(column, &format!("\u{2713} {}", title), style(20., DONE_COLOR), 2.);
+                }
+            }
+        });
+}
+
+fn line(column: &mut ChildBuilder, text: &str, style: TextStyle, margin: f32) {
+    column.spawn_bundle(TextBundle {
+        text: Text::from_section(text, style),
+        style: Style {
+            max_size: Size::new(Val::Px(PANEL_WIDTH - 32.), Val::Undefined),
+            margin: UiRect {
+                bottom: Val::Px(margin),
+                ..default()
+            },
+            ..default()
+        },
+        ..default()
+    });
+}
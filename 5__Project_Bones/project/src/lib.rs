@@ -3,6 +3,25 @@ pub mod components_events;
 pub mod systems;
 pub mod settings;
 pub mod setup;
+pub mod dialogue;
+pub mod dialogue_ui;
+pub mod npc;
+pub mod navigation;
+pub mod puzzle;
+pub mod triggers;
+pub mod limbs;
+pub mod animation;
+pub mod aseprite;
+pub mod ysort;
+pub mod camera;
+pub mod menu;
+pub mod pause;
+pub mod loading;
+pub mod save;
+pub mod flags;
+pub mod quest;
+pub mod journal;
+pub mod replay;
 
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -0,0 +1,761 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+use crate::camera::Cursor;
+use crate::components_events::*;
+use crate::navigation::{NavGrid, CELL_SIZE};
+use crate::ysort::{NoYSort, Y_SORT_SCALE};
+use crate::GameState;
+
+pub struct LimbPlugin;
+
+impl Plugin for LimbPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<BodySheet>()
+            .init_resource::<TetherLengths>()
+            .add_event::<LimbDetached>()
+            .add_event::<LimbRecalled>()
+            .add_event::<LimbReattached>()
+
+            // the IntGrid is spawned by LDtk while the game is still Loading
+            .add_system(mark_hidden_walls)
+            // limbs also come back from saves, which load from the main menu
+            .add_system(attach_tethers)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(detach_part)
+                    .with_system(limb_movement)
+                    .with_system(recall_limbs)
+                    .with_system(
+                        merge_parts
+                        .after(detach_part)
+                        .after(recall_limbs)
+                    )
+                    .with_system(head_vision)
+                    .with_system(aim_arm)
+                    .with_system(
+                        preview_trajectory
+                        .after(aim_arm)
+                    )
+                    .with_system(
+                        keep_tethers_round
+                        .after(limb_movement)
+                        .after(recall_limbs)
+                    )
+                    .with_system(
+                        draw_tethers
+                        .after(keep_tethers_round)
+                    )
+            );
+    }
+}
+
+/// Parts come off the body in this order, one per `Split`.
+pub const DETACH_ORDER: [BodyPart; 5] = [
+    BodyPart::LeftArm,
+    BodyPart::RightArm,
+    BodyPart::LeftLeg,
+    BodyPart::RightLeg,
+    BodyPart::Head,
+];
+
+pub const BODY_SHEET: &str = "visual/body_parts.png";
+pub const BODY_SHEET_CELL: f32 = 16.;
+const LIMB_RADIUS: f32 = 5.;
+const LIMB_DAMPING: f32 = 8.;
+// how close a limb has to be to the body to be put back on
+const MERGE_RANGE: f32 = 24.;
+
+// pixels per second
+const CRAWL_SPEED: f32 = 40.;
+const HOP_SPEED: f32 = 110.;
+const ROLL_SPEED: f32 = 70.;
+const RECALL_SPEED: f32 = 160.;
+// seconds, a leg is only moving for the first part of every hop
+const HOP_PERIOD: f32 = 0.5;
+const HOP_AIRTIME: f32 = 0.2;
+
+// launch speed per pixel the arm is pulled back, and the most it can get
+const FLING_STRENGTH: f32 = 8.;
+const MAX_FLING_SPEED: f32 = 900.;
+// how far a fully pushed stick pulls the arm back, in pixels
+const STICK_PULL: f32 = 100.;
+const STICK_DEADZONE: f32 = 0.2;
+const PREVIEW_DOTS: usize = 12;
+// seconds of flight between two dots
+const PREVIEW_STEP: f32 = 0.05;
+const PREVIEW_COLOR: Color = Color::rgba(1., 1., 1., 0.7);
+const TETHER_WIDTH: f32 = 1.5;
+const TETHER_COLOR: Color = Color::rgb(0.62, 0.25, 0.25);
+// drawn brighter once the limb is this close to the end of its tether
+const TETHER_TAUT: f32 = 0.95;
+const TETHER_TAUT_COLOR: Color = Color::rgb(0.9, 0.4, 0.35);
+
+// value of the `hidden` cells in the Sp_wall_Collider IntGrid
+const HIDDEN_WALL: i32 = 2;
+const HIDDEN_WALL_COLOR: Color = Color::rgba(0.6, 0.3, 0.9, 0.45);
+
+// LIMB COMPONENTS
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Locomotion {
+    // arms drag themselves along at a steady pace
+    Crawl,
+    // legs cover ground in short bursts
+    Hop,
+    // the head is quick and spins as it goes
+    Roll,
+}
+
+impl Locomotion {
+    pub fn for_part(part: BodyPart) -> Self {
+        match part {
+            BodyPart::LeftLeg | BodyPart::RightLeg => Locomotion::Hop,
+            BodyPart::Head => Locomotion::Roll,
+            _ => Locomotion::Crawl,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Component)]
+pub struct Limb {
+    pub locomotion: Locomotion,
+    pub hop: Timer,
+}
+
+impl Limb {
+    pub fn new(part: BodyPart) -> Self {
+        Limb {
+            locomotion: Locomotion::for_part(part),
+            hop: Timer::from_seconds(HOP_PERIOD, true),
+        }
+    }
+}
+
+// A limb finding its own way back to the body, see `recall_limbs`
+#[derive(Clone, Debug, Default, Component)]
+pub struct Recalling {
+    // world positions still to walk through, next one first
+    pub path: Vec<Vec2>,
+}
+
+// One dot of the dotted line showing where a flung arm will land
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct TrajectoryDot(pub usize);
+
+// The sinew drawn between the body and a detached limb
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub struct TetherLine(pub Entity);
+
+// Shown over a hidden wall while the head is looking around
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct HiddenWall;
+
+// RESOURCES
+
+/// One 16px cell per body part, in `BodyPart` order.
+pub struct BodySheet(pub Handle<TextureAtlas>);
+
+impl FromWorld for BodySheet {
+    fn from_world(world: &mut World) -> Self {
+        let texture = world.resource::<AssetServer>().load(BODY_SHEET);
+        let atlas = TextureAtlas::from_grid(texture, Vec2::splat(BODY_SHEET_CELL), 6, 1);
+        BodySheet(world.resource_mut::<Assets<TextureAtlas>>().add(atlas))
+    }
+}
+
+/// Furthest each kind of limb can stray from the body, in pixels. Insert your
+/// own before adding `LimbPlugin` to change them.
+#[derive(Clone, Debug)]
+pub struct TetherLengths {
+    pub arm: f32,
+    pub leg: f32,
+    pub head: f32,
+}
+
+impl Default for TetherLengths {
+    fn default() -> Self {
+        TetherLengths {
+            arm: 96.,
+            leg: 64.,
+            head: 128.,
+        }
+    }
+}
+
+impl TetherLengths {
+    pub fn for_part(&self, part: BodyPart) -> f32 {
+        match part {
+            BodyPart::LeftArm | BodyPart::RightArm => self.arm,
+            BodyPart::LeftLeg | BodyPart::RightLeg => self.leg,
+            BodyPart::Head => self.head,
+            BodyPart::Body => 0.,
+        }
+    }
+}
+
+pub fn sheet_index(part: BodyPart) -> usize {
+    match part {
+        BodyPart::Body => 0,
+        BodyPart::LeftArm => 1,
+        BodyPart::RightArm => 2,
+        BodyPart::LeftLeg => 3,
+        BodyPart::RightLeg => 4,
+        BodyPart::Head => 5,
+    }
+}
+
+// where a part lands relative to the body when it comes off
+fn detach_offset(part: BodyPart) -> Vec2 {
+    match part {
+        BodyPart::LeftArm => Vec2::new(-14., 0.),
+        BodyPart::RightArm => Vec2::new(14., 0.),
+        BodyPart::LeftLeg => Vec2::new(-6., -16.),
+        BodyPart::RightLeg => Vec2::new(6., -16.),
+        BodyPart::Head => Vec2::new(0., 16.),
+        BodyPart::Body => Vec2::ZERO,
+    }
+}
+
+pub fn launch_velocity(pull: Vec2) -> Vec2 {
+    (pull * FLING_STRENGTH).clamp_length_max(MAX_FLING_SPEED)
+}
+
+// Where a limb launched at `velocity` is after `seconds`, slowed by damping
+pub fn fling_position(start: Vec2, velocity: Vec2, seconds: f32) -> Vec2 {
+    start + velocity * (1. - (-LIMB_DAMPING * seconds).exp()) / LIMB_DAMPING
+}
+
+/// The tether is a Rapier joint between the body and the limb. Rapier 0.16
+/// has no rope joint and limits each axis of a generic joint on its own, so
+/// the joint only keeps the limb inside a square around the body, `length` to
+/// each side. `keep_tethers_round` cuts the corners off, the limb never gets
+/// further than `length` from the body.
+pub fn tether_joint(length: f32) -> GenericJoint {
+    GenericJointBuilder::new(JointAxesMask::empty())
+        .limits(JointAxis::X, [-length, length])
+        .limits(JointAxis::Y, [-length, length])
+        .build()
+}
+
+// Where the tether stops a limb heading for `position`
+pub fn clamp_to_tether(position: Vec2, body: Vec2, length: f32) -> Vec2 {
+    body + (position - body).clamp_length_max(length)
+}
+
+// How far along the tether the limb is, 1 once it is taut
+pub fn tether_stretch(offset: Vec2, length: f32) -> f32 {
+    offset.length() / length
+}
+
+// SYSTEMS
+
+fn detach_part(
+    mut commands: Commands,
+    body_sheet: Res<BodySheet>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    mut player: Query<&mut BodyParts, With<Player>>,
+    body_parts: Query<(&BodyPart, &GlobalTransform)>,
+    mut detached: EventWriter<LimbDetached>,
+) {
+    let action_state = action_state.single();
+    let mut player = player.single_mut();
+
+    if !action_state.just_pressed(Action::Split) || player.current() != BodyPart::Body {
+        return;
+    }
+
+    let part = match DETACH_ORDER.iter().find(|part| !player.contains(**part)) {
+        Some(part) => *part,
+        None => {
+            info!("Nothing left to detach");
+            return;
+        }
+    };
+
+    let body = match body_parts.iter().find(|(body_part, _)| **body_part == BodyPart::Body) {
+        Some((_, transform)) => transform.translation(),
+        None => return,
+    };
+    let position = body + detach_offset(part).extend(0.);
+    let limb = spawn_limb(&mut commands, &body_sheet, part, position);
+
+    player.push(part);
+    detached.send(LimbDetached(limb, part));
+    info!("Detached {:?}", part);
+}
+
+/// Spawns a detached limb lying still at `position`. Adding it to the
+/// player's `BodyParts` is left to the caller.
+pub fn spawn_limb(commands: &mut Commands, body_sheet: &BodySheet, part: BodyPart, position: Vec3) -> Entity {
+    let mut limb = commands.spawn_bundle(SpriteSheetBundle {
+        texture_atlas: body_sheet.0.clone(),
+        sprite: TextureAtlasSprite::new(sheet_index(part)),
+        transform: Transform::from_translation(position),
+        ..default()
+    });
+    limb
+        .insert(part)
+        .insert(Limb::new(part))
+        .insert(RigidBody::Dynamic)
+        .insert(Collider::ball(LIMB_RADIUS))
+        .insert(Velocity::default())
+        .insert(ExternalImpulse::default())
+        .insert(ReadMassProperties::default())
+        .insert(Damping {
+            linear_damping: LIMB_DAMPING,
+            angular_damping: LIMB_DAMPING,
+        });
+    // only the head is round enough to roll
+    if part != BodyPart::Head {
+        limb.insert(LockedAxes::ROTATION_LOCKED);
+    }
+    limb.id()
+}
+
+// Puts every limb in range back on the body. Control falls back to the body
+// if the current part was one of them, and the CameraRig takes the camera
+// along with it.
+fn merge_parts(
+    mut commands: Commands,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    mut player: Query<&mut BodyParts, With<Player>>,
+    body_parts: Query<(&BodyPart, &GlobalTransform)>,
+    limbs: Query<(Entity, &BodyPart, &GlobalTransform, Option<&Recalling>), With<Limb>>,
+    mut reattached: EventWriter<LimbReattached>,
+) {
+    let action_state = action_state.single();
+    let mut player = player.single_mut();
+
+    let body = match body_parts.iter().find(|(body_part, _)| **body_part == BodyPart::Body) {
+        Some((_, transform)) => transform.translation().truncate(),
+        None => return,
+    };
+    let merging = action_state.just_pressed(Action::Merge);
+
+    for (entity, part, transform, recalling) in limbs.iter() {
+        // recalled limbs climb back on by themselves
+        if !merging && recalling.is_none() {
+            continue;
+        }
+        if transform.translation().truncate().distance(body) > MERGE_RANGE {
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+        player.remove(*part);
+        reattached.send(LimbReattached(*part));
+        info!("Reattached {:?}", part);
+    }
+}
+
+// Recall sends every detached limb back to the body along the nav grid,
+// re-pathing whenever it runs out of path before getting there.
+fn recall_limbs(
+    mut commands: Commands,
+    nav_grid: Res<NavGrid>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    body_parts: Query<(&BodyPart, &GlobalTransform), Without<Limb>>,
+    mut limbs: Query<(Entity, &BodyPart, &GlobalTransform, &mut Velocity, Option<&mut Recalling>), With<Limb>>,
+    mut recalled: EventWriter<LimbRecalled>,
+) {
+    let action_state = action_state.single();
+
+    let body = match body_parts.iter().find(|(body_part, _)| **body_part == BodyPart::Body) {
+        Some((_, transform)) => transform.translation().truncate(),
+        None => return,
+    };
+
+    if action_state.just_pressed(Action::Recall) {
+        for (entity, part, _, _, recalling) in limbs.iter_mut() {
+            if recalling.is_none() {
+                commands.entity(entity).insert(Recalling::default());
+                recalled.send(LimbRecalled(entity, *part));
+            }
+        }
+        return;
+    }
+
+    for (_, _, transform, mut velocity, recalling) in limbs.iter_mut() {
+        let mut recalling = match recalling {
+            Some(recalling) => recalling,
+            None => continue,
+        };
+        let position = transform.translation().truncate();
+
+        if recalling.path.is_empty() {
+            recalling.path = nav_grid
+                .find_path(NavGrid::world_to_cell(position), NavGrid::world_to_cell(body))
+                .map(|cells| cells.into_iter().map(NavGrid::cell_to_world).collect())
+                // straight at the body if the grid has no way through
+                .unwrap_or_default();
+            recalling.path.push(body);
+        }
+
+        let target = recalling.path[0];
+        if position.distance(target) < CELL_SIZE / 2. {
+            recalling.path.remove(0);
+        }
+        velocity.linvel = (target - position).normalize_or_zero() * RECALL_SPEED;
+    }
+}
+
+fn limb_movement(
+    time: Res<Time>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    player: Query<(&Player, &BodyParts)>,
+    mut limbs: Query<(&BodyPart, &mut Limb, &mut Velocity), Without<Recalling>>,
+) {
+    let action_state = action_state.single();
+    let (player, body_parts) = player.single();
+
+    if *player != Player::Moving {
+        return;
+    }
+
+    let mut direction = Vec2::ZERO;
+    if action_state.pressed(Action::Up) {
+        direction.y += 1.;
+    }
+    if action_state.pressed(Action::Down) {
+        direction.y -= 1.;
+    }
+    if action_state.pressed(Action::Left) {
+        direction.x -= 1.;
+    }
+    if action_state.pressed(Action::Right) {
+        direction.x += 1.;
+    }
+    let direction = direction.normalize_or_zero();
+
+    for (part, mut limb, mut velocity) in limbs.iter_mut() {
+        if *part != body_parts.current() {
+            continue;
+        }
+
+        // damping brings the limb to a stop once nothing is held
+        if direction == Vec2::ZERO {
+            limb.hop.reset();
+            continue;
+        }
+
+        match limb.locomotion {
+            Locomotion::Crawl => velocity.linvel = direction * CRAWL_SPEED,
+
+            Locomotion::Hop => {
+                limb.hop.tick(time.delta());
+                if limb.hop.elapsed_secs() < HOP_AIRTIME {
+                    velocity.linvel = direction * HOP_SPEED;
+                }
+            }
+
+            Locomotion::Roll => {
+                velocity.linvel = direction * ROLL_SPEED;
+                velocity.angvel = -direction.x * ROLL_SPEED / LIMB_RADIUS;
+            }
+        }
+    }
+}
+
+// Hidden walls get an overlay that only shows while the head is looking
+fn mark_hidden_walls(
+    mut commands: Commands,
+    cells: Query<(Entity, &IntGridCell, &Parent), Added<IntGridCell>>,
+    layers: Query<&LayerMetadata>,
+) {
+    for (entity, cell, parent) in cells.iter() {
+        if cell.value != HIDDEN_WALL {
+            continue;
+        }
+        match layers.get(parent.get()) {
+            Ok(layer) if layer.identifier == "Sp_wall_Collider" => {}
+            _ => continue,
+        }
+
+        commands.entity(entity).with_children(|cell| {
+            cell.spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: HIDDEN_WALL_COLOR,
+                    custom_size: Some(Vec2::splat(BODY_SHEET_CELL)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(0., 0., 50.),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(HiddenWall)
+            .insert(NoYSort);
+        });
+    }
+}
+
+fn head_vision(
+    player: Query<&BodyParts, (With<Player>, Changed<BodyParts>)>,
+    mut hidden_walls: Query<&mut Visibility, With<HiddenWall>>,
+) {
+    let player = match player.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    let looking = player.current() == BodyPart::Head;
+    for mut visibility in hidden_walls.iter_mut() {
+        if visibility.is_visible != looking {
+            visibility.is_visible = looking;
+        }
+    }
+}
+
+// Slingshot aiming for the selected arm. Drag the mouse back from where it
+// was pressed, or push the right stick away from the target, and let go to
+// fling the arm the other way.
+fn aim_arm(
+    cursor: Res<Cursor>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    mut player: Query<(&Player, &BodyParts, &mut ArmAim)>,
+    mut arms: Query<(&BodyPart, &ReadMassProperties, &mut ExternalImpulse), With<Limb>>,
+) {
+    let action_state = action_state.single();
+    let (player, body_parts, mut aim) = player.single_mut();
+    let current = body_parts.current();
+
+    if *player != Player::Moving || !matches!(current, BodyPart::LeftArm | BodyPart::RightArm) {
+        if *aim != ArmAim::default() {
+            *aim = ArmAim::default();
+        }
+        return;
+    }
+
+    let mut release = false;
+
+    let cursor = cursor.0;
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        aim.drag_start = cursor;
+    }
+    if let (Some(start), Some(cursor)) = (aim.drag_start, cursor) {
+        aim.pull = Some(start - cursor);
+    }
+    if mouse_buttons.just_released(MouseButton::Left) && aim.drag_start.is_some() {
+        aim.drag_start = None;
+        release = true;
+    }
+
+    if let Some(stick) = action_state.axis_pair(Action::Aim) {
+        let stick = stick.xy();
+        if stick.length() > STICK_DEADZONE {
+            aim.pull = Some(stick * STICK_PULL);
+            aim.stick_held = true;
+        } else if aim.stick_held {
+            aim.stick_held = false;
+            release = true;
+        }
+    }
+
+    if !release {
+        return;
+    }
+    let pull = match aim.pull.take() {
+        Some(pull) => pull,
+        None => return,
+    };
+
+    for (part, mass, mut impulse) in arms.iter_mut() {
+        if *part == current {
+            impulse.impulse = launch_velocity(pull) * mass.0.mass;
+            info!("Flung {:?}", part);
+        }
+    }
+}
+
+fn preview_trajectory(
+    mut commands: Commands,
+    lengths: Res<TetherLengths>,
+    player: Query<(&BodyParts, &ArmAim), With<Player>>,
+    bodies: Query<(&BodyPart, &GlobalTransform), Without<Limb>>,
+    limbs: Query<(&BodyPart, &GlobalTransform), With<Limb>>,
+    mut dots: Query<(&TrajectoryDot, &mut Transform, &mut Visibility)>,
+) {
+    let (body_parts, aim) = player.single();
+
+    if dots.is_empty() {
+        for index in 0..PREVIEW_DOTS {
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: PREVIEW_COLOR,
+                        custom_size: Some(Vec2::splat(2.)),
+                        ..default()
+                    },
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(TrajectoryDot(index))
+                .insert(NoYSort);
+        }
+        return;
+    }
+
+    let start = limbs
+        .iter()
+        .find(|(part, _)| **part == body_parts.current())
+        .map(|(_, transform)| transform.translation());
+
+    let (start, pull) = match (start, aim.pull) {
+        (Some(start), Some(pull)) => (start, pull),
+        _ => {
+            for (_, _, mut visibility) in dots.iter_mut() {
+                if visibility.is_visible {
+                    visibility.is_visible = false;
+                }
+            }
+            return;
+        }
+    };
+
+    let body = bodies
+        .iter()
+        .find(|(part, _)| **part == BodyPart::Body)
+        .map_or(start.truncate(), |(_, transform)| transform.translation().truncate());
+    let length = lengths.for_part(body_parts.current());
+
+    let velocity = launch_velocity(pull);
+    for (dot, mut transform, mut visibility) in dots.iter_mut() {
+        let position = fling_position(start.truncate(), velocity, (dot.0 + 1) as f32 * PREVIEW_STEP);
+        // the tether stops the arm short, so does the preview
+        let position = clamp_to_tether(position, body, length);
+        transform.translation = position.extend(start.z + 1.);
+        visibility.is_visible = true;
+    }
+}
+
+// Ties every new limb to the body. The body is kinematic, so the joint drags
+// limbs along behind it when it walks away, and flung or crawling limbs are
+// stopped by the solver at the end of their tether.
+fn attach_tethers(
+    mut commands: Commands,
+    lengths: Res<TetherLengths>,
+    bodies: Query<(Entity, &BodyPart), Without<Limb>>,
+    limbs: Query<(Entity, &BodyPart), (With<Limb>, Without<ImpulseJoint>)>,
+) {
+    let body = match bodies.iter().find(|(_, part)| **part == BodyPart::Body) {
+        Some((entity, _)) => entity,
+        None => return,
+    };
+
+    for (limb, part) in limbs.iter() {
+        commands
+            .entity(limb)
+            .insert(ImpulseJoint::new(body, tether_joint(lengths.for_part(*part))));
+    }
+}
+
+// The joint lets a limb into the corners of its square, a limb past its
+// length is put back on the circle and loses any speed carrying it further out.
+fn keep_tethers_round(
+    lengths: Res<TetherLengths>,
+    bodies: Query<(&BodyPart, &GlobalTransform), Without<Limb>>,
+    mut limbs: Query<(&BodyPart, &mut Transform, &mut Velocity), With<Limb>>,
+) {
+    let body = match bodies.iter().find(|(part, _)| **part == BodyPart::Body) {
+        Some((_, transform)) => transform.translation().truncate(),
+        None => return,
+    };
+
+    for (part, mut transform, mut velocity) in limbs.iter_mut() {
+        let position = transform.translation.truncate();
+        let length = lengths.for_part(*part);
+        if position.distance(body) <= length {
+            continue;
+        }
+
+        let clamped = clamp_to_tether(position, body, length);
+        transform.translation.x = clamped.x;
+        transform.translation.y = clamped.y;
+
+        let outward = (position - body).normalize();
+        let speed = velocity.linvel.dot(outward);
+        if speed > 0. {
+            velocity.linvel -= outward * speed;
+        }
+    }
+}
+
+fn draw_tethers(
+    mut commands: Commands,
+    lengths: Res<TetherLengths>,
+    bodies: Query<(&BodyPart, &GlobalTransform), Without<Limb>>,
+    new_limbs: Query<Entity, Added<Limb>>,
+    limbs: Query<(&BodyPart, &GlobalTransform), With<Limb>>,
+    mut lines: Query<(Entity, &TetherLine, &mut Transform, &mut Sprite)>,
+) {
+    for limb in new_limbs.iter() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: TETHER_COLOR,
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(TetherLine(limb))
+            .insert(NoYSort);
+    }
+
+    let body = match bodies.iter().find(|(part, _)| **part == BodyPart::Body) {
+        Some((_, transform)) => transform.translation(),
+        None => return,
+    };
+
+    for (entity, line, mut transform, mut sprite) in lines.iter_mut() {
+        // the limb was put back on
+        let (part, limb) = match limbs.get(line.0) {
+            Ok((part, limb)) => (*part, limb.translation()),
+            Err(_) => {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+
+        let offset = (limb - body).truncate();
+        let length = offset.length();
+        // just behind whichever end is drawn further back
+        transform.translation = ((body + limb) / 2.).truncate().extend(body.z.min(limb.z) - Y_SORT_SCALE);
+        transform.rotation = Quat::from_rotation_z(offset.y.atan2(offset.x));
+        sprite.custom_size = Some(Vec2::new(length, TETHER_WIDTH));
+        sprite.color = if tether_stretch(offset, lengths.for_part(part)) >= TETHER_TAUT {
+            TETHER_TAUT_COLOR
+        } else {
+            TETHER_COLOR
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tether_is_round() {
+        let body = Vec2::new(10., 10.);
+        let diagonal = body + Vec2::splat(100.);
+
+        let clamped = clamp_to_tether(diagonal, body, 50.);
+        assert!((clamped.distance(body) - 50.).abs() < 1e-4);
+        assert!((tether_stretch(clamped - body, 50.) - 1.).abs() < 1e-4);
+        // the corner of the joint's square is well past the end of the tether
+        assert!(tether_stretch(Vec2::splat(50.), 50.) > 1.4);
+    }
+
+    #[test]
+    fn a_limb_inside_its_tether_is_left_alone() {
+        let body = Vec2::ZERO;
+        let position = Vec2::new(30., -20.);
+        assert_eq!(clamp_to_tether(position, body, 50.), position);
+        assert!(tether_stretch(position, 50.) < 1.);
+    }
+}
@@ -0,0 +1,323 @@
+use std::path::Path;
+use bevy::{
+    app::AppExit,
+    asset::LoadState,
+    prelude::*,
+};
+use bevy_ecs_ldtk::prelude::*;
+
+use crate::animation::ANIMATION_FILE;
+use crate::aseprite::ASEPRITE_FILES;
+use crate::camera::CAMERA_FILE;
+use crate::components_events::*;
+use crate::dialogue::DIALOGUE_FILES;
+use crate::dialogue_ui::FONT;
+use crate::limbs::BODY_SHEET;
+use crate::npc::NPC_FILE;
+use crate::quest::QUEST_FILE;
+use crate::settings::{read_settings, SETTINGS_FILE};
+use crate::setup::LDTK_FILE;
+use crate::GameState;
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<LoadingTracker>()
+
+            .add_system_set(
+                SystemSet::on_enter(GameState::Loading)
+                    .with_system(track_required_assets)
+                    .with_system(spawn_loading_screen)
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Loading)
+                    .with_system(finish_loading)
+                    .with_system(
+                        update_loading_screen
+                        .after(finish_loading)
+                    )
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Loading)
+                    .with_system(close_loading_screen)
+            );
+    }
+}
+
+// seconds before a load that is still going counts as stuck
+const LOAD_TIMEOUT: f32 = 30.;
+
+// There is no audio yet, assets/audio only holds a placeholder, and items
+// come from LDtk entity fields rather than a data file. Both belong in here
+// once they exist.
+
+// read straight off the disk by their modules at startup, only checked to
+// exist. The settings are also parsed, nothing can stand in for them.
+const DATA_FILES: [&str; 5] = [
+    SETTINGS_FILE,
+    ANIMATION_FILE,
+    CAMERA_FILE,
+    NPC_FILE,
+    QUEST_FILE,
+];
+
+const BACKGROUND_COLOR: Color = Color::rgb(0.05, 0.05, 0.08);
+const TEXT_COLOR: Color = Color::WHITE;
+const ERROR_COLOR: Color = Color::rgb(0.9, 0.35, 0.35);
+const BAR_COLOR: Color = Color::rgb(0.2, 0.2, 0.28);
+const FILL_COLOR: Color = Color::rgb(0.45, 0.3, 0.55);
+
+// RESOURCES
+
+/// Everything the game needs before it can leave `Loading`. Handles are
+/// loaded by path, so they are the same ones the owning modules hold. The
+/// tilesets are only known once the LDtk project has loaded, they are added
+/// then from its tileset map.
+#[derive(Clone, Debug, Default)]
+pub struct LoadingTracker {
+    pub handles: Vec<(String, HandleUntyped)>,
+    pub tilesets_tracked: bool,
+    pub missing_files: Vec<&'static str>,
+    pub elapsed: f32,
+    // 0 to 1
+    pub progress: f32,
+    // set once loading failed or timed out, the game stays on the error screen
+    pub error: Option<String>,
+}
+
+// LOADING COMPONENTS
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LoadingRoot;
+
+// the UI needs a camera before Setup makes the real ones
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LoadingCamera;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LoadingBar;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LoadingText;
+
+// SYSTEMS
+
+fn track_required_assets(
+    asset_server: Res<AssetServer>,
+    mut tracker: ResMut<LoadingTracker>,
+) {
+    let settings_error = match read_settings(Path::new(SETTINGS_FILE)) {
+        Err(error) if Path::new(SETTINGS_FILE).is_file() => {
+            error!("Could not read {}: {}", SETTINGS_FILE, error);
+            Some(SETTINGS_FILE)
+        }
+        _ => None,
+    };
+
+    let paths = [LDTK_FILE, BODY_SHEET, FONT]
+        .into_iter()
+        .chain(ASEPRITE_FILES)
+        .chain(DIALOGUE_FILES);
+
+    *tracker = LoadingTracker {
+        handles: paths.map(|path| (path.to_string(), asset_server.load_untyped(path))).collect(),
+        missing_files: DATA_FILES
+            .into_iter()
+            .filter(|path| !Path::new(path).is_file())
+            .chain(settings_error)
+            .collect(),
+        ..default()
+    };
+    info!("Loading {} assets", tracker.handles.len());
+}
+
+// The project loads before the images it points at, the tileset handles in
+// its map are the ones the LDtk plugin draws with. Aseprite tilesets are
+// already tracked through ASEPRITE_FILES.
+fn track_tilesets(
+    tracker: &mut LoadingTracker,
+    asset_server: &AssetServer,
+    ldtk_assets: &Assets<LdtkAsset>,
+) {
+    let ldtk_asset = match ldtk_assets.get(&asset_server.get_handle(LDTK_FILE)) {
+        Some(ldtk_asset) => ldtk_asset,
+        None => return,
+    };
+
+    for (uid, handle) in ldtk_asset.tileset_map.iter() {
+        if tracker.handles.iter().any(|(_, tracked)| tracked.id == handle.id) {
+            continue;
+        }
+        let path = ldtk_asset.project.defs.tilesets
+            .iter()
+            .find(|tileset| tileset.uid == *uid)
+            .map_or_else(|| format!("tileset {}", uid), |tileset| {
+                tileset.rel_path.clone().unwrap_or_else(|| tileset.identifier.clone())
+            });
+        tracker.handles.push((path, handle.clone_untyped()));
+    }
+    tracker.tilesets_tracked = true;
+}
+
+// Setup needs the player, so the LDtk level has to have spawned them as well
+fn finish_loading(
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+    mut tracker: ResMut<LoadingTracker>,
+    body_parts: Query<(), With<BodyPart>>,
+    mut app_state: ResMut<State<GameState>>,
+) {
+    if tracker.error.is_some() {
+        return;
+    }
+    tracker.elapsed += time.delta_seconds();
+    if !tracker.tilesets_tracked {
+        track_tilesets(&mut tracker, &asset_server, &ldtk_assets);
+    }
+
+    let mut pending = Vec::new();
+    let mut failed: Vec<String> = tracker.missing_files.iter().map(|path| path.to_string()).collect();
+    for (path, handle) in tracker.handles.iter() {
+        match asset_server.get_load_state(handle) {
+            LoadState::Loaded => {}
+            LoadState::Failed => failed.push(path.clone()),
+            _ => pending.push(path.clone()),
+        }
+    }
+    if !tracker.tilesets_tracked {
+        pending.push("the LDtk tilesets".to_string());
+    }
+    let level_spawned = !body_parts.is_empty();
+    if !level_spawned {
+        pending.push("the player, from the LDtk level".to_string());
+    }
+
+    // the tilesets and the level count as one more step each
+    let total = tracker.handles.len() + DATA_FILES.len() + 2;
+    tracker.progress = (total - pending.len() - failed.len()) as f32 / total as f32;
+
+    if !failed.is_empty() {
+        let error = format!("Could not load\n{}", failed.join("\n"));
+        error!("{}", error);
+        tracker.error = Some(error);
+    } else if tracker.elapsed > LOAD_TIMEOUT {
+        let error = format!("Timed out waiting for\n{}", pending.join("\n"));
+        error!("{}", error);
+        tracker.error = Some(error);
+    } else if pending.is_empty() {
+        info!("Loaded in {:.1}s", tracker.elapsed);
+        app_state.overwrite_set(GameState::Setup).unwrap();
+    }
+}
+
+fn spawn_loading_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    commands
+        .spawn_bundle(Camera2dBundle::default())
+        .insert(LoadingCamera);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: BACKGROUND_COLOR.into(),
+            ..default()
+        })
+        .insert(LoadingRoot)
+        .with_children(|column| {
+            column
+                .spawn_bundle(TextBundle {
+                    text: Text::from_section(
+                        "Loading",
+                        TextStyle {
+                            font: asset_server.load(FONT),
+                            font_size: 28.,
+                            color: TEXT_COLOR,
+                        },
+                    ),
+                    style: Style {
+                        margin: UiRect {
+                            bottom: Val::Px(12.),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(LoadingText);
+
+            column
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(320.), Val::Px(16.)),
+                        ..default()
+                    },
+                    color: BAR_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.), Val::Percent(100.)),
+                                ..default()
+                            },
+                            color: FILL_COLOR.into(),
+                            ..default()
+                        })
+                        .insert(LoadingBar);
+                });
+        });
+}
+
+// The font is one of the tracked assets, so the text only shows up once it
+// has loaded. Escape quits from the error screen, there are no keybinds yet.
+fn update_loading_screen(
+    tracker: Res<LoadingTracker>,
+    keys: Res<Input<KeyCode>>,
+    mut bars: Query<&mut Style, With<LoadingBar>>,
+    mut texts: Query<&mut Text, With<LoadingText>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let width = Val::Percent(tracker.progress * 100.);
+    for mut style in bars.iter_mut() {
+        if style.size.width != width {
+            style.size.width = width;
+        }
+    }
+
+    let error = match &tracker.error {
+        Some(error) => error,
+        None => return,
+    };
+    if keys.just_pressed(KeyCode::Escape) {
+        exit.send(AppExit);
+    }
+
+    for mut text in texts.iter_mut() {
+        let message = format!("{}\n\nPress escape to quit", error);
+        if text.sections[0].value != message {
+            text.sections[0].value = message;
+            text.sections[0].style.color = ERROR_COLOR;
+        }
+    }
+}
+
+fn close_loading_screen(
+    mut commands: Commands,
+    screens: Query<Entity, Or<(With<LoadingRoot>, With<LoadingCamera>)>>,
+) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,30 +1,69 @@
 use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
 use leafwing_input_manager::prelude::*;
 use bevy::{
     prelude::*,
-    window::close_on_esc,
+    asset::AssetServerSettings,
+    render::texture::ImageSettings,
 };
 use project_bones::{
     GameState,
     components_events::*,
     systems::InteractionsPlugin,
+    dialogue::DialoguePlugin,
+    dialogue_ui::DialogueUiPlugin,
+    npc::NpcPlugin,
+    puzzle::PuzzlePlugin,
+    triggers::TriggerPlugin,
+    limbs::LimbPlugin,
+    animation::AnimationPlugin,
+    aseprite::AsepritePlugin,
+    ysort::YSortPlugin,
+    camera::CameraPlugin,
+    menu::MenuPlugin,
+    pause::PausePlugin,
+    save::SavePlugin,
+    flags::FlagsPlugin,
+    quest::QuestPlugin,
+    journal::JournalPlugin,
+    replay::ReplayPlugin,
     movement::MovementPlugin, 
+    loading::LoadingPlugin,
     setup::*,
 };
 
 fn main() {
     App::new()
         // Basic Plugins, Setup/Loading Logic
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..default()
+        })
+        // pixel art, never smoothed
+        .insert_resource(ImageSettings::default_nearest())
         .add_plugins(DefaultPlugins)
         .add_plugin(LdtkPlugin)
         .add_plugin(InputManagerPlugin::<Action>::default())
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(16.))
+        // top down, nothing should fall
+        .insert_resource(RapierConfiguration {
+            gravity: Vec2::ZERO,
+            ..default()
+        })
         .add_state(GameState::Loading)
+        .add_plugin(LoadingPlugin)
         .add_plugin(SetupPlugin)
         
         // ldtk configuring
         .register_ldtk_entity::<PlayerBodyPartBundle>("Player_start")
 
-        .register_ldtk_entity::<InteractableBundle>("NPC_spawn")
+        .register_ldtk_entity::<NpcBundle>("NPC_spawn")
+        .register_ldtk_entity::<WaypointBundle>("Waypoint")
+        .register_ldtk_entity::<PuzzleBundle>("Puzzle")
+        .register_ldtk_entity::<InteractableBundle>("Switch")
+        .register_ldtk_entity::<TriggerBundle>("Plate")
+        .register_ldtk_entity::<TriggerBundle>("Lever")
+        .register_ldtk_entity::<DoorObject>("WoodDoor")
         .register_ldtk_entity::<InteractableBundle>("Vendor")
         .register_ldtk_entity::<InteractableBundle>("Gum_Machine")
 
@@ -38,11 +77,26 @@ fn main() {
             ..Default::default()
         })
         
-        .add_system(close_on_esc)
-        
         // Runtime Plugins
         .add_plugin(MovementPlugin)
+        .add_plugin(LimbPlugin)
+        .add_plugin(AnimationPlugin)
+        .add_plugin(AsepritePlugin)
+        .add_plugin(YSortPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(PausePlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(FlagsPlugin)
+        .add_plugin(QuestPlugin)
+        .add_plugin(JournalPlugin)
+        .add_plugin(ReplayPlugin::default())
         .add_plugin(InteractionsPlugin)
+        .add_plugin(DialoguePlugin)
+        .add_plugin(DialogueUiPlugin)
+        .add_plugin(NpcPlugin)
+        .add_plugin(PuzzlePlugin)
+        .add_plugin(TriggerPlugin)
 
         .run();
 
@@ -0,0 +1,510 @@
+use bevy::{
+    app::AppExit,
+    prelude::*,
+};
+use leafwing_input_manager::prelude::*;
+
+use crate::components_events::*;
+use crate::save::{ActiveSlot, SaveDir};
+use crate::settings::*;
+use crate::GameState;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<MainMenu>()
+            .add_event::<NewGame>()
+            .add_event::<ContinueGame>()
+
+            .add_system_set(
+                SystemSet::on_enter(GameState::MainMenu)
+                    .with_system(open_menu)
+            )
+            .add_system_set(menu_systems(GameState::MainMenu))
+            .add_system_set(
+                SystemSet::on_exit(GameState::MainMenu)
+                    .with_system(close_menu)
+            )
+
+            // the pause menu is the same menu on a different page
+            .add_system_set(
+                SystemSet::on_enter(GameState::Pause)
+                    .with_system(open_pause_menu)
+            )
+            .add_system_set(menu_systems(GameState::Pause))
+            .add_system_set(
+                SystemSet::on_exit(GameState::Pause)
+                    .with_system(close_menu)
+            );
+    }
+}
+
+fn menu_systems(state: GameState) -> SystemSet {
+    SystemSet::on_update(state)
+        // before navigation, so the key that starts a rebind is not taken as the new one
+        .with_system(rebind_key)
+        .with_system(
+            navigate_menu
+            .after(rebind_key)
+        )
+        .with_system(
+            spawn_menu
+            .after(navigate_menu)
+        )
+}
+
+const FONT: &str = "fonts/DejaVuSans.ttf";
+const VOLUME_STEP: f32 = 0.1;
+
+const BACKGROUND_COLOR: Color = Color::rgb(0.05, 0.05, 0.08);
+// the paused game stays visible behind it
+const OVERLAY_COLOR: Color = Color::rgba(0.05, 0.05, 0.08, 0.7);
+const TITLE_COLOR: Color = Color::rgb(0.95, 0.8, 0.45);
+const TEXT_COLOR: Color = Color::WHITE;
+const DISABLED_COLOR: Color = Color::rgb(0.4, 0.4, 0.45);
+const BUTTON_COLOR: Color = Color::rgb(0.2, 0.2, 0.28);
+const SELECTED_COLOR: Color = Color::rgb(0.45, 0.3, 0.55);
+
+// MENU DATA
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MenuPage {
+    Main,
+    Pause,
+    Options,
+}
+
+impl Default for MenuPage {
+    fn default() -> Self {
+        MenuPage::Main
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Volume {
+    Master,
+    Characters,
+    Environment,
+    Music,
+}
+
+impl Volume {
+    pub fn get_mut(self, settings: &mut Settings) -> &mut f32 {
+        match self {
+            Volume::Master => &mut settings.master,
+            Volume::Characters => &mut settings.characters,
+            Volume::Environment => &mut settings.environment,
+            Volume::Music => &mut settings.music,
+        }
+    }
+
+    pub fn get(self, settings: &Settings) -> f32 {
+        match self {
+            Volume::Master => settings.master,
+            Volume::Characters => settings.characters,
+            Volume::Environment => settings.environment,
+            Volume::Music => settings.music,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MenuItem {
+    NewGame,
+    Continue,
+    Options,
+    Quit,
+    Resume,
+    Save,
+    QuitToMenu,
+    Volume(Volume),
+    WindowType,
+    // index into Settings::keybinds
+    Keybind(usize),
+    Back,
+}
+
+// RESOURCES
+
+/// Where the player is in the main or pause menu. Left and Right change
+/// volumes and the window type, Confirm on a keybind waits for the next key
+/// pressed.
+#[derive(Clone, Debug, Default)]
+pub struct MainMenu {
+    pub page: MenuPage,
+    // where Back on the options page leads, Main or Pause
+    pub home: MenuPage,
+    pub selected: usize,
+    pub rebinding: Option<usize>,
+    // Continue stays disabled without one
+    pub save_exists: bool,
+}
+
+impl MainMenu {
+    pub fn items(&self, settings: &Settings) -> Vec<MenuItem> {
+        match self.page {
+            MenuPage::Main => vec![MenuItem::NewGame, MenuItem::Continue, MenuItem::Options, MenuItem::Quit],
+            MenuPage::Pause => vec![MenuItem::Resume, MenuItem::Options, MenuItem::Save, MenuItem::QuitToMenu],
+            MenuPage::Options => {
+                let mut items = vec![
+                    MenuItem::Volume(Volume::Master),
+                    MenuItem::Volume(Volume::Characters),
+                    MenuItem::Volume(Volume::Environment),
+                    MenuItem::Volume(Volume::Music),
+                    MenuItem::WindowType,
+                ];
+                items.extend((0..settings.keybinds.len()).map(MenuItem::Keybind));
+                items.push(MenuItem::Back);
+                items
+            }
+        }
+    }
+
+    pub fn enabled(&self, item: MenuItem) -> bool {
+        item != MenuItem::Continue || self.save_exists
+    }
+
+    // moves the selection by step, wrapping around and skipping disabled items
+    pub fn step(&mut self, items: &[MenuItem], step: isize) {
+        let len = items.len() as isize;
+        let mut index = self.selected as isize;
+        for _ in 0..len {
+            index = (index + step).rem_euclid(len);
+            if self.enabled(items[index as usize]) {
+                self.selected = index as usize;
+                return;
+            }
+        }
+    }
+
+    pub fn open_page(&mut self, page: MenuPage, settings: &Settings) {
+        let from = self.page;
+        self.page = page;
+        // coming back lands on Options rather than the top
+        self.selected = self.items(settings)
+            .iter()
+            .position(|item| from == MenuPage::Options && *item == MenuItem::Options)
+            .unwrap_or(0);
+    }
+
+    pub fn label(&self, item: MenuItem, settings: &Settings, slot: ActiveSlot) -> String {
+        match item {
+            MenuItem::NewGame => "New Game".to_string(),
+            MenuItem::Continue => "Continue".to_string(),
+            MenuItem::Options => "Options".to_string(),
+            MenuItem::Quit => "Quit".to_string(),
+            MenuItem::Resume => "Resume".to_string(),
+            MenuItem::Save => format!("Save   < slot {} >", slot.0),
+            MenuItem::QuitToMenu => "Quit to Menu".to_string(),
+            MenuItem::Volume(volume) => {
+                format!("{:?} volume   < {:.0}% >", volume, volume.get(settings) * 100.)
+            }
+            MenuItem::WindowType => format!("Window   < {} >", settings.window_type),
+            MenuItem::Keybind(index) => {
+                let [action, key] = &settings.keybinds[index];
+                if self.rebinding == Some(index) {
+                    format!("{}   press a key, escape to cancel", action)
+                } else {
+                    format!("{}   {}", action, key)
+                }
+            }
+            MenuItem::Back => "Back".to_string(),
+        }
+    }
+}
+
+// MENU COMPONENTS
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct MenuRoot;
+
+// position in MainMenu::items
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct MenuButton(pub usize);
+
+// SYSTEMS
+
+fn open_menu(
+    save_dir: Res<SaveDir>,
+    mut menu: ResMut<MainMenu>,
+) {
+    let save_exists = save_dir.any_exists();
+    *menu = MainMenu {
+        // straight onto Continue when there is something to continue
+        selected: if save_exists { 1 } else { 0 },
+        save_exists,
+        ..default()
+    };
+    info!("Opened main menu");
+}
+
+fn open_pause_menu(
+    mut menu: ResMut<MainMenu>,
+) {
+    *menu = MainMenu {
+        page: MenuPage::Pause,
+        home: MenuPage::Pause,
+        ..default()
+    };
+}
+
+fn close_menu(
+    mut commands: Commands,
+    roots: Query<Entity, With<MenuRoot>>,
+) {
+    for entity in roots.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn rebind_key(
+    keys: Res<Input<KeyCode>>,
+    mut menu: ResMut<MainMenu>,
+    mut settings: ResMut<Settings>,
+    settings_path: Res<SettingsPath>,
+    mut input_maps: Query<&mut InputMap<Action>, With<Player>>,
+) {
+    let index = match menu.rebinding {
+        Some(index) => index,
+        None => return,
+    };
+    let key = match keys.get_just_pressed().next() {
+        Some(key) => *key,
+        None => return,
+    };
+
+    menu.rebinding = None;
+    if key == KeyCode::Escape {
+        return;
+    }
+
+    let name = match keycode_to_string(key) {
+        Some(name) => name,
+        None => {
+            warn!("{:?} can not be bound", key);
+            return;
+        }
+    };
+    info!("Bound {} to {}", settings.keybinds[index][0], name);
+    settings.keybinds[index][1] = name;
+    save_settings(&settings_path, &settings);
+
+    for mut input_map in input_maps.iter_mut() {
+        *input_map = build_input_map(&settings);
+    }
+}
+
+// Keyboard and gamepad go through the player's actions, the mouse selects
+// what it hovers and picks what it clicks.
+#[allow(clippy::too_many_arguments)]
+pub fn navigate_menu(
+    mut menu: ResMut<MainMenu>,
+    mut settings: ResMut<Settings>,
+    settings_path: Res<SettingsPath>,
+    mut windows: ResMut<Windows>,
+    mut app_state: ResMut<State<GameState>>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    buttons: Query<(&MenuButton, &Interaction), Changed<Interaction>>,
+    mut new_game: EventWriter<NewGame>,
+    mut continue_game: EventWriter<ContinueGame>,
+    mut save_game: EventWriter<SaveGame>,
+    mut active_slot: ResMut<ActiveSlot>,
+    mut exit: EventWriter<AppExit>,
+    mut was_rebinding: Local<bool>,
+) {
+    // the key that finished a rebind still counts as its old action this frame
+    let rebinding = menu.rebinding.is_some();
+    if rebinding || *was_rebinding {
+        *was_rebinding = rebinding;
+        return;
+    }
+
+    let action_state = match action_state.get_single() {
+        Ok(action_state) => action_state,
+        Err(_) => return,
+    };
+    let items = menu.items(&settings);
+    let mut picked = action_state.just_pressed(Action::Confirm);
+
+    for (button, interaction) in buttons.iter() {
+        let enabled = items.get(button.0).map_or(false, |item| menu.enabled(*item));
+        match interaction {
+            Interaction::Hovered if enabled && menu.selected != button.0 => menu.selected = button.0,
+            Interaction::Clicked if enabled => {
+                menu.selected = button.0;
+                picked = true;
+            }
+            _ => {}
+        }
+    }
+
+    if action_state.just_pressed(Action::Up) {
+        menu.step(&items, -1);
+    }
+    if action_state.just_pressed(Action::Down) {
+        menu.step(&items, 1);
+    }
+    // Back on the pause page resumes, see toggle_pause
+    if action_state.just_pressed(Action::Back) && menu.page == MenuPage::Options {
+        let home = menu.home;
+        menu.open_page(home, &settings);
+        return;
+    }
+
+    let item = match items.get(menu.selected) {
+        Some(item) => *item,
+        None => return,
+    };
+
+    let mut adjust = 0.;
+    if action_state.just_pressed(Action::Left) {
+        adjust -= 1.;
+    }
+    if action_state.just_pressed(Action::Right) || picked {
+        adjust += 1.;
+    }
+
+    match item {
+        MenuItem::Volume(volume) if adjust != 0. => {
+            let level = volume.get_mut(&mut settings);
+            *level = ((*level + adjust * VOLUME_STEP) * 10.).round().clamp(0., 10.) / 10.;
+            save_settings(&settings_path, &settings);
+        }
+        MenuItem::WindowType if adjust != 0. => {
+            let current = WINDOW_TYPES
+                .iter()
+                .position(|window_type| *window_type == settings.window_type)
+                .unwrap_or(0);
+            let next = (current as isize + adjust as isize).rem_euclid(WINDOW_TYPES.len() as isize);
+            settings.window_type = WINDOW_TYPES[next as usize].to_string();
+            apply_window_type(&settings, &mut windows);
+            save_settings(&settings_path, &settings);
+        }
+        // Left and Right pick the slot, Confirm saves to it
+        MenuItem::Save if !picked && adjust != 0. => active_slot.step(adjust as isize),
+        _ if !picked || !menu.enabled(item) => {}
+
+        MenuItem::NewGame => {
+            info!("Starting a new game");
+            new_game.send(NewGame);
+            app_state.overwrite_set(GameState::Running).unwrap();
+        }
+        MenuItem::Continue => {
+            continue_game.send(ContinueGame);
+            app_state.overwrite_set(GameState::Running).unwrap();
+        }
+        MenuItem::Options => menu.open_page(MenuPage::Options, &settings),
+        MenuItem::Quit => exit.send(AppExit),
+        MenuItem::Resume => {
+            info!("Resumed");
+            app_state.overwrite_pop().unwrap();
+        }
+        MenuItem::Save => save_game.send(SaveGame(active_slot.0)),
+        // drops Running off the stack as well
+        MenuItem::QuitToMenu => app_state.overwrite_replace(GameState::MainMenu).unwrap(),
+        MenuItem::Keybind(index) => menu.rebinding = Some(index),
+        MenuItem::Back => {
+            let home = menu.home;
+            menu.open_page(home, &settings);
+        }
+        MenuItem::Volume(_) | MenuItem::WindowType => {}
+    }
+}
+
+// Rebuilt whenever the menu, the settings or the save slot change. Bevy's UI
+// has y pointing up, so the column is reversed to read top to bottom.
+fn spawn_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    menu: Res<MainMenu>,
+    settings: Res<Settings>,
+    active_slot: Res<ActiveSlot>,
+    roots: Query<Entity, With<MenuRoot>>,
+) {
+    if !menu.is_changed() && !settings.is_changed() && !active_slot.is_changed() {
+        return;
+    }
+
+    for entity in roots.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let font: Handle<Font> = asset_server.load(FONT);
+    let items = menu.items(&settings);
+    let title = match menu.page {
+        MenuPage::Main => "Project Bones",
+        MenuPage::Pause => "Paused",
+        MenuPage::Options => "Options",
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: if menu.home == MenuPage::Pause { OVERLAY_COLOR } else { BACKGROUND_COLOR }.into(),
+            ..default()
+        })
+        .insert(MenuRoot)
+        .with_children(|column| {
+            column.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    title,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 48.,
+                        color: TITLE_COLOR,
+                    },
+                ),
+                style: Style {
+                    margin: UiRect {
+                        bottom: Val::Px(24.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                ..default()
+            });
+
+            for (index, item) in items.iter().enumerate() {
+                let enabled = menu.enabled(*item);
+                let color = if index == menu.selected { SELECTED_COLOR } else { BUTTON_COLOR };
+
+                column
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(420.), Val::Auto),
+                            padding: UiRect::all(Val::Px(6.)),
+                            margin: UiRect {
+                                bottom: Val::Px(4.),
+                                ..default()
+                            },
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        color: color.into(),
+                        ..default()
+                    })
+                    .insert(MenuButton(index))
+                    .with_children(|button| {
+                        button.spawn_bundle(TextBundle {
+                            text: Text::from_section(
+                                menu.label(*item, &settings, *active_slot),
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 22.,
+                                    color: if enabled { TEXT_COLOR } else { DISABLED_COLOR },
+                                },
+                            ),
+                            ..default()
+                        });
+                    });
+            }
+        });
+}
@@ -0,0 +1,265 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+pub const CELL_SIZE: f32 = 16.;
+
+// A* gives up after expanding this many cells
+const MAX_SEARCH: usize = 4096;
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+const NEIGHBOURS: [(IVec2, u32); 8] = [
+    (IVec2::new(1, 0), STRAIGHT_COST),
+    (IVec2::new(-1, 0), STRAIGHT_COST),
+    (IVec2::new(0, 1), STRAIGHT_COST),
+    (IVec2::new(0, -1), STRAIGHT_COST),
+    (IVec2::new(1, 1), DIAGONAL_COST),
+    (IVec2::new(1, -1), DIAGONAL_COST),
+    (IVec2::new(-1, 1), DIAGONAL_COST),
+    (IVec2::new(-1, -1), DIAGONAL_COST),
+];
+
+/// Walkability of the world in 16px cells. Every level shares the same world
+/// grid, so a cell is just its world position divided by `CELL_SIZE`. Only
+/// cells inside a level can be walked, and any non-zero cell of the `Collider`
+/// or `Sp_wall_Collider` IntGrid layers blocks.
+#[derive(Clone, Debug, Default)]
+pub struct NavGrid {
+    // cells covered by each level, min inclusive and max exclusive
+    pub levels: Vec<(IVec2, IVec2)>,
+    pub blocked: HashSet<IVec2>,
+}
+
+impl NavGrid {
+    pub fn world_to_cell(position: Vec2) -> IVec2 {
+        (position / CELL_SIZE).floor().as_ivec2()
+    }
+
+    pub fn cell_to_world(cell: IVec2) -> Vec2 {
+        (cell.as_vec2() + Vec2::splat(0.5)) * CELL_SIZE
+    }
+
+    // a level by its bottom left corner and size in pixels
+    pub fn add_level(&mut self, min: Vec2, size: Vec2) {
+        let bounds = (NavGrid::world_to_cell(min), NavGrid::world_to_cell(min + size));
+        if !self.levels.contains(&bounds) {
+            self.levels.push(bounds);
+        }
+    }
+
+    pub fn in_level(&self, cell: IVec2) -> bool {
+        self.levels
+            .iter()
+            .any(|(min, max)| cell.cmpge(*min).all() && cell.cmplt(*max).all())
+    }
+
+    pub fn is_walkable(&self, cell: IVec2) -> bool {
+        self.in_level(cell) && !self.blocked.contains(&cell)
+    }
+
+    /// A* over the eight neighbours of each cell, never cutting the corner of
+    /// a blocked cell. The path excludes `start` and ends on `goal`.
+    pub fn find_path(&self, start: IVec2, goal: IVec2) -> Option<Vec<IVec2>> {
+        if !self.is_walkable(goal) {
+            return None;
+        }
+        if start == goal {
+            return Some(Vec::new());
+        }
+
+        let mut open = BinaryHeap::new();
+        let mut closed = HashSet::new();
+        let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+        let mut cost: HashMap<IVec2, u32> = HashMap::new();
+
+        cost.insert(start, 0);
+        open.push(Reverse((heuristic(start, goal), start.x, start.y)));
+
+        while let Some(Reverse((_, x, y))) = open.pop() {
+            let cell = IVec2::new(x, y);
+
+            if cell == goal {
+                return Some(reconstruct_path(&came_from, start, goal));
+            }
+            if !closed.insert(cell) {
+                continue;
+            }
+            if closed.len() > MAX_SEARCH {
+                return None;
+            }
+
+            let cell_cost = cost[&cell];
+            for (offset, step_cost) in NEIGHBOURS.iter() {
+                let next = cell + *offset;
+                if !self.is_walkable(next) || closed.contains(&next) {
+                    continue;
+                }
+
+                let diagonal = offset.x != 0 && offset.y != 0;
+                if diagonal && (
+                    !self.is_walkable(cell + IVec2::new(offset.x, 0)) ||
+                    !self.is_walkable(cell + IVec2::new(0, offset.y))
+                ) {
+                    continue;
+                }
+
+                let next_cost = cell_cost + step_cost;
+                if cost.get(&next).map_or(true, |&known| next_cost < known) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, cell);
+                    open.push(Reverse((next_cost + heuristic(next, goal), next.x, next.y)));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// octile distance, exact on an empty grid
+fn heuristic(from: IVec2, to: IVec2) -> u32 {
+    let delta = (to - from).abs();
+    let (long, short) = (delta.x.max(delta.y) as u32, delta.x.min(delta.y) as u32);
+    STRAIGHT_COST * long + (DIAGONAL_COST - STRAIGHT_COST) * short
+}
+
+fn reconstruct_path(came_from: &HashMap<IVec2, IVec2>, start: IVec2, goal: IVec2) -> Vec<IVec2> {
+    let mut path = vec![goal];
+    let mut cell = goal;
+
+    while let Some(previous) = came_from.get(&cell) {
+        if *previous == start {
+            break;
+        }
+        path.push(*previous);
+        cell = *previous;
+    }
+
+    path.reverse();
+    path
+}
+
+// IntGrid cells and levels only have a real GlobalTransform after transform
+// propagation, so the grid is filled in as those first change. Levels are
+// placed by their bottom left corner.
+pub fn build_nav_grid(
+    mut nav_grid: ResMut<NavGrid>,
+    level_assets: Res<Assets<LdtkLevel>>,
+    levels: Query<(&Handle<LdtkLevel>, &GlobalTransform), Changed<GlobalTransform>>,
+    cells: Query<(&IntGridCell, &GlobalTransform), Changed<GlobalTransform>>,
+) {
+    for (handle, transform) in levels.iter() {
+        if let Some(level) = level_assets.get(handle) {
+            let size = Vec2::new(level.level.px_wid as f32, level.level.px_hei as f32);
+            nav_grid.add_level(transform.translation().truncate(), size);
+        }
+    }
+
+    for (cell, transform) in cells.iter() {
+        if cell.value != 0 {
+            nav_grid.blocked.insert(NavGrid::world_to_cell(transform.translation().truncate()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(width: i32, height: i32, blocked: &[(i32, i32)]) -> NavGrid {
+        NavGrid {
+            levels: vec![(IVec2::ZERO, IVec2::new(width, height))],
+            blocked: blocked.iter().map(|(x, y)| IVec2::new(*x, *y)).collect(),
+        }
+    }
+
+    fn cells(cells: &[(i32, i32)]) -> Vec<IVec2> {
+        cells.iter().map(|(x, y)| IVec2::new(*x, *y)).collect()
+    }
+
+    // every step moves to a walkable neighbour without cutting a corner
+    fn assert_walkable_path(grid: &NavGrid, start: IVec2, path: &[IVec2]) {
+        let mut cell = start;
+        for next in path {
+            let offset = *next - cell;
+            assert!(offset.abs().max_element() == 1, "{} to {} is not a step", cell, next);
+            assert!(grid.is_walkable(*next), "{} is not walkable", next);
+            if offset.x != 0 && offset.y != 0 {
+                assert!(grid.is_walkable(cell + IVec2::new(offset.x, 0)), "{} to {} cuts a corner", cell, next);
+                assert!(grid.is_walkable(cell + IVec2::new(0, offset.y)), "{} to {} cuts a corner", cell, next);
+            }
+            cell = *next;
+        }
+    }
+
+    #[test]
+    fn an_open_grid_is_crossed_in_a_straight_line() {
+        let grid = grid(10, 10, &[]);
+        assert_eq!(
+            grid.find_path(IVec2::new(1, 1), IVec2::new(5, 1)),
+            Some(cells(&[(2, 1), (3, 1), (4, 1), (5, 1)])),
+        );
+        assert_eq!(
+            grid.find_path(IVec2::new(1, 1), IVec2::new(3, 3)),
+            Some(cells(&[(2, 2), (3, 3)])),
+        );
+        assert_eq!(grid.find_path(IVec2::new(1, 1), IVec2::new(1, 1)), Some(Vec::new()));
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let wall: Vec<(i32, i32)> = (0..8).map(|y| (3, y)).collect();
+        let grid = grid(10, 10, &wall);
+        let start = IVec2::new(1, 1);
+
+        let path = grid.find_path(start, IVec2::new(5, 1)).unwrap();
+        assert_walkable_path(&grid, start, &path);
+        assert_eq!(path.last(), Some(&IVec2::new(5, 1)));
+        assert!(path.iter().any(|cell| cell.y >= 8));
+    }
+
+    #[test]
+    fn paths_never_cut_a_blocked_corner() {
+        let grid = grid(10, 10, &[(2, 1), (1, 2)]);
+        let start = IVec2::new(1, 1);
+
+        let path = grid.find_path(start, IVec2::new(2, 2)).unwrap();
+        assert_walkable_path(&grid, start, &path);
+        assert!(path.len() > 1);
+    }
+
+    #[test]
+    fn a_walled_in_goal_is_unreachable() {
+        let grid = grid(10, 10, &[(4, 4), (5, 4), (6, 4), (4, 5), (6, 5), (4, 6), (5, 6), (6, 6)]);
+        assert_eq!(grid.find_path(IVec2::new(1, 1), IVec2::new(5, 5)), None);
+        assert_eq!(grid.find_path(IVec2::new(1, 1), IVec2::new(4, 4)), None);
+    }
+
+    #[test]
+    fn cells_outside_the_levels_are_never_walked() {
+        assert!(!grid(10, 10, &[]).is_walkable(IVec2::new(-1, 0)));
+        assert!(!grid(10, 10, &[]).is_walkable(IVec2::new(10, 3)));
+        assert_eq!(grid(10, 10, &[]).find_path(IVec2::new(1, 1), IVec2::new(12, 1)), None);
+
+        // the only way around the wall is through the void below the level
+        let grid = grid(5, 3, &[(2, 0), (2, 1), (2, 2)]);
+        assert_eq!(grid.find_path(IVec2::new(0, 1), IVec2::new(4, 1)), None);
+    }
+
+    #[test]
+    fn touching_levels_connect_and_gaps_between_them_do_not() {
+        let mut grid = NavGrid::default();
+        grid.add_level(Vec2::ZERO, Vec2::new(4. * CELL_SIZE, 4. * CELL_SIZE));
+        grid.add_level(Vec2::new(6. * CELL_SIZE, 0.), Vec2::new(4. * CELL_SIZE, 4. * CELL_SIZE));
+        assert_eq!(grid.levels, vec![(IVec2::ZERO, IVec2::new(4, 4)), (IVec2::new(6, 0), IVec2::new(10, 4))]);
+        assert_eq!(grid.find_path(IVec2::new(1, 1), IVec2::new(8, 1)), None);
+
+        grid.add_level(Vec2::new(4. * CELL_SIZE, 0.), Vec2::new(2. * CELL_SIZE, 4. * CELL_SIZE));
+        let path = grid.find_path(IVec2::new(1, 1), IVec2::new(8, 1)).unwrap();
+        assert_walkable_path(&grid, IVec2::new(1, 1), &path);
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    fs,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::Animator;
+use crate::components_events::*;
+use crate::dialogue::DialogueRunner;
+use crate::navigation::*;
+use crate::pause::PlayTime;
+use crate::replay::GameRng;
+use crate::GameState;
+
+pub struct NpcPlugin;
+
+impl Plugin for NpcPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NpcArchetypes>()
+            .init_resource::<NavGrid>()
+
+            .add_startup_system(load_npc_archetypes)
+            // NPCs and the IntGrid are spawned by LDtk while the game is still Loading
+            .add_system(setup_npcs)
+            .add_system(build_nav_grid)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(apply_npc_schedules)
+                    .with_system(
+                        npc_behavior
+                        .after(apply_npc_schedules)
+                    )
+                    .with_system(face_speaker)
+            );
+    }
+}
+
+pub const NPC_FILE: &str = "assets/data/npcs.json";
+// the source NPCs.png is exported from, its frames are the same cells in the same order
+const NPC_ATLAS: &str = "visual/NPCS.ase#atlas";
+
+// pixels per second
+const NPC_SPEED: f32 = 32.;
+// seconds an NPC waits after reaching the end of a path
+const NPC_PAUSE: f32 = 2.;
+
+// NPC COMPONENTS
+
+/// Mirrors the `NPCtypes` enum in floor_1.ldtk, read off every `NPC_spawn`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Component, Serialize, Deserialize)]
+pub enum NpcKind {
+    Receptionist,
+    #[serde(rename = "Boulder_guy")]
+    BoulderGuy,
+    Vlad,
+    #[serde(rename = "Ms_Fuzz")]
+    MsFuzz,
+    Player,
+    Apprentice1,
+    Wizard,
+    Slime,
+    Goblin,
+    DapperGoblin,
+    Apprentice2,
+    SkeletonGuard,
+}
+
+// LDtk falls back to the first value of an enum, so we do too
+impl Default for NpcKind {
+    fn default() -> Self {
+        NpcKind::Receptionist
+    }
+}
+
+impl NpcKind {
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        match identifier {
+            "Receptionist" => Some(NpcKind::Receptionist),
+            "Boulder_guy" => Some(NpcKind::BoulderGuy),
+            "Vlad" => Some(NpcKind::Vlad),
+            "Ms_Fuzz" => Some(NpcKind::MsFuzz),
+            "Player" => Some(NpcKind::Player),
+            "Apprentice1" => Some(NpcKind::Apprentice1),
+            "Wizard" => Some(NpcKind::Wizard),
+            "Slime" => Some(NpcKind::Slime),
+            "Goblin" => Some(NpcKind::Goblin),
+            "DapperGoblin" => Some(NpcKind::DapperGoblin),
+            "Apprentice2" => Some(NpcKind::Apprentice2),
+            "SkeletonGuard" => Some(NpcKind::SkeletonGuard),
+            _ => None,
+        }
+    }
+}
+
+impl From<EntityInstance> for NpcKind {
+    fn from(entity_instance: EntityInstance) -> Self {
+        let identifier = field_enum(&entity_instance, "NPCtypes");
+        match identifier.as_deref().and_then(NpcKind::from_identifier) {
+            Some(kind) => kind,
+            None => {
+                warn!("NPC_spawn {} has no usable NPCtypes value", entity_instance.iid);
+                NpcKind::default()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Component, Serialize, Deserialize)]
+pub enum NpcBehavior {
+    Idle,
+    // picks random spots within radius pixels of where the NPC spawned
+    Wander { radius: f32 },
+    // walks the Waypoint entities of the route in order, looping
+    Patrol { route: String },
+}
+
+impl Default for NpcBehavior {
+    fn default() -> Self {
+        NpcBehavior::Idle
+    }
+}
+
+/// Switches an NPC's behavior over a repeating cycle of `period` seconds.
+/// An empty schedule leaves the archetype's behavior alone.
+#[derive(Clone, Debug, Default, PartialEq, Component, Serialize, Deserialize)]
+pub struct NpcSchedule {
+    pub period: f32,
+    pub entries: Vec<ScheduleEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    // seconds into the cycle
+    pub at: f32,
+    pub behavior: NpcBehavior,
+}
+
+impl NpcSchedule {
+    pub fn behavior_at(&self, seconds: f32) -> Option<&NpcBehavior> {
+        if self.entries.is_empty() || self.period <= 0. {
+            return None;
+        }
+
+        let seconds = seconds % self.period;
+        self.entries
+            .iter()
+            .filter(|entry| entry.at <= seconds)
+            .last()
+            .or_else(|| self.entries.last())
+            .map(|entry| &entry.behavior)
+    }
+}
+
+#[derive(Clone, Debug, Default, Component)]
+pub struct NpcMovement {
+    pub home: Vec2,
+    // world positions still to walk through, next one first
+    pub path: Vec<Vec2>,
+    pub pause: Timer,
+    pub patrol_index: usize,
+}
+
+// NPC ARCHETYPES
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NpcArchetype {
+    pub kind: NpcKind,
+    // frame of NPCS.ase, the 32px cells of NPCs.png read left to right
+    pub sprite_index: usize,
+    // used when the LDtk Dialogue field is left empty
+    #[serde(default)]
+    pub dialogue: Option<String>,
+    #[serde(default)]
+    pub behavior: NpcBehavior,
+    #[serde(default)]
+    pub schedule: NpcSchedule,
+    // set in animations.json, NPCs without one keep sprite_index
+    #[serde(default)]
+    pub animation: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NpcArchetypes(pub HashMap<NpcKind, NpcArchetype>);
+
+// SYSTEMS
+
+fn load_npc_archetypes(
+    mut archetypes: ResMut<NpcArchetypes>,
+) {
+    let string_data = match fs::read_to_string(NPC_FILE) {
+        Ok(string_data) => string_data,
+        Err(error) => {
+            error!("Could not read {}: {}", NPC_FILE, error);
+            return;
+        }
+    };
+
+    match serde_json::from_str::<Vec<NpcArchetype>>(&string_data) {
+        Ok(list) => {
+            for mut archetype in list {
+                check_behavior(archetype.kind, &mut archetype.behavior);
+                for entry in archetype.schedule.entries.iter_mut() {
+                    check_behavior(archetype.kind, &mut entry.behavior);
+                }
+                archetype.schedule.entries.sort_by(|a, b| a.at.total_cmp(&b.at));
+                archetypes.0.insert(archetype.kind, archetype);
+            }
+        }
+        Err(error) => error!("Could not parse {}: {}", NPC_FILE, error),
+    }
+}
+
+// A wander radius has to be a range to pick spots from, anything else would
+// panic in npc_behavior every time the NPC picks a new goal
+fn check_behavior(kind: NpcKind, behavior: &mut NpcBehavior) {
+    if let NpcBehavior::Wander { radius } = behavior {
+        if !radius.is_finite() || *radius < 0. {
+            error!("{:?} has an invalid wander radius {} in {}, it stays idle", kind, radius, NPC_FILE);
+            *behavior = NpcBehavior::Idle;
+        }
+    }
+}
+
+fn setup_npcs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    archetypes: Res<NpcArchetypes>,
+    mut npcs: Query<(Entity, &NpcKind, &Transform, &mut Interactable, &mut TextureAtlasSprite), Added<NpcKind>>,
+) {
+    for (entity, kind, transform, mut interactable, mut sprite) in npcs.iter_mut() {
+        let archetype = match archetypes.0.get(kind) {
+            Some(archetype) => archetype,
+            None => {
+                warn!("No archetype for {:?}", kind);
+                continue;
+            }
+        };
+
+        sprite.index = archetype.sprite_index;
+
+        if let Some(person) = interactable.person.as_mut() {
+            if person.0.is_none() {
+                person.0 = archetype.dialogue.clone();
+            }
+        }
+
+        commands.entity(entity)
+            .insert(asset_server.load::<TextureAtlas, _>(NPC_ATLAS))
+            .insert(archetype.behavior.clone())
+            .insert(archetype.schedule.clone())
+            .insert(NpcMovement {
+                home: transform.translation.truncate(),
+                ..default()
+            });
+        if let Some(animation) = &archetype.animation {
+            commands.entity(entity).insert(Animator::new(animation));
+        }
+        info!("Made NPC {:?}", kind);
+    }
+}
+
+fn apply_npc_schedules(
+    play_time: Res<PlayTime>,
+    mut npcs: Query<(&NpcSchedule, &mut NpcBehavior)>,
+) {
+    let seconds = play_time.0;
+
+    for (schedule, mut behavior) in npcs.iter_mut() {
+        if let Some(scheduled) = schedule.behavior_at(seconds) {
+            if *behavior != *scheduled {
+                *behavior = scheduled.clone();
+            }
+        }
+    }
+}
+
+pub fn npc_behavior(
+    time: Res<Time>,
+    nav_grid: Res<NavGrid>,
+    runner: Res<DialogueRunner>,
+    mut rng: ResMut<GameRng>,
+    waypoints: Query<(&Waypoint, &GlobalTransform)>,
+    mut npcs: Query<(Entity, &NpcBehavior, &mut NpcMovement, &mut Transform, &mut TextureAtlasSprite)>,
+) {
+    for (entity, behavior, mut movement, mut transform, mut sprite) in npcs.iter_mut() {
+        // NPCs hold still while someone is talking to them
+        if runner.speaker == Some(entity) {
+            continue;
+        }
+
+        let position = transform.translation.truncate();
+
+        if let Some(target) = movement.path.first().copied() {
+            let step = NPC_SPEED * time.delta_seconds();
+            let offset = target - position;
+
+            if offset.length() <= step {
+                transform.translation.x = target.x;
+                transform.translation.y = target.y;
+                movement.path.remove(0);
+                if movement.path.is_empty() {
+                    movement.pause = Timer::from_seconds(NPC_PAUSE, false);
+                }
+            } else {
+                let moved = offset.normalize() * step;
+                transform.translation += moved.extend(0.);
+                if moved.x != 0. {
+                    sprite.flip_x = moved.x < 0.;
+                }
+            }
+            continue;
+        }
+
+        movement.pause.tick(time.delta());
+        if !movement.pause.finished() {
+            continue;
+        }
+
+        let goal = match behavior {
+            NpcBehavior::Idle => None,
+
+            NpcBehavior::Wander { radius } => {
+                let offset = Vec2::new(
+                    rng.0.gen_range(-*radius..=*radius),
+                    rng.0.gen_range(-*radius..=*radius),
+                );
+                Some(movement.home + offset)
+            }
+
+            NpcBehavior::Patrol { route } => {
+                let mut points: Vec<(i32, Vec2)> = waypoints
+                    .iter()
+                    .filter(|(waypoint, _)| waypoint.route == *route)
+                    .map(|(waypoint, transform)| (waypoint.order, transform.translation().truncate()))
+                    .collect();
+                points.sort_by_key(|(order, _)| *order);
+
+                if points.is_empty() {
+                    None
+                } else {
+                    let index = movement.patrol_index % points.len();
+                    movement.patrol_index = index + 1;
+                    Some(points[index].1)
+                }
+            }
+        };
+
+        if let Some(goal) = goal {
+            let path = nav_grid.find_path(NavGrid::world_to_cell(position), NavGrid::world_to_cell(goal));
+            match path {
+                Some(cells) => movement.path = cells.into_iter().map(NavGrid::cell_to_world).collect(),
+                // try somewhere else after a pause
+                None => movement.pause = Timer::from_seconds(NPC_PAUSE, false),
+            }
+        }
+    }
+}
+
+// Turns whoever the player starts talking to towards the current body part
+fn face_speaker(
+    runner: Res<DialogueRunner>,
+    player: Query<&BodyParts, With<Player>>,
+    body_parts: Query<(&Transform, &BodyPart)>,
+    mut npcs: Query<(&Transform, &mut TextureAtlasSprite), (With<NpcKind>, Without<BodyPart>)>,
+) {
+    if !runner.is_changed() {
+        return;
+    }
+
+    let speaker = match runner.speaker {
+        Some(speaker) => speaker,
+        None => return,
+    };
+    let player = player.single();
+
+    for (part_transform, body_part) in body_parts.iter() {
+        if *body_part != player.current() {
+            continue;
+        }
+
+        if let Ok((transform, mut sprite)) = npcs.get_mut(speaker) {
+            sprite.flip_x = part_transform.translation.x < transform.translation.x;
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+use crate::components_events::*;
+use crate::journal::Journal;
+use crate::menu::{MainMenu, MenuPage, navigate_menu};
+use crate::GameState;
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PlayTime>()
+
+            // outside of any state set so it runs once a frame, the push and
+            // the pop would otherwise both see the same key press
+            .add_system(
+                toggle_pause
+                .before(navigate_menu)
+            )
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(tick_play_time)
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::Pause)
+                    .with_system(freeze_physics)
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Pause)
+                    .with_system(thaw_physics)
+            );
+    }
+}
+
+// RESOURCES
+
+/// Seconds spent in `Running`. Stands in for `Time` wherever gameplay has to
+/// stop while paused, like NPC schedules.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PlayTime(pub f32);
+
+// SYSTEMS
+
+// Running only sits under Pause on the stack, popping it skips Running's
+// on_enter setup. With the journal open the key only closes the journal.
+pub fn toggle_pause(
+    menu: Res<MainMenu>,
+    journal: Res<Journal>,
+    mut app_state: ResMut<State<GameState>>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+) {
+    let action_state = match action_state.get_single() {
+        Ok(action_state) => action_state,
+        Err(_) => return,
+    };
+
+    match app_state.current() {
+        GameState::Running if action_state.just_pressed(Action::Pause) && !journal.open => {
+            info!("Paused");
+            app_state.overwrite_push(GameState::Pause).unwrap();
+        }
+        // Back on the options page only leaves the options page
+        GameState::Pause if menu.page == MenuPage::Pause && menu.rebinding.is_none()
+            && (action_state.just_pressed(Action::Pause) || action_state.just_pressed(Action::Back)) => {
+            info!("Resumed");
+            app_state.overwrite_pop().unwrap();
+        }
+        _ => {}
+    }
+}
+
+fn tick_play_time(
+    time: Res<Time>,
+    mut play_time: ResMut<PlayTime>,
+) {
+    play_time.0 += time.delta_seconds();
+}
+
+// Gameplay systems only run on_update(Running) and stop by themselves, but
+// bodies with a velocity would keep sliding.
+fn freeze_physics(
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+fn thaw_physics(
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    rapier_config.physics_pipeline_active = true;
+}
@@ -0,0 +1,395 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+use crate::dialogue::DialogueEffect;
+use crate::flags::FlagValue;
+use crate::GameState;
+
+pub struct PuzzlePlugin;
+
+impl Plugin for PuzzlePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<PuzzleSignal>()
+            .add_event::<PuzzleSolved>()
+            .add_event::<PuzzleFailed>()
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(update_puzzles)
+            );
+    }
+}
+
+// PUZZLE COMPONENTS
+
+/// The controller of a puzzle, placed in LDtk as a `Puzzle` entity. Switches
+/// and plates find it through `id` and feed it `PuzzleInput`s.
+#[derive(Clone, Debug, Default, PartialEq, Component)]
+pub struct Puzzle {
+    pub id: String,
+    pub kind: PuzzleKind,
+    pub solved: bool,
+    // applied once, when the puzzle is solved
+    pub rewards: Vec<DialogueEffect>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PuzzleKind {
+    // switches must be used in this order, a wrong one resets the puzzle but
+    // still counts as the first of a new attempt
+    Sequence { solution: Vec<String>, entered: Vec<String> },
+    // every plate has to be held down at the same time
+    Plates { plates: Vec<String>, pressed: Vec<(String, BodyPart)> },
+    // each switch turns a dial from 0 to 9, named by its index
+    Combination { solution: Vec<u8>, dials: Vec<u8> },
+    // the body parts on the puzzle's plates must weigh at least this much
+    Weight { weight: u32, pressed: Vec<(String, BodyPart)> },
+}
+
+impl Default for PuzzleKind {
+    fn default() -> Self {
+        PuzzleKind::Sequence {
+            solution: Vec::new(),
+            entered: Vec::new(),
+        }
+    }
+}
+
+impl PuzzleKind {
+    /// Builds a puzzle from its LDtk `Kind` and `Solution` fields. Solutions
+    /// are comma separated switch or plate names, digits for a combination,
+    /// or a single number for a weight puzzle. None for an unknown kind, or a
+    /// solution that is empty or can't be read.
+    pub fn from_config(kind: &str, solution: &str) -> Option<Self> {
+        let names: Vec<String> = solution
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+
+        match kind {
+            "Sequence" => Some(PuzzleKind::Sequence {
+                solution: names,
+                entered: Vec::new(),
+            }),
+
+            "Plates" => Some(PuzzleKind::Plates {
+                plates: names,
+                pressed: Vec::new(),
+            }),
+
+            "Combination" => {
+                let solution = solution
+                    .chars()
+                    .filter(|c| !c.is_whitespace() && *c != ',')
+                    .map(|c| c.to_digit(10).map(|digit| digit as u8))
+                    .collect::<Option<Vec<u8>>>()?;
+                let dials = vec![0; solution.len()];
+                Some(PuzzleKind::Combination { solution, dials })
+            }
+
+            "Weight" => Some(PuzzleKind::Weight {
+                weight: solution.trim().parse().ok().filter(|weight| *weight > 0)?,
+                pressed: Vec::new(),
+            }),
+
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PuzzleInput {
+    // a switch named by its LDtk Element field was used
+    Activate(String),
+    Pressed(String, BodyPart),
+    Released(String, BodyPart),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PuzzleOutcome {
+    // the input does not apply to this kind of puzzle, or it is already solved
+    Ignored,
+    Progress,
+    Solved,
+    Failed,
+}
+
+impl Puzzle {
+    pub fn apply(&mut self, input: &PuzzleInput) -> PuzzleOutcome {
+        if self.solved {
+            return PuzzleOutcome::Ignored;
+        }
+
+        let outcome = match (&mut self.kind, input) {
+            (PuzzleKind::Sequence { solution, entered }, PuzzleInput::Activate(element)) => {
+                entered.push(element.clone());
+                if !solution.starts_with(entered) {
+                    entered.clear();
+                    if solution.starts_with(std::slice::from_ref(element)) {
+                        entered.push(element.clone());
+                    }
+                    PuzzleOutcome::Failed
+                } else if entered.len() == solution.len() {
+                    PuzzleOutcome::Solved
+                } else {
+                    PuzzleOutcome::Progress
+                }
+            }
+
+            (PuzzleKind::Combination { solution, dials }, PuzzleInput::Activate(element)) => {
+                match element.parse::<usize>() {
+                    Ok(index) if index < dials.len() => {
+                        dials[index] = (dials[index] + 1) % 10;
+                        if dials == solution {
+                            PuzzleOutcome::Solved
+                        } else {
+                            PuzzleOutcome::Progress
+                        }
+                    }
+                    _ => PuzzleOutcome::Ignored,
+                }
+            }
+
+            (PuzzleKind::Plates { plates, pressed }, PuzzleInput::Pressed(plate, part)) => {
+                pressed.push((plate.clone(), *part));
+                let all_down = plates
+                    .iter()
+                    .all(|plate| pressed.iter().any(|(pressed, _)| pressed == plate));
+                if all_down {
+                    PuzzleOutcome::Solved
+                } else {
+                    PuzzleOutcome::Progress
+                }
+            }
+
+            (PuzzleKind::Weight { weight, pressed }, PuzzleInput::Pressed(plate, part)) => {
+                pressed.push((plate.clone(), *part));
+                let total: u32 = pressed.iter().map(|(_, part)| part.weight()).sum();
+                if total >= *weight {
+                    PuzzleOutcome::Solved
+                } else {
+                    PuzzleOutcome::Progress
+                }
+            }
+
+            (PuzzleKind::Plates { pressed, .. }, PuzzleInput::Released(plate, part)) |
+            (PuzzleKind::Weight { pressed, .. }, PuzzleInput::Released(plate, part)) => {
+                match pressed.iter().position(|(pressed, pressed_part)| pressed == plate && pressed_part == part) {
+                    Some(index) => {
+                        pressed.remove(index);
+                        PuzzleOutcome::Progress
+                    }
+                    None => PuzzleOutcome::Ignored,
+                }
+            }
+
+            _ => PuzzleOutcome::Ignored,
+        };
+
+        if outcome == PuzzleOutcome::Solved {
+            self.solved = true;
+        }
+        outcome
+    }
+}
+
+impl From<EntityInstance> for Puzzle {
+    fn from(entity_instance: EntityInstance) -> Self {
+        let id = field_string(&entity_instance, "Id").unwrap_or_default();
+        let kind = field_enum(&entity_instance, "Kind").unwrap_or_default();
+        let solution = field_string(&entity_instance, "Solution").unwrap_or_default();
+
+        let kind = PuzzleKind::from_config(&kind, &solution).unwrap_or_else(|| {
+            warn!("Puzzle {} has an unreadable Kind or Solution", id);
+            PuzzleKind::default()
+        });
+
+        let mut rewards = Vec::new();
+        if let Some(door) = field_string(&entity_instance, "Reward_door") {
+            rewards.push(DialogueEffect::OpenDoor(door));
+        }
+        if let Some(item) = field_string(&entity_instance, "Reward_item") {
+            rewards.push(DialogueEffect::GiveItem(item));
+        }
+        if let Some(flag) = field_string(&entity_instance, "Reward_flag") {
+            rewards.push(DialogueEffect::SetFlag(flag, FlagValue::Bool(true)));
+        }
+
+        Puzzle {
+            id,
+            kind,
+            solved: false,
+            rewards,
+        }
+    }
+}
+
+// SYSTEMS
+
+// Rewards go out as DialogueEffects, so they open doors, hand out items and
+// set flags exactly the way a conversation would.
+pub fn update_puzzles(
+    mut signals: EventReader<PuzzleSignal>,
+    mut puzzles: Query<&mut Puzzle>,
+    mut solved: EventWriter<PuzzleSolved>,
+    mut failed: EventWriter<PuzzleFailed>,
+    mut effects: EventWriter<DialogueEffect>,
+) {
+    for signal in signals.iter() {
+        for mut puzzle in puzzles.iter_mut() {
+            if puzzle.id != signal.0 {
+                continue;
+            }
+
+            match puzzle.apply(&signal.1) {
+                PuzzleOutcome::Solved => {
+                    info!("Solved puzzle {}!", puzzle.id);
+                    solved.send(PuzzleSolved(puzzle.id.clone()));
+                    for reward in puzzle.rewards.iter() {
+                        effects.send(reward.clone());
+                    }
+                }
+                PuzzleOutcome::Failed => {
+                    info!("Failed puzzle {}", puzzle.id);
+                    failed.send(PuzzleFailed(puzzle.id.clone()));
+                }
+                PuzzleOutcome::Progress | PuzzleOutcome::Ignored => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzle(kind: &str, solution: &str) -> Puzzle {
+        Puzzle {
+            id: "test".to_string(),
+            kind: PuzzleKind::from_config(kind, solution).unwrap(),
+            ..default()
+        }
+    }
+
+    fn activate(element: &str) -> PuzzleInput {
+        PuzzleInput::Activate(element.to_string())
+    }
+
+    fn pressed(plate: &str, part: BodyPart) -> PuzzleInput {
+        PuzzleInput::Pressed(plate.to_string(), part)
+    }
+
+    fn released(plate: &str, part: BodyPart) -> PuzzleInput {
+        PuzzleInput::Released(plate.to_string(), part)
+    }
+
+    #[test]
+    fn a_sequence_is_solved_in_order() {
+        let mut puzzle = puzzle("Sequence", "a, b, c");
+        assert_eq!(puzzle.apply(&activate("a")), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&activate("b")), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&activate("c")), PuzzleOutcome::Solved);
+        assert!(puzzle.solved);
+        assert_eq!(puzzle.apply(&activate("a")), PuzzleOutcome::Ignored);
+    }
+
+    #[test]
+    fn a_wrong_switch_resets_the_sequence() {
+        let mut puzzle = puzzle("Sequence", "a, b");
+        assert_eq!(puzzle.apply(&activate("b")), PuzzleOutcome::Failed);
+        assert_eq!(puzzle.apply(&activate("a")), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&activate("c")), PuzzleOutcome::Failed);
+        assert_eq!(puzzle.kind, PuzzleKind::Sequence {
+            solution: vec!["a".to_string(), "b".to_string()],
+            entered: Vec::new(),
+        });
+    }
+
+    #[test]
+    fn a_wrong_switch_that_starts_the_sequence_begins_a_new_attempt() {
+        let mut puzzle = puzzle("Sequence", "a, b");
+        assert_eq!(puzzle.apply(&activate("a")), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&activate("a")), PuzzleOutcome::Failed);
+        assert_eq!(puzzle.apply(&activate("b")), PuzzleOutcome::Solved);
+    }
+
+    #[test]
+    fn a_combination_is_solved_when_every_dial_matches() {
+        let mut puzzle = puzzle("Combination", "1,2");
+        assert_eq!(puzzle.apply(&activate("0")), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&activate("1")), PuzzleOutcome::Progress);
+        // dials that don't exist are left alone
+        assert_eq!(puzzle.apply(&activate("2")), PuzzleOutcome::Ignored);
+        assert_eq!(puzzle.apply(&activate("lever")), PuzzleOutcome::Ignored);
+        assert_eq!(puzzle.apply(&activate("1")), PuzzleOutcome::Solved);
+    }
+
+    #[test]
+    fn combination_dials_wrap_past_nine() {
+        let mut puzzle = puzzle("Combination", "0 1");
+        for _ in 0..10 {
+            assert_eq!(puzzle.apply(&activate("0")), PuzzleOutcome::Progress);
+        }
+        assert_eq!(puzzle.apply(&activate("1")), PuzzleOutcome::Solved);
+    }
+
+    #[test]
+    fn plates_must_all_be_held_at_once() {
+        let mut puzzle = puzzle("Plates", "left, right");
+        assert_eq!(puzzle.apply(&pressed("left", BodyPart::Body)), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&released("left", BodyPart::Body)), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&pressed("right", BodyPart::Head)), PuzzleOutcome::Progress);
+        // not on the plate, nothing to release
+        assert_eq!(puzzle.apply(&released("left", BodyPart::Head)), PuzzleOutcome::Ignored);
+        assert_eq!(puzzle.apply(&pressed("left", BodyPart::LeftArm)), PuzzleOutcome::Solved);
+    }
+
+    #[test]
+    fn weight_counts_every_part_on_the_plates() {
+        // the body weighs 6, a leg 3 and an arm 1
+        let mut puzzle = puzzle("Weight", "9");
+        assert_eq!(puzzle.apply(&pressed("scale", BodyPart::Body)), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&pressed("scale", BodyPart::LeftArm)), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&released("scale", BodyPart::LeftArm)), PuzzleOutcome::Progress);
+        assert_eq!(puzzle.apply(&pressed("other", BodyPart::LeftLeg)), PuzzleOutcome::Solved);
+    }
+
+    #[test]
+    fn inputs_for_other_kinds_are_ignored() {
+        let mut sequence = puzzle("Sequence", "a");
+        assert_eq!(sequence.apply(&pressed("a", BodyPart::Body)), PuzzleOutcome::Ignored);
+        let mut weight = puzzle("Weight", "1");
+        assert_eq!(weight.apply(&activate("a")), PuzzleOutcome::Ignored);
+    }
+
+    #[test]
+    fn bad_configs_are_rejected() {
+        assert_eq!(PuzzleKind::from_config("Maze", "a"), None);
+        assert_eq!(PuzzleKind::from_config("Sequence", ""), None);
+        assert_eq!(PuzzleKind::from_config("Plates", " , "), None);
+        assert_eq!(PuzzleKind::from_config("Combination", "12a"), None);
+        assert_eq!(PuzzleKind::from_config("Combination", ""), None);
+        assert_eq!(PuzzleKind::from_config("Weight", "heavy"), None);
+        assert_eq!(PuzzleKind::from_config("Weight", "-3"), None);
+        assert_eq!(PuzzleKind::from_config("Weight", "0"), None);
+    }
+
+    #[test]
+    fn configs_read_their_solution() {
+        assert_eq!(PuzzleKind::from_config("Combination", "3, 0,7"), Some(PuzzleKind::Combination {
+            solution: vec![3, 0, 7],
+            dials: vec![0, 0, 0],
+        }));
+        assert_eq!(PuzzleKind::from_config("Weight", " 12 "), Some(PuzzleKind::Weight {
+            weight: 12,
+            pressed: Vec::new(),
+        }));
+    }
+}
@@ -0,0 +1,457 @@
+use std::fs;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+use crate::flags::{Condition, GameFlags};
+use crate::puzzle::Puzzle;
+use crate::save::CurrentRoom;
+use crate::setup::LEVEL_IIDS;
+use crate::GameState;
+
+pub struct QuestPlugin;
+
+impl Plugin for QuestPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<QuestBook>()
+            .init_resource::<QuestLog>()
+            .add_event::<QuestStarted>()
+            .add_event::<QuestStepCompleted>()
+            .add_event::<QuestCompleted>()
+
+            .add_startup_system(load_quests)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(update_quests)
+            );
+    }
+}
+
+pub const QUEST_FILE: &str = "assets/data/quests.json";
+
+// QUEST DATA
+
+/// What finishes a step. Written in quests.json as `{ "item": "Gumball" }`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    // a condition over flags and the inventory, like an LDtk Condition field
+    Condition(Condition),
+    // carried, however it got there
+    Item(String),
+    // the LDtk identifier of a level, walked into
+    Room(String),
+    // the id of a Puzzle entity, solved
+    Puzzle(String),
+    // the LDtk iid of a door, used or opened
+    Door(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QuestStep {
+    // shown in the journal
+    pub description: String,
+    pub objective: Objective,
+}
+
+/// A quest as written in quests.json. Steps are done in order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quest {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    // the quest starts as soon as this holds, or right away without one
+    #[serde(default)]
+    pub start: Option<Condition>,
+    pub steps: Vec<QuestStep>,
+}
+
+/// How far a started quest is. Saves keep these.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuestProgress {
+    pub id: String,
+    // steps done so far, the index of the current one
+    pub step: usize,
+    pub completed: bool,
+}
+
+/// What happened this frame and how the world stands, gathered once and
+/// checked against the current step of every active quest.
+#[derive(Clone, Debug, Default)]
+pub struct QuestFacts {
+    // level identifiers, the current one and any entered this frame
+    pub rooms: Vec<String>,
+    // puzzle ids
+    pub solved: Vec<String>,
+    // door iids, used this frame or standing open
+    pub doors: Vec<String>,
+}
+
+impl Objective {
+    pub fn is_met(&self, facts: &QuestFacts, flags: &GameFlags, inventory: &Inventory) -> bool {
+        match self {
+            Objective::Condition(condition) => condition.evaluate(flags, inventory),
+            Objective::Item(id) => inventory.contains(id),
+            Objective::Room(room) => facts.rooms.contains(room),
+            Objective::Puzzle(id) => facts.solved.contains(id),
+            Objective::Door(iid) => facts.doors.contains(iid),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuestUpdate {
+    Started(String),
+    // quest id and the index of the step
+    StepCompleted(String, usize),
+    Completed(String),
+}
+
+// RESOURCES
+
+/// Every quest from quests.json, in file order.
+#[derive(Clone, Debug, Default)]
+pub struct QuestBook(pub Vec<Quest>);
+
+impl QuestBook {
+    pub fn get(&self, id: &str) -> Option<&Quest> {
+        self.0.iter().find(|quest| quest.id == id)
+    }
+}
+
+/// The quests the player has started, in the order they started.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuestLog(pub Vec<QuestProgress>);
+
+impl QuestLog {
+    pub fn get(&self, id: &str) -> Option<&QuestProgress> {
+        self.0.iter().find(|progress| progress.id == id)
+    }
+
+    pub fn is_started(&self, id: &str) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn is_completed(&self, id: &str) -> bool {
+        self.get(id).map_or(false, |progress| progress.completed)
+    }
+
+    pub fn active(&self) -> impl Iterator<Item = &QuestProgress> {
+        self.0.iter().filter(|progress| !progress.completed)
+    }
+
+    pub fn completed(&self) -> impl Iterator<Item = &QuestProgress> {
+        self.0.iter().filter(|progress| progress.completed)
+    }
+
+    /// Starts the quests whose start condition holds and moves every active
+    /// quest past each step that is done. Several steps can finish at once,
+    /// a quest started this frame included.
+    pub fn update(
+        &mut self,
+        book: &QuestBook,
+        facts: &QuestFacts,
+        flags: &GameFlags,
+        inventory: &Inventory,
+    ) -> Vec<QuestUpdate> {
+        let mut updates = Vec::new();
+
+        for quest in book.0.iter() {
+            let ready = quest.start
+                .as_ref()
+                .map_or(true, |condition| condition.evaluate(flags, inventory));
+            if ready && !self.is_started(&quest.id) {
+                self.0.push(QuestProgress {
+                    id: quest.id.clone(),
+                    step: 0,
+                    completed: false,
+                });
+                updates.push(QuestUpdate::Started(quest.id.clone()));
+            }
+        }
+
+        for progress in self.0.iter_mut().filter(|progress| !progress.completed) {
+            // a save can outlive a quest that was taken out of quests.json
+            let quest = match book.get(&progress.id) {
+                Some(quest) => quest,
+                None => continue,
+            };
+
+            while let Some(step) = quest.steps.get(progress.step) {
+                if !step.objective.is_met(facts, flags, inventory) {
+                    break;
+                }
+                updates.push(QuestUpdate::StepCompleted(quest.id.clone(), progress.step));
+                progress.step += 1;
+            }
+
+            if progress.step >= quest.steps.len() {
+                progress.completed = true;
+                updates.push(QuestUpdate::Completed(quest.id.clone()));
+            }
+        }
+
+        updates
+    }
+}
+
+// SYSTEMS
+
+fn load_quests(
+    mut book: ResMut<QuestBook>,
+) {
+    let string_data = match fs::read_to_string(QUEST_FILE) {
+        Ok(string_data) => string_data,
+        Err(error) => {
+            error!("Could not read {}: {}", QUEST_FILE, error);
+            return;
+        }
+    };
+
+    match serde_json::from_str::<Vec<Quest>>(&string_data) {
+        Ok(quests) => book.0 = quests,
+        Err(error) => error!("Could not parse {}: {}", QUEST_FILE, error),
+    }
+}
+
+// Event objectives only count while their step is the current one, but
+// rooms, puzzles and doors are also checked against the world so a step that
+// was already done before it came up finishes straight away. Items only count
+// once they are in the inventory, a full one leaves them where they were.
+pub fn update_quests(
+    mut door_interactions: EventReader<DoorInteraction>,
+    mut room_changes: EventReader<RoomChange>,
+    mut puzzle_solved: EventReader<PuzzleSolved>,
+    book: Res<QuestBook>,
+    flags: Res<GameFlags>,
+    room: Res<CurrentRoom>,
+    level_assets: Res<Assets<LdtkLevel>>,
+    levels: Query<&Handle<LdtkLevel>>,
+    inventory: Query<&Inventory>,
+    interactables: Query<(&EntityInstance, &Interactable)>,
+    puzzles: Query<&Puzzle>,
+    mut log: ResMut<QuestLog>,
+    mut started: EventWriter<QuestStarted>,
+    mut step_completed: EventWriter<QuestStepCompleted>,
+    mut completed: EventWriter<QuestCompleted>,
+) {
+    let inventory = match inventory.get_single() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+
+    let room_identifier = |index: usize| {
+        let iid = LEVEL_IIDS.get(index)?;
+        levels
+            .iter()
+            .filter_map(|handle| level_assets.get(handle))
+            .find(|level| level.level.iid == *iid)
+            .map(|level| level.level.identifier.clone())
+    };
+
+    let mut facts = QuestFacts::default();
+    facts.rooms.extend(
+        room_changes
+            .iter()
+            .map(|change| change.0)
+            .chain(room.0)
+            .filter_map(room_identifier)
+    );
+    facts.solved.extend(puzzle_solved.iter().map(|solved| solved.0.clone()));
+    facts.solved.extend(
+        puzzles
+            .iter()
+            .filter(|puzzle| puzzle.solved)
+            .map(|puzzle| puzzle.id.clone())
+    );
+    for interaction in door_interactions.iter() {
+        if let Ok((instance, _)) = interactables.get(interaction.1) {
+            facts.doors.push(instance.iid.clone());
+        }
+    }
+    for (instance, interactable) in interactables.iter() {
+        if interactable.door.as_ref().map_or(false, |door| !door.closed) {
+            facts.doors.push(instance.iid.clone());
+        }
+    }
+
+    // worked out on a copy so the log is only marked changed when a quest
+    // moved, the journal rebuilds whenever it is
+    let mut preview = log.clone();
+    let updates = preview.update(&book, &facts, &flags, inventory);
+    if updates.is_empty() {
+        return;
+    }
+    *log = preview;
+
+    for update in updates {
+        match update {
+            QuestUpdate::Started(id) => {
+                info!("Started quest {}", id);
+                started.send(QuestStarted(id));
+            }
+            QuestUpdate::StepCompleted(id, step) => {
+                info!("Finished step {} of quest {}", step, id);
+                step_completed.send(QuestStepCompleted(id, step));
+            }
+            QuestUpdate::Completed(id) => {
+                info!("Completed quest {}", id);
+                completed.send(QuestCompleted(id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(objective: Objective) -> QuestStep {
+        QuestStep {
+            description: String::new(),
+            objective,
+        }
+    }
+
+    fn quest(id: &str, start: Option<&str>, steps: Vec<Objective>) -> Quest {
+        Quest {
+            id: id.to_string(),
+            title: id.to_string(),
+            description: String::new(),
+            start: start.map(|source| Condition::parse(source).unwrap()),
+            steps: steps.into_iter().map(step).collect(),
+        }
+    }
+
+    fn started(id: &str) -> QuestUpdate {
+        QuestUpdate::Started(id.to_string())
+    }
+
+    fn step_completed(id: &str, step: usize) -> QuestUpdate {
+        QuestUpdate::StepCompleted(id.to_string(), step)
+    }
+
+    fn completed(id: &str) -> QuestUpdate {
+        QuestUpdate::Completed(id.to_string())
+    }
+
+    #[test]
+    fn a_quest_waits_for_its_start_condition() {
+        let book = QuestBook(vec![quest(
+            "check_in",
+            Some("flag met_receptionist"),
+            vec![Objective::Room("Office".to_string())],
+        )]);
+        let mut log = QuestLog::default();
+        let mut flags = GameFlags::default();
+        let inventory = Inventory::default();
+
+        assert!(log.update(&book, &QuestFacts::default(), &flags, &inventory).is_empty());
+        assert!(!log.is_started("check_in"));
+
+        flags.set("met_receptionist", true);
+        assert_eq!(log.update(&book, &QuestFacts::default(), &flags, &inventory), vec![started("check_in")]);
+        assert_eq!(log.get("check_in").map(|progress| progress.step), Some(0));
+
+        // started once only
+        assert!(log.update(&book, &QuestFacts::default(), &flags, &inventory).is_empty());
+    }
+
+    #[test]
+    fn several_steps_can_finish_in_one_frame() {
+        let book = QuestBook(vec![quest(
+            "errand",
+            Some("flag asked"),
+            vec![
+                Objective::Item("Gumball".to_string()),
+                Objective::Door("office_door".to_string()),
+                Objective::Puzzle("levers".to_string()),
+            ],
+        )]);
+        let mut flags = GameFlags::default();
+        flags.set("asked", true);
+        let mut log = QuestLog::default();
+        log.update(&book, &QuestFacts::default(), &flags, &Inventory::default());
+
+        let mut inventory = Inventory::default();
+        inventory.give(Item { id: "Gumball".to_string() });
+        let facts = QuestFacts {
+            doors: vec!["office_door".to_string()],
+            ..default()
+        };
+        // stops at the puzzle, which is not solved yet
+        assert_eq!(
+            log.update(&book, &facts, &flags, &inventory),
+            vec![step_completed("errand", 0), step_completed("errand", 1)]
+        );
+        assert_eq!(log.get("errand").map(|progress| progress.step), Some(2));
+        assert!(!log.is_completed("errand"));
+    }
+
+    #[test]
+    fn a_quest_can_start_and_finish_in_the_same_frame() {
+        let book = QuestBook(vec![quest(
+            "tour",
+            None,
+            vec![
+                Objective::Room("Lobby".to_string()),
+                Objective::Condition(Condition::parse("flag waved").unwrap()),
+            ],
+        )]);
+        let mut flags = GameFlags::default();
+        flags.set("waved", true);
+        let facts = QuestFacts {
+            rooms: vec!["Lobby".to_string()],
+            ..default()
+        };
+        let mut log = QuestLog::default();
+
+        assert_eq!(
+            log.update(&book, &facts, &flags, &Inventory::default()),
+            vec![started("tour"), step_completed("tour", 0), step_completed("tour", 1), completed("tour")]
+        );
+        assert!(log.is_completed("tour"));
+        assert_eq!(log.active().count(), 0);
+    }
+
+    #[test]
+    fn an_item_only_counts_once_it_is_carried() {
+        let book = QuestBook(vec![quest("snack", None, vec![Objective::Item("Gumball".to_string())])]);
+        let flags = GameFlags::default();
+        let mut log = QuestLog::default();
+
+        assert_eq!(
+            log.update(&book, &QuestFacts::default(), &flags, &Inventory::default()),
+            vec![started("snack")]
+        );
+
+        let mut inventory = Inventory::default();
+        inventory.give(Item { id: "Gumball".to_string() });
+        assert_eq!(
+            log.update(&book, &QuestFacts::default(), &flags, &inventory),
+            vec![step_completed("snack", 0), completed("snack")]
+        );
+    }
+
+    #[test]
+    fn progress_on_a_quest_that_was_taken_out_is_kept_as_it_was() {
+        let book = QuestBook(vec![quest("tour", None, vec![Objective::Room("Lobby".to_string())])]);
+        let removed = QuestProgress {
+            id: "cut_content".to_string(),
+            step: 1,
+            completed: false,
+        };
+        let mut log = QuestLog(vec![removed.clone()]);
+
+        assert_eq!(
+            log.update(&book, &QuestFacts::default(), &GameFlags::default(), &Inventory::default()),
+            vec![started("tour")]
+        );
+        assert_eq!(log.get("cut_content"), Some(&removed));
+        assert_eq!(log.active().count(), 2);
+    }
+}
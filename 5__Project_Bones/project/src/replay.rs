@@ -0,0 +1,568 @@
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use bevy::{
+    app::AppExit,
+    prelude::*,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use leafwing_input_manager::{axislike::DualAxisData, prelude::*, Actionlike};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Cursor;
+use crate::components_events::*;
+use crate::GameState;
+
+/// Records or replays in the given mode. The default reads it off the
+/// command line, see `ReplayMode`.
+pub struct ReplayPlugin {
+    pub mode: ReplayMode,
+}
+
+impl Default for ReplayPlugin {
+    fn default() -> Self {
+        ReplayPlugin {
+            mode: ReplayMode::from_args(env::args().skip(1)),
+        }
+    }
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(self.mode.clone())
+            .init_resource::<ReplayState>()
+            .init_resource::<RecordingFile>()
+            .init_resource::<FixedClock>()
+            .init_resource::<GameRng>()
+
+            .add_startup_system(start_replay)
+
+            // replaces the real frame time before anything reads it
+            .add_stage_after(CoreStage::First, FIXED_CLOCK, SystemStage::single_threaded())
+            .add_system_to_stage(FIXED_CLOCK, fixed_clock)
+            // after leafwing and the cursor have been updated for the frame,
+            // before any gameplay system looks at them
+            .add_stage_after(CoreStage::PreUpdate, RECORDED_INPUT, SystemStage::single_threaded())
+            .add_system_to_stage(RECORDED_INPUT, record_or_replay_input)
+
+            .add_system_to_stage(CoreStage::Last, finish_recording)
+            .add_system_to_stage(CoreStage::Last, finish_replay);
+    }
+}
+
+const FIXED_CLOCK: &str = "fixed_clock";
+const RECORDED_INPUT: &str = "recorded_input";
+
+/// Bumped whenever `Recording` changes shape.
+pub const RECORDING_VERSION: u32 = 2;
+/// Seconds per frame while recording or replaying, whatever the real frame
+/// rate is.
+pub const TIMESTEP: f32 = 1. / 60.;
+
+const MOUSE_BUTTONS: [MouseButton; 3] = [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+
+// RECORDING DATA
+
+/// Everything the player did in one frame. Empty fields are left out of the
+/// file, most frames are just a few held actions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TickInput {
+    // bit n is set while the action with index n is held
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub actions: u64,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub aim: Option<[f32; 2]>,
+    // in world space, like `Cursor`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub cursor: Option<[f32; 2]>,
+    // bit n is set while MOUSE_BUTTONS[n] is held
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mouse: u8,
+}
+
+impl TickInput {
+    pub fn capture(action_state: &ActionState<Action>, cursor: &Cursor, mouse: &Input<MouseButton>) -> Self {
+        TickInput {
+            actions: Action::variants()
+                .filter(|action| action_state.pressed(*action))
+                .fold(0, |bits, action| bits | (1 << action.index())),
+            aim: action_state.axis_pair(Action::Aim).map(|pair| pair.xy().to_array()),
+            cursor: cursor.0.map(|position| position.to_array()),
+            mouse: MOUSE_BUTTONS
+                .iter()
+                .enumerate()
+                .filter(|(_, button)| mouse.pressed(**button))
+                .fold(0, |bits, (bit, _)| bits | (1 << bit)),
+        }
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.actions & (1 << action.index()) != 0
+    }
+
+    fn mouse_pressed(&self, bit: usize) -> bool {
+        self.mouse & (1 << bit) != 0
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// A recorded session, from the first frame of a new game to the frame the
+/// game was closed or crashed. Written as it is played, see `RecordingWriter`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    pub version: u32,
+    pub seed: u64,
+    pub timestep: f32,
+    pub ticks: Vec<TickInput>,
+    // of the body and limbs after the last tick, see `checksum`. None when
+    // the game never got to quit, there is nothing to check a replay against.
+    pub checksum: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordingHeader {
+    version: u32,
+    seed: u64,
+    timestep: f32,
+}
+
+// every line after the header, the checksum only comes last
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RecordingLine {
+    End { checksum: u64 },
+    Tick(TickInput),
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "{}", error),
+            ReplayError::Json(error) => write!(f, "{}", error),
+            ReplayError::Version(version) => {
+                write!(f, "recording is version {}, this build reads version {}", version, RECORDING_VERSION)
+            }
+        }
+    }
+}
+
+/// Gzipped JSON lines, a header, one line per tick and the checksum on
+/// quitting. Every line is flushed as it is written, so a run that crashes
+/// still leaves a file with every tick up to the crash.
+pub struct RecordingWriter {
+    encoder: GzEncoder<BufWriter<File>>,
+}
+
+impl RecordingWriter {
+    // the ticks and checksum of `recording` are left for `tick` and `finish`
+    pub fn create(path: &Path, recording: &Recording) -> Result<Self, ReplayError> {
+        let file = File::create(path).map_err(ReplayError::Io)?;
+        let mut writer = RecordingWriter {
+            encoder: GzEncoder::new(BufWriter::new(file), Compression::default()),
+        };
+        writer.line(&RecordingHeader {
+            version: recording.version,
+            seed: recording.seed,
+            timestep: recording.timestep,
+        })?;
+        Ok(writer)
+    }
+
+    pub fn tick(&mut self, tick: &TickInput) -> Result<(), ReplayError> {
+        self.line(&RecordingLine::Tick(tick.clone()))
+    }
+
+    pub fn finish(mut self, checksum: Option<u64>) -> Result<(), ReplayError> {
+        if let Some(checksum) = checksum {
+            self.line(&RecordingLine::End { checksum })?;
+        }
+        self.encoder.finish().and_then(|mut writer| writer.flush()).map_err(ReplayError::Io)
+    }
+
+    fn line(&mut self, value: &impl Serialize) -> Result<(), ReplayError> {
+        serde_json::to_writer(&mut self.encoder, value).map_err(ReplayError::Json)?;
+        self.encoder.write_all(b"\n").map_err(ReplayError::Io)?;
+        self.encoder.flush().map_err(ReplayError::Io)
+    }
+}
+
+pub fn write_recording(path: &Path, recording: &Recording) -> Result<(), ReplayError> {
+    let mut writer = RecordingWriter::create(path, recording)?;
+    for tick in recording.ticks.iter() {
+        writer.tick(tick)?;
+    }
+    writer.finish(recording.checksum)
+}
+
+// A file that ends early is from a run that crashed, it is read up to there
+pub fn read_recording(path: &Path) -> Result<Recording, ReplayError> {
+    let file = File::open(path).map_err(ReplayError::Io)?;
+    let mut lines = BufReader::new(GzDecoder::new(BufReader::new(file))).lines();
+
+    let header = lines
+        .next()
+        .unwrap_or_else(|| Err(ErrorKind::UnexpectedEof.into()))
+        .map_err(ReplayError::Io)?;
+    let header: RecordingHeader = serde_json::from_str(&header).map_err(ReplayError::Json)?;
+    if header.version != RECORDING_VERSION {
+        return Err(ReplayError::Version(header.version));
+    }
+
+    let mut recording = Recording {
+        version: header.version,
+        seed: header.seed,
+        timestep: header.timestep,
+        ..default()
+    };
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(ReplayError::Io(error)),
+        };
+        match serde_json::from_str(&line).map_err(ReplayError::Json)? {
+            RecordingLine::Tick(tick) => recording.ticks.push(tick),
+            RecordingLine::End { checksum } => {
+                recording.checksum = Some(checksum);
+                break;
+            }
+        }
+    }
+    Ok(recording)
+}
+
+/// FNV-1a over the translation and rotation of every body part, the body
+/// and detached limbs alike. Sorted first so query order doesn't matter.
+pub fn checksum(parts: impl Iterator<Item = (BodyPart, Transform)>) -> u64 {
+    let mut bits: Vec<(u8, [u32; 7])> = parts
+        .map(|(part, transform)| {
+            let [x, y, z] = transform.translation.to_array();
+            let [i, j, k, w] = transform.rotation.to_array();
+            (part as u8, [x, y, z, i, j, k, w].map(f32::to_bits))
+        })
+        .collect();
+    bits.sort();
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (part, floats) in bits {
+        let bytes = std::iter::once(part).chain(floats.iter().flat_map(|float| float.to_le_bytes()));
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// RESOURCES
+
+/// Picked from the command line. `--record file` plays normally and writes
+/// every input to the file as it goes, `--seed n` fixes its seed.
+/// `--replay file` plays a recording back and checks it ends the same way.
+/// Both skip the main menu into a new game and run on a fixed timestep.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayMode {
+    Off,
+    Record { path: PathBuf, seed: u64 },
+    Replay(PathBuf),
+}
+
+impl ReplayMode {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        let args: Vec<String> = args.collect();
+        let value = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|index| args.get(index + 1))
+        };
+
+        if let Some(path) = value("--replay") {
+            ReplayMode::Replay(PathBuf::from(path))
+        } else if let Some(path) = value("--record") {
+            let seed = value("--seed")
+                .and_then(|seed| seed.parse().ok())
+                .unwrap_or_else(rand::random);
+            ReplayMode::Record { path: PathBuf::from(path), seed }
+        } else {
+            ReplayMode::Off
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        *self != ReplayMode::Off
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayState {
+    pub recording: Recording,
+    // set on the frame the new game starts, the first recorded tick
+    pub started: bool,
+    // the next tick to replay
+    pub next: usize,
+    pub finished: bool,
+}
+
+/// Where a recording is being written, None when not recording or when the
+/// file could not be created.
+#[derive(Default)]
+pub struct RecordingFile(pub Option<RecordingWriter>);
+
+/// Every random choice the game makes comes from here, so a recording can
+/// seed it and its replay makes the same choices.
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng(StdRng::from_entropy())
+    }
+}
+
+/// The `Time` the game sees while recording or replaying, one `TIMESTEP`
+/// further along every frame.
+#[derive(Clone, Debug)]
+pub struct FixedClock {
+    pub time: Time,
+    pub next: Instant,
+}
+
+impl Default for FixedClock {
+    fn default() -> Self {
+        FixedClock {
+            time: Time::default(),
+            next: Instant::now(),
+        }
+    }
+}
+
+// SYSTEMS
+
+fn start_replay(
+    mode: Res<ReplayMode>,
+    mut state: ResMut<ReplayState>,
+    mut file: ResMut<RecordingFile>,
+    mut rng: ResMut<GameRng>,
+    mut exit: EventWriter<AppExit>,
+) {
+    match &*mode {
+        ReplayMode::Off => {}
+        ReplayMode::Record { path, seed } => {
+            info!("Recording input to {} with seed {}", path.display(), seed);
+            rng.0 = StdRng::seed_from_u64(*seed);
+            state.recording = Recording {
+                version: RECORDING_VERSION,
+                seed: *seed,
+                timestep: TIMESTEP,
+                ..default()
+            };
+            match RecordingWriter::create(path, &state.recording) {
+                Ok(writer) => file.0 = Some(writer),
+                Err(error) => error!("Could not create recording {}: {}", path.display(), error),
+            }
+        }
+        ReplayMode::Replay(path) => match read_recording(path) {
+            Ok(recording) => {
+                info!("Replaying {} frames from {}", recording.ticks.len(), path.display());
+                if recording.timestep != TIMESTEP {
+                    warn!("{} was recorded at a timestep of {}s, this build uses {}s", path.display(), recording.timestep, TIMESTEP);
+                }
+                rng.0 = StdRng::seed_from_u64(recording.seed);
+                state.recording = recording;
+            }
+            Err(error) => {
+                error!("Could not read recording {}: {}", path.display(), error);
+                exit.send(AppExit);
+            }
+        },
+    }
+}
+
+fn fixed_clock(
+    mode: Res<ReplayMode>,
+    mut clock: ResMut<FixedClock>,
+    mut time: ResMut<Time>,
+) {
+    if !mode.is_on() {
+        return;
+    }
+
+    let next = clock.next;
+    clock.time.update_with_instant(next);
+    clock.next += Duration::from_secs_f32(TIMESTEP);
+    *time = clock.time.clone();
+}
+
+// The new game starts on the first frame the main menu is up, and that frame
+// is the first tick. A replay takes the player's InputMap away, so leafwing
+// only ticks the ActionState and the keyboard can't interfere.
+fn record_or_replay_input(
+    mut commands: Commands,
+    mode: Res<ReplayMode>,
+    mut state: ResMut<ReplayState>,
+    mut file: ResMut<RecordingFile>,
+    mut app_state: ResMut<State<GameState>>,
+    mut cursor: ResMut<Cursor>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    mut player: Query<(Entity, &mut ActionState<Action>, Option<&InputMap<Action>>), With<Player>>,
+) {
+    if !mode.is_on() || state.finished {
+        return;
+    }
+    let (entity, mut action_state, input_map) = match player.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    if let (ReplayMode::Replay(_), Some(_)) = (&*mode, input_map) {
+        commands.entity(entity).remove::<InputMap<Action>>();
+    }
+
+    if !state.started {
+        if *app_state.current() != GameState::MainMenu {
+            return;
+        }
+        info!("Starting a new game");
+        app_state.overwrite_set(GameState::Running).unwrap();
+        state.started = true;
+    }
+
+    match &*mode {
+        ReplayMode::Off => {}
+        ReplayMode::Record { path, .. } => {
+            let tick = TickInput::capture(&action_state, &cursor, &mouse);
+            let written = file.0.as_mut().map(|writer| writer.tick(&tick));
+            if let Some(Err(error)) = written {
+                error!("Could not write to recording {}, stopping it: {}", path.display(), error);
+                file.0 = None;
+            }
+            state.recording.ticks.push(tick);
+        }
+        ReplayMode::Replay(_) => {
+            let tick = match state.recording.ticks.get(state.next) {
+                Some(tick) => tick.clone(),
+                None => return,
+            };
+            let previous = match state.next {
+                0 => TickInput::default(),
+                next => state.recording.ticks[next - 1].clone(),
+            };
+            state.next += 1;
+
+            for action in Action::variants() {
+                if tick.pressed(action) {
+                    action_state.press(action);
+                } else {
+                    action_state.release(action);
+                }
+            }
+            let mut aim = action_state.action_data(Action::Aim).clone();
+            aim.axis_pair = tick.aim.map(|[x, y]| DualAxisData::new(x, y));
+            action_state.set_action_data(Action::Aim, aim);
+
+            cursor.0 = tick.cursor.map(Vec2::from);
+
+            // built from scratch so just_pressed and just_released follow the
+            // recording, not the real mouse
+            let mut replayed = Input::default();
+            for (bit, button) in MOUSE_BUTTONS.iter().enumerate() {
+                if previous.mouse_pressed(bit) {
+                    replayed.press(*button);
+                }
+            }
+            replayed.clear();
+            for (bit, button) in MOUSE_BUTTONS.iter().enumerate() {
+                if tick.mouse_pressed(bit) {
+                    replayed.press(*button);
+                } else {
+                    replayed.release(*button);
+                }
+            }
+            *mouse = replayed;
+        }
+    }
+}
+
+// AppExit is read the frame it is sent, the app only stops after Last. The
+// ticks are already on disk, quitting only adds the checksum.
+fn finish_recording(
+    mode: Res<ReplayMode>,
+    mut exits: EventReader<AppExit>,
+    mut state: ResMut<ReplayState>,
+    mut file: ResMut<RecordingFile>,
+    parts: Query<(&BodyPart, &Transform)>,
+) {
+    let path = match &*mode {
+        ReplayMode::Record { path, .. } => path,
+        _ => return,
+    };
+    if exits.iter().last().is_none() || !state.started || state.finished {
+        return;
+    }
+    state.finished = true;
+
+    let writer = match file.0.take() {
+        Some(writer) => writer,
+        None => return,
+    };
+    let checksum = checksum(parts.iter().map(|(part, transform)| (*part, *transform)));
+    state.recording.checksum = Some(checksum);
+    match writer.finish(Some(checksum)) {
+        Ok(()) => info!(
+            "Recorded {} frames to {}, checksum {:016x}",
+            state.recording.ticks.len(),
+            path.display(),
+            checksum,
+        ),
+        Err(error) => error!("Could not write recording {}: {}", path.display(), error),
+    }
+}
+
+// A mismatch panics, so a replay fails a CI run or a test the same way
+fn finish_replay(
+    mode: Res<ReplayMode>,
+    mut state: ResMut<ReplayState>,
+    parts: Query<(&BodyPart, &Transform)>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !matches!(*mode, ReplayMode::Replay(_)) || !state.started || state.finished {
+        return;
+    }
+    if state.next < state.recording.ticks.len() {
+        return;
+    }
+    state.finished = true;
+
+    let replayed = checksum(parts.iter().map(|(part, transform)| (*part, *transform)));
+    match state.recording.checksum {
+        Some(recorded) if recorded == replayed => {
+            info!("Replay finished, checksum {:016x} matches", replayed);
+            exit.send(AppExit);
+        }
+        Some(recorded) => panic!(
+            "Replay diverged, checksum {:016x} but the recording ended on {:016x}",
+            replayed,
+            recorded,
+        ),
+        // the run crashed or was killed, getting this far is all there is to check
+        None => {
+            info!("Replay finished, the recording has no checksum to compare {:016x} with", replayed);
+            exit.send(AppExit);
+        }
+    }
+}
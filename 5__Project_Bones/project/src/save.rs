@@ -0,0 +1,493 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+};
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components_events::*;
+use crate::flags::{FlagValue, GameFlags};
+use crate::limbs::{spawn_limb, BodySheet, Limb};
+use crate::pause::PlayTime;
+use crate::puzzle::{Puzzle, PuzzleKind};
+use crate::quest::{QuestLog, QuestProgress};
+use crate::settings::user_data_dir;
+use crate::setup::{spawn_world, LEVEL_IIDS};
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SaveDir>()
+            .init_resource::<ActiveSlot>()
+            .init_resource::<CurrentRoom>()
+            .add_event::<SaveGame>()
+            .add_event::<LoadGame>()
+            .add_event::<RoomChange>()
+
+            // outside of any state, New Game and Continue are picked on the
+            // main menu and Save on the pause menu
+            .add_system(new_game)
+            .add_system(continue_game)
+            .add_system(
+                load_game
+                .after(continue_game)
+            )
+            .add_system(save_game)
+            .add_system(track_room)
+            .add_system(
+                autosave
+                .after(track_room)
+                .before(save_game)
+            );
+    }
+}
+
+/// Bumped whenever `SaveData` changes shape. Saves from another version are
+/// refused rather than half read.
+pub const SAVE_VERSION: u32 = 3;
+/// Written on every room change, never picked on the pause menu.
+pub const AUTOSAVE_SLOT: usize = 0;
+/// Slots the pause menu cycles through, after the autosave.
+pub const SAVE_SLOTS: usize = 3;
+
+// SAVE DATA
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedBodyPart {
+    pub part: BodyPart,
+    pub position: [f32; 2],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedPuzzle {
+    pub id: String,
+    pub kind: PuzzleKind,
+    pub solved: bool,
+}
+
+/// Everything that changes while playing. The level itself comes from LDtk,
+/// doors, item sources and puzzles are found again by their iid or id.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SaveData {
+    pub version: u32,
+    // seconds since the unix epoch, Continue picks the newest
+    pub saved_at: u64,
+    pub play_time: f32,
+    pub room: Option<usize>,
+    pub player: Player,
+    // in BodyParts order, the body first
+    pub body_parts: Vec<BodyPart>,
+    pub selected: BodyPart,
+    pub positions: Vec<SavedBodyPart>,
+    pub inventory: Vec<Option<String>>,
+    // LDtk iids
+    pub opened_doors: Vec<String>,
+    pub consumed_items: Vec<String>,
+    pub flags: BTreeMap<String, FlagValue>,
+    pub puzzles: Vec<SavedPuzzle>,
+    pub quests: Vec<QuestProgress>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "{}", error),
+            SaveError::Json(error) => write!(f, "{}", error),
+            SaveError::Version(version) => {
+                write!(f, "save is version {}, this build reads version {}", version, SAVE_VERSION)
+            }
+        }
+    }
+}
+
+// RESOURCES
+
+/// Where save slots are written, `project_bones/saves` in the platform data
+/// directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveDir(pub PathBuf);
+
+impl Default for SaveDir {
+    fn default() -> Self {
+        SaveDir(user_data_dir().join("saves"))
+    }
+}
+
+impl SaveDir {
+    pub fn slot_path(&self, slot: usize) -> PathBuf {
+        if slot == AUTOSAVE_SLOT {
+            self.0.join("autosave.json")
+        } else {
+            self.0.join(format!("slot_{}.json", slot))
+        }
+    }
+
+    pub fn exists(&self, slot: usize) -> bool {
+        self.slot_path(slot).is_file()
+    }
+
+    pub fn any_exists(&self) -> bool {
+        (0..=SAVE_SLOTS).any(|slot| self.exists(slot))
+    }
+
+    // the slot saved to last, by the time written inside the save
+    pub fn newest(&self) -> Option<usize> {
+        (0..=SAVE_SLOTS)
+            .filter_map(|slot| Some((slot, read_save(self, slot).ok()?.saved_at)))
+            .max_by_key(|(_, saved_at)| *saved_at)
+            .map(|(slot, _)| slot)
+    }
+}
+
+/// The slot the pause menu saves to, and the one last loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ActiveSlot(pub usize);
+
+impl Default for ActiveSlot {
+    fn default() -> Self {
+        ActiveSlot(1)
+    }
+}
+
+impl ActiveSlot {
+    // steps through the manual slots, wrapping around
+    pub fn step(&mut self, step: isize) {
+        let index = (self.0 as isize - 1 + step).rem_euclid(SAVE_SLOTS as isize);
+        self.0 = index as usize + 1;
+    }
+}
+
+/// Index into `LEVEL_IIDS` of the level the selected body part is in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CurrentRoom(pub Option<usize>);
+
+pub fn write_save(dir: &SaveDir, slot: usize, data: &SaveData) -> Result<(), SaveError> {
+    let string_data = serde_json::to_string_pretty(data).map_err(SaveError::Json)?;
+    fs::create_dir_all(&dir.0).map_err(SaveError::Io)?;
+    fs::write(dir.slot_path(slot), string_data).map_err(SaveError::Io)
+}
+
+// the version is read on its own first, so a changed layout is reported as such
+pub fn read_save(dir: &SaveDir, slot: usize) -> Result<SaveData, SaveError> {
+    let string_data = fs::read_to_string(dir.slot_path(slot)).map_err(SaveError::Io)?;
+    let value: serde_json::Value = serde_json::from_str(&string_data).map_err(SaveError::Json)?;
+
+    let version = value.get("version").and_then(|version| version.as_u64()).unwrap_or(0) as u32;
+    if version != SAVE_VERSION {
+        return Err(SaveError::Version(version));
+    }
+    serde_json::from_value(value).map_err(SaveError::Json)
+}
+
+// SAVE STATE
+
+/// The parts of the world a save reads from and writes back to.
+#[derive(SystemParam)]
+pub struct SaveState<'w, 's> {
+    commands: Commands<'w, 's>,
+    body_sheet: Res<'w, BodySheet>,
+    play_time: ResMut<'w, PlayTime>,
+    room: ResMut<'w, CurrentRoom>,
+    flags: ResMut<'w, GameFlags>,
+    quests: ResMut<'w, QuestLog>,
+    player: Query<'w, 's, (&'static mut Player, &'static mut BodyParts, &'static mut Inventory)>,
+    body_parts: Query<'w, 's, (Entity, &'static BodyPart, &'static mut Transform, Option<&'static Limb>)>,
+    interactables: Query<'w, 's, (&'static EntityInstance, &'static mut Interactable)>,
+    puzzles: Query<'w, 's, (Option<&'static EntityInstance>, &'static mut Puzzle)>,
+}
+
+impl<'w, 's> SaveState<'w, 's> {
+    pub fn capture(&self) -> Option<SaveData> {
+        let (player, parts, inventory) = self.player.get_single().ok()?;
+
+        let mut opened_doors = Vec::new();
+        let mut consumed_items = Vec::new();
+        for (instance, interactable) in self.interactables.iter() {
+            match interactable.interaction_type {
+                InteractionType::Door if interactable.door.as_ref().map_or(false, |door| !door.closed) => {
+                    opened_doors.push(instance.iid.clone());
+                }
+                // an item source with nothing left to give
+                InteractionType::Item if interactable.item.is_none() => {
+                    consumed_items.push(instance.iid.clone());
+                }
+                _ => {}
+            }
+        }
+
+        Some(SaveData {
+            version: SAVE_VERSION,
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            play_time: self.play_time.0,
+            room: self.room.0,
+            player: *player,
+            body_parts: parts.parts().to_vec(),
+            selected: parts.current(),
+            positions: self.body_parts
+                .iter()
+                .map(|(_, part, transform, _)| SavedBodyPart {
+                    part: *part,
+                    position: transform.translation.truncate().to_array(),
+                })
+                .collect(),
+            inventory: inventory.0
+                .iter()
+                .map(|slot| slot.as_ref().map(|item| item.id.clone()))
+                .collect(),
+            opened_doors,
+            consumed_items,
+            flags: self.flags.to_map(),
+            puzzles: self.puzzles
+                .iter()
+                .map(|(_, puzzle)| SavedPuzzle {
+                    id: puzzle.id.clone(),
+                    kind: puzzle.kind.clone(),
+                    solved: puzzle.solved,
+                })
+                .collect(),
+            quests: self.quests.0.clone(),
+        })
+    }
+
+    // Detached limbs are thrown away and spawned again where the save had
+    // them. The body part entities from LDtk are only moved. Doors, item
+    // sources and puzzles go back to how LDtk has them first, so nothing
+    // opened or taken since the save is carried over.
+    pub fn apply(&mut self, data: &SaveData) {
+        self.play_time.0 = data.play_time;
+        self.room.0 = data.room;
+        self.flags.replace(data.flags.clone());
+        self.quests.0 = data.quests.clone();
+
+        if let Ok((mut player, mut parts, mut inventory)) = self.player.get_single_mut() {
+            *player = data.player;
+
+            *parts = BodyParts::default();
+            for part in data.body_parts.iter() {
+                parts.push(*part);
+            }
+            parts.select(data.selected);
+
+            *inventory = Inventory::default();
+            for (slot, id) in inventory.0.iter_mut().zip(data.inventory.iter()) {
+                *slot = id.as_ref().map(|id| Item { id: id.clone() });
+            }
+        }
+
+        for (entity, part, mut transform, limb) in self.body_parts.iter_mut() {
+            if limb.is_some() {
+                self.commands.entity(entity).despawn_recursive();
+                continue;
+            }
+            if let Some(saved) = data.positions.iter().find(|saved| saved.part == *part) {
+                transform.translation.x = saved.position[0];
+                transform.translation.y = saved.position[1];
+            }
+        }
+        for saved in data.positions.iter() {
+            if saved.part != BodyPart::Body && data.body_parts.contains(&saved.part) {
+                let position = Vec2::from(saved.position).extend(0.);
+                spawn_limb(&mut self.commands, &self.body_sheet, saved.part, position);
+            }
+        }
+
+        for (instance, mut interactable) in self.interactables.iter_mut() {
+            let fresh = Interactable::from(instance.clone());
+            interactable.door = fresh.door;
+            interactable.item = fresh.item;
+
+            if data.opened_doors.contains(&instance.iid) {
+                if let Some(door) = interactable.door.as_mut() {
+                    door.requirements = None;
+                    door.closed = false;
+                }
+            }
+            if data.consumed_items.contains(&instance.iid) {
+                interactable.item = None;
+            }
+        }
+
+        for (instance, mut puzzle) in self.puzzles.iter_mut() {
+            if let Some(instance) = instance {
+                *puzzle = Puzzle::from(instance.clone());
+            }
+            if let Some(saved) = data.puzzles.iter().find(|saved| saved.id == puzzle.id) {
+                puzzle.kind = saved.kind.clone();
+                puzzle.solved = saved.solved;
+            }
+        }
+    }
+
+    // Back to how a fresh start has it. Doors, items and puzzles live on the
+    // LDtk entities, new_game spawns the world again for those.
+    pub fn reset(&mut self) {
+        self.play_time.0 = 0.;
+        self.room.0 = None;
+        self.flags.replace(BTreeMap::new());
+        self.quests.0.clear();
+
+        if let Ok((mut player, mut parts, mut inventory)) = self.player.get_single_mut() {
+            *player = Player::default();
+            *parts = BodyParts::default();
+            *inventory = Inventory::default();
+        }
+
+        for (entity, _, _, limb) in self.body_parts.iter() {
+            if limb.is_some() {
+                self.commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+// SYSTEMS
+
+// Quit to Menu leaves the last session in the world, a new game starts over
+// from the LDtk file rather than from whatever was left standing.
+pub fn new_game(
+    mut new_games: EventReader<NewGame>,
+    asset_server: Res<AssetServer>,
+    worlds: Query<Entity, With<Handle<LdtkAsset>>>,
+    mut state: SaveState,
+) {
+    if new_games.iter().last().is_none() {
+        return;
+    }
+
+    state.reset();
+    for world in worlds.iter() {
+        state.commands.entity(world).despawn_recursive();
+    }
+    spawn_world(&mut state.commands, &asset_server);
+}
+
+fn continue_game(
+    mut continued: EventReader<ContinueGame>,
+    save_dir: Res<SaveDir>,
+    mut load: EventWriter<LoadGame>,
+) {
+    if continued.iter().last().is_none() {
+        return;
+    }
+
+    match save_dir.newest() {
+        Some(slot) => load.send(LoadGame(slot)),
+        None => warn!("There is no save to continue"),
+    }
+}
+
+pub fn save_game(
+    mut saves: EventReader<SaveGame>,
+    save_dir: Res<SaveDir>,
+    mut active_slot: ResMut<ActiveSlot>,
+    state: SaveState,
+) {
+    for SaveGame(slot) in saves.iter() {
+        let data = match state.capture() {
+            Some(data) => data,
+            None => {
+                warn!("Nothing to save yet");
+                continue;
+            }
+        };
+
+        match write_save(&save_dir, *slot, &data) {
+            Ok(()) => info!("Saved to slot {}", slot),
+            Err(error) => error!("Could not save to slot {}: {}", slot, error),
+        }
+        if *slot != AUTOSAVE_SLOT {
+            active_slot.0 = *slot;
+        }
+    }
+}
+
+pub fn load_game(
+    mut loads: EventReader<LoadGame>,
+    save_dir: Res<SaveDir>,
+    mut active_slot: ResMut<ActiveSlot>,
+    mut state: SaveState,
+) {
+    for LoadGame(slot) in loads.iter() {
+        match read_save(&save_dir, *slot) {
+            Ok(data) => {
+                state.apply(&data);
+                info!("Loaded slot {}", slot);
+            }
+            Err(error) => {
+                error!("Could not load slot {}: {}", slot, error);
+                continue;
+            }
+        }
+        if *slot != AUTOSAVE_SLOT {
+            active_slot.0 = *slot;
+        }
+    }
+}
+
+// Sends RoomChange when the selected body part walks into another level
+fn track_room(
+    level_assets: Res<Assets<LdtkLevel>>,
+    levels: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    player: Query<&BodyParts, With<Player>>,
+    body_parts: Query<(&BodyPart, &GlobalTransform)>,
+    mut room: ResMut<CurrentRoom>,
+    mut room_change: EventWriter<RoomChange>,
+) {
+    let current = match player.get_single() {
+        Ok(parts) => parts.current(),
+        Err(_) => return,
+    };
+    let position = match body_parts.iter().find(|(part, _)| **part == current) {
+        Some((_, transform)) => transform.translation().truncate(),
+        None => return,
+    };
+
+    let inside = levels.iter().find_map(|(handle, level_transform)| {
+        let level = &level_assets.get(handle)?.level;
+        let min = level_transform.translation().truncate();
+        let max = min + Vec2::new(level.px_wid as f32, level.px_hei as f32);
+        if position.cmpge(min).all() && position.cmple(max).all() {
+            LEVEL_IIDS.iter().position(|iid| *iid == level.iid)
+        } else {
+            None
+        }
+    });
+
+    // between levels counts as still being in the last one
+    if let Some(index) = inside {
+        if room.0 != Some(index) {
+            let first = room.0.is_none();
+            room.0 = Some(index);
+            // the room the game starts or loads in is not a change
+            if !first {
+                room_change.send(RoomChange(index));
+            }
+        }
+    }
+}
+
+fn autosave(
+    mut room_change: EventReader<RoomChange>,
+    mut saves: EventWriter<SaveGame>,
+) {
+    if room_change.iter().last().is_some() {
+        saves.send(SaveGame(AUTOSAVE_SLOT));
+    }
+}
@@ -1,4 +1,9 @@
-use std::fs;
+use std::{
+    env,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
 use bevy::{
     prelude::*,
     window::WindowMode,
@@ -7,70 +12,194 @@ use leafwing_input_manager::prelude::*;
 use serde::{Deserialize, Serialize};
 use crate::components_events::{Hotkeys, Action};
 
-pub fn load_settings(
-    mut hotkey_event: EventWriter<Hotkeys>,
-    mut windows: ResMut<Windows>,
-) {
-    #[derive(Debug, Serialize, Deserialize)]
-    struct JsonSettings {
-        window_type: String,
-        resolution: [f32; 2],
-        master: f32,
-        characters: f32,
-        environment: f32,
-        keybinds: [[String; 2]; 7]
+// the defaults the game ships with, never written to
+pub const SETTINGS_FILE: &str = "assets/settings.json";
+
+/// Window modes the options menu cycles through, in order.
+pub const WINDOW_TYPES: [&str; 3] = ["Windowed", "Fullscreen", "BorderlessFullscreen"];
+
+/// settings.json, kept around so the options menu can change and save it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Settings {
+    pub window_type: String,
+    pub resolution: [f32; 2],
+    pub master: f32,
+    pub characters: f32,
+    pub environment: f32,
+    #[serde(default = "full_volume")]
+    pub music: f32,
+    // action name and key name, an action can have several
+    pub keybinds: Vec<[String; 2]>,
+}
+
+fn full_volume() -> f32 {
+    1.
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Io(error) => write!(f, "{}", error),
+            SettingsError::Json(error) => write!(f, "{}", error),
+        }
     }
+}
 
-    let string_data = fs::read_to_string("assets/settings.json").unwrap();
+/// Where the player's own settings are written, `project_bones/settings.json`
+/// in the platform data directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingsPath(pub PathBuf);
 
-    println!("string_data: {}", string_data);
-    let json_settings: JsonSettings = serde_json::from_str(&string_data).unwrap();
-    let mut input_map: InputMap<Action> = InputMap::default();
+impl Default for SettingsPath {
+    fn default() -> Self {
+        SettingsPath(user_data_dir().join("settings.json"))
+    }
+}
 
-    match json_settings.window_type.as_str() {
-        
-        "BorderlessFullscreen" => {
-            for window in windows.iter_mut() {
-                window.set_mode(WindowMode::BorderlessFullscreen);
-                window.set_resizable(false);
-                window.set_title(String::from("Project Bones"));
-            }
+/// `project_bones` in the platform data directory, where everything the
+/// player changes is kept.
+pub fn user_data_dir() -> PathBuf {
+    data_dir().join("project_bones")
+}
+
+// %APPDATA% on Windows, Application Support on macOS, XDG elsewhere. Falls
+// back to the working directory when none of them are set.
+fn data_dir() -> PathBuf {
+    let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+
+    let dir = if cfg!(target_os = "windows") {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local").join("share")))
+    };
+    dir.unwrap_or_default()
+}
+
+pub fn read_settings(path: &Path) -> Result<Settings, SettingsError> {
+    let string_data = fs::read_to_string(path).map_err(SettingsError::Io)?;
+    serde_json::from_str(&string_data).map_err(SettingsError::Json)
+}
+
+// The player's settings if they have saved any, the shipped ones otherwise.
+// The loading screen refuses to go on if the shipped ones can't be read.
+pub fn load_settings(
+    mut commands: Commands,
+    settings_path: Res<SettingsPath>,
+    mut hotkey_event: EventWriter<Hotkeys>,
+    mut windows: ResMut<Windows>,
+) {
+    let user_settings = match read_settings(&settings_path.0) {
+        Ok(settings) => Some(settings),
+        Err(SettingsError::Io(error)) if error.kind() == ErrorKind::NotFound => None,
+        Err(error) => {
+            error!("Could not read {}, using the defaults: {}", settings_path.0.display(), error);
+            None
         }
-        
-        "Fullscreen" => {
-            for window in windows.iter_mut() {
-                window.set_mode(WindowMode::Fullscreen);
-                window.set_resizable(false);
-                window.set_title(String::from("Project Bones"));
-            }
+    };
+    let settings = match user_settings.map_or_else(|| read_settings(Path::new(SETTINGS_FILE)), Ok) {
+        Ok(settings) => settings,
+        Err(error) => {
+            error!("Could not read {}: {}", SETTINGS_FILE, error);
+            return;
         }
+    };
 
-        "Windowed" => {
-            for window in windows.iter_mut() {
-                window.set_mode(WindowMode::Windowed);
-                window.set_resizable(true);
-                window.set_title(String::from("Project Bones"));
-            }
-        }
+    apply_window_type(&settings, &mut windows);
+    for window in windows.iter_mut() {
+        window.set_resolution(settings.resolution[0], settings.resolution[1]);
+    }
 
-        _ => {
-            for window in windows.iter_mut() {
-                window.set_mode(WindowMode::BorderlessFullscreen);
-                window.set_resizable(false);
-                window.set_title(String::from("Project Bones"));
-            }
-        }
+    hotkey_event.send(Hotkeys(build_input_map(&settings)));
+    commands.insert_resource(settings);
+}
+
+pub fn write_settings(path: &Path, settings: &Settings) -> Result<(), SettingsError> {
+    let string_data = serde_json::to_string_pretty(settings).map_err(SettingsError::Json)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(SettingsError::Io)?;
+    }
+    fs::write(path, string_data).map_err(SettingsError::Io)
+}
+
+pub fn save_settings(settings_path: &SettingsPath, settings: &Settings) {
+    if let Err(error) = write_settings(&settings_path.0, settings) {
+        error!("Could not save {}: {}", settings_path.0.display(), error);
     }
+}
+
+pub fn apply_window_type(settings: &Settings, windows: &mut Windows) {
+    let (mode, resizable) = match settings.window_type.as_str() {
+        "BorderlessFullscreen" => (WindowMode::BorderlessFullscreen, false),
+        "Fullscreen" => (WindowMode::Fullscreen, false),
+        "Windowed" => (WindowMode::Windowed, true),
+        _ => (WindowMode::BorderlessFullscreen, false),
+    };
 
     for window in windows.iter_mut() {
-        window.set_resolution(json_settings.resolution[0], json_settings.resolution[1]);
+        window.set_mode(mode);
+        window.set_resizable(resizable);
+        window.set_title(String::from("Project Bones"));
     }
+}
+
+pub fn build_input_map(settings: &Settings) -> InputMap<Action> {
+    let mut input_map: InputMap<Action> = InputMap::default();
 
-    for keybind in json_settings.keybinds.iter() {
+    for keybind in settings.keybinds.iter() {
         input_map.insert(string_to_keycode(&keybind[1]), string_to_action(&keybind[0]));
     }
 
-    hotkey_event.send(Hotkeys(input_map));
+    // gamepad layout is fixed, only the keyboard is rebindable
+    input_map.insert(GamepadButtonType::DPadUp, Action::Up);
+    input_map.insert(GamepadButtonType::DPadDown, Action::Down);
+    input_map.insert(GamepadButtonType::DPadLeft, Action::Left);
+    input_map.insert(GamepadButtonType::DPadRight, Action::Right);
+    input_map.insert(GamepadButtonType::South, Action::Interact);
+    input_map.insert(GamepadButtonType::LeftTrigger, Action::CycleBackward);
+    input_map.insert(GamepadButtonType::RightTrigger, Action::CycleForward);
+    input_map.insert(GamepadButtonType::West, Action::Split);
+    input_map.insert(GamepadButtonType::North, Action::Merge);
+    input_map.insert(GamepadButtonType::East, Action::Recall);
+    input_map.insert(DualAxis::right_stick(), Action::Aim);
+    input_map.insert(GamepadButtonType::RightThumb, Action::ZoomIn);
+    input_map.insert(GamepadButtonType::LeftThumb, Action::ZoomOut);
+    input_map.insert(GamepadButtonType::Select, Action::ZoomToFit);
+    input_map.insert(GamepadButtonType::LeftTrigger2, Action::Journal);
+    input_map.insert(GamepadButtonType::South, Action::Confirm);
+    input_map.insert(GamepadButtonType::East, Action::Back);
+    input_map.insert(GamepadButtonType::Start, Action::Pause);
+
+    input_map
+}
+
+/// The name settings.json uses for a key, if it has one.
+pub fn keycode_to_string(key: KeyCode) -> Option<String> {
+    let name = match key {
+        KeyCode::Space => "spacebar".to_string(),
+        KeyCode::Back => "backspace".to_string(),
+        _ => {
+            let name = format!("{:?}", key).to_lowercase();
+            // number keys are written without the Key in front
+            match name.strip_prefix("key") {
+                Some(digit) if digit.len() == 1 => digit.to_string(),
+                _ => name,
+            }
+        }
+    };
+
+    if string_to_keycode(&name) == key {
+        Some(name)
+    } else {
+        None
+    }
 }
 
 fn string_to_keycode(string: &str) -> KeyCode {
@@ -269,10 +398,29 @@ fn string_to_action(string: &str) -> Action {
         "Left" => Action::Left,
         "Right" => Action::Right,
         "Split" => Action::Split,
+        "Merge" => Action::Merge,
+        "Recall" => Action::Recall,
+
+        "SelectBody" => Action::SelectBody,
+        "SelectHead" => Action::SelectHead,
+        "SelectLeftArm" => Action::SelectLeftArm,
+        "SelectRightArm" => Action::SelectRightArm,
+        "SelectLeftLeg" => Action::SelectLeftLeg,
+        "SelectRightLeg" => Action::SelectRightLeg,
         
         "Interact" => Action::Interact,
         "CycleForward" => Action::CycleForward,
         "CycleBackward" => Action::CycleBackward,
+
+        "ZoomIn" => Action::ZoomIn,
+        "ZoomOut" => Action::ZoomOut,
+        "ZoomToFit" => Action::ZoomToFit,
+
+        "Journal" => Action::Journal,
+
+        "Confirm" => Action::Confirm,
+        "Back" => Action::Back,
+        "Pause" => Action::Pause,
         
         _ => Action::Unused,
     }
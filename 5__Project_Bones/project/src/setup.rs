@@ -1,16 +1,23 @@
-use bevy::prelude::*;
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{camera::RenderTarget, view::RenderLayers},
+};
 use bevy_ecs_ldtk::prelude::*;
 use leafwing_input_manager::prelude::*;
 use std::collections::HashSet;
 
+use crate::camera::{CameraConfig, CameraRig, CanvasSprite, PixelCanvas, CANVAS_LAYER};
 use crate::components_events::*;
 use crate::GameState;
-use crate::settings::load_settings;
+use crate::settings::{load_settings, SettingsPath};
+use crate::ysort::NoYSort;
 
 pub struct SetupPlugin;
 impl Plugin for SetupPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<SettingsPath>()
             .add_startup_system(ldtk_setup)
             
             .add_event::<Hotkeys>()
@@ -36,7 +43,9 @@ impl Plugin for SetupPlugin {
     }
 }
 
-const LEVEL_IIDS: [&str; 4] = [
+pub const LDTK_FILE: &str = "data/levels/floor_1.ldtk";
+
+pub const LEVEL_IIDS: [&str; 4] = [
     "af5e2950-5110-11ed-befe-d36fb482da1b",
     "7bbce970-5110-11ed-befe-35cfe01a3e95",
     "514859e0-5110-11ed-aff0-7b38360eac55",
@@ -48,41 +57,83 @@ fn ldtk_setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
 ) {
+    spawn_world(&mut commands, &asset_server);
+    info!("Began loading asset");
+}
+
+// also used by New Game to put every level back the way LDtk has it
+pub fn spawn_world(commands: &mut Commands, asset_server: &AssetServer) {
     let iids: HashSet<String> = LEVEL_IIDS.into_iter().map(|s| s.to_string()).collect();
 
     commands.spawn_bundle(LdtkWorldBundle {
-        ldtk_handle: asset_server.load("data\\levels\\floor_1.ldtk"),
+        ldtk_handle: asset_server.load(LDTK_FILE),
         level_set: LevelSet { iids: iids },
         ..Default::default()
     });
-    info!("Began loading asset");
 }
 
+// Leaving Loading is up to finish_loading, once everything else is in too
 fn add_player_parent(
     query: Query<Added<BodyPart>>,
+    parents: Query<(), With<Player>>,
     mut commands: Commands,
-    mut app_state: ResMut<State<GameState>>,
 ) {
-    for _query in query.iter() {
+    if query.iter().next().is_some() && parents.is_empty() {
         commands.spawn_bundle(PlayerParentBundle::default());
-        app_state.overwrite_set(GameState::Setup).unwrap();
         info!("Setup Player");
     }
 }
 
+// The world camera draws to the PixelCanvas, a second camera puts the
+// canvas on the window. UI is left to the window camera so it stays sharp.
 fn camera_setup(
     mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    config: Res<CameraConfig>,
     body_part: Query<&Transform, With<BodyPart>>,
 ) {
     let body_part = body_part.single();
-        
-    commands.spawn_bundle(Camera2dBundle {
-        projection: OrthographicProjection {
-            scale: 0.35,
+    let image = images.add(PixelCanvas::image(config.resolution));
+
+    let mut world_camera = Camera2dBundle {
+        camera: Camera {
+            target: RenderTarget::Image(image.clone()),
+            // drawn before the window camera reads it
+            priority: -1,
             ..default()
         },
-        transform: *body_part,
         ..default()
+    };
+    world_camera.transform.translation.x = body_part.translation.x;
+    world_camera.transform.translation.y = body_part.translation.y;
+    commands.spawn_bundle(world_camera)
+        .insert(CameraRig {
+            zoom: config.default_zoom,
+            ..default()
+        })
+        .insert(UiCameraConfig { show_ui: false });
+
+    commands.spawn_bundle(Camera2dBundle {
+        camera_2d: Camera2d {
+            // the letterbox
+            clear_color: ClearColorConfig::Custom(Color::BLACK),
+        },
+        ..default()
+    })
+    .insert(RenderLayers::layer(CANVAS_LAYER));
+
+    commands.spawn_bundle(SpriteBundle {
+        texture: image.clone(),
+        ..default()
+    })
+    .insert(CanvasSprite)
+    .insert(NoYSort)
+    .insert(RenderLayers::layer(CANVAS_LAYER));
+
+    commands.insert_resource(PixelCanvas {
+        image,
+        resolution: config.resolution,
+        scale: 1.,
     });
 }
 
@@ -100,6 +151,6 @@ fn hotkey_setup(
             input_map: input_map.0.clone(),
         });
         info!("Setup Hotkeys");
-        app_state.overwrite_set(GameState::Running).unwrap();
+        app_state.overwrite_set(GameState::MainMenu).unwrap();
     }  
 }
\ No newline at end of file
@@ -1,6 +1,10 @@
 use bevy::prelude::*;
 use leafwing_input_manager::prelude::*;
 use crate::components_events::*;
+use crate::dialogue::*;
+use crate::dialogue_ui::{Typewriter, MAX_CHOICES};
+use crate::flags::GameFlags;
+use crate::puzzle::PuzzleInput;
 use crate::GameState;
 
 pub struct InteractionsPlugin;
@@ -17,13 +21,27 @@ impl Plugin for InteractionsPlugin {
             .add_system_set(
                 SystemSet::on_update(GameState::Running)
                     .with_system(detect_interactables)
-                    .with_system(broadcast_interactions)
+                    .with_system(
+                        broadcast_interactions
+                        .after(interact)
+                    )
                     .with_system(puzzle_interaction)
                     .with_system(item_interaction)
-                    .with_system(dialogue_receive)
+                    .with_system(
+                        door_interaction
+                        .after(broadcast_interactions)
+                    )
+                    .with_system(
+                        dialogue_receive
+                        .after(broadcast_interactions)
+                        .after(dialogue_send)
+                    )
                     .with_system(dialogue_send)
                     .with_system(action_debug)
-                    .with_system(interact)   
+                    .with_system(
+                        interact
+                        .before(dialogue_send)
+                    )
                     //.with_system(update_level_selection)
             );
     }
@@ -71,7 +89,7 @@ pub fn detect_interactables(
     
     for body_part in body_parts.iter() {
         for mut interactable in query.iter_mut() {
-            if *body_part.1 == parent.current_part {
+            if *body_part.1 == parent.current() {
                 let proximity = (
                     (interactable.1.translation.x - body_part.0.translation.x).powi(2) + 
                     (interactable.1.translation.y - body_part.0.translation.y).powi(2)
@@ -89,13 +107,21 @@ pub fn detect_interactables(
 pub fn interact(
     query: Query<(Entity, &Interactable)>,
     action_state: Query<&ActionState<Action>, With<Player>>,
+    inventory: Query<&Inventory>,
+    runner: Res<DialogueRunner>,
+    flags: Res<GameFlags>,
     mut interact_event: EventWriter<InteractionWrapper>,
 ) {
     let action_state = action_state.single();
+    let inventory = inventory.single();
     
-    if action_state.just_pressed(Action::Interact) {
+    // Interact advances the conversation instead while one is open
+    if action_state.just_pressed(Action::Interact) && !runner.is_active() {
         for query in query.iter() {
-            if query.1.can_interact == true {
+            let allowed = query.1.condition
+                .as_ref()
+                .map_or(true, |condition| condition.evaluate(&flags, inventory));
+            if query.1.can_interact == true && allowed {
                 interact_event.send(InteractionWrapper(query.0, query.1.interaction_type.clone()));
             }
         }
@@ -104,6 +130,7 @@ pub fn interact(
 
 pub fn broadcast_interactions(
     mut interact_event: EventReader<InteractionWrapper>,
+    interactables: Query<&Interactable>,
     mut item_interaction: EventWriter<ItemInteraction>,
     mut puzzle_interaction: EventWriter<PuzzleInteraction>,
     mut person_interaction: EventWriter<PersonInteraction>,
@@ -113,95 +140,172 @@ pub fn broadcast_interactions(
         match event.1 {
             InteractionType::Item => {
                 info!("Interacted with a item!");
-                item_interaction.send(ItemInteraction(Item::default()));
+                match interactables.get(event.0).ok().and_then(|interactable| interactable.item.clone()) {
+                    Some(item) => item_interaction.send(ItemInteraction(item, event.0)),
+                    None => info!("There is nothing left here"),
+                }
             },
             InteractionType::Puzzle => {
                 info!("Interacted with a puzzle!");
-                puzzle_interaction.send(PuzzleInteraction(Puzzle));
+                let switch = interactables
+                    .get(event.0)
+                    .ok()
+                    .and_then(|interactable| interactable.puzzle.clone())
+                    .unwrap_or_default();
+                puzzle_interaction.send(PuzzleInteraction(switch, event.0));
             },
             InteractionType::Person => {
                 info!("Interacted with a person!");
-                person_interaction.send(PersonInteraction(Person::default(), (0, 0)));
+                let person = interactables
+                    .get(event.0)
+                    .ok()
+                    .and_then(|interactable| interactable.person.clone())
+                    .unwrap_or_default();
+                person_interaction.send(PersonInteraction(person, event.0));
             },
             InteractionType::Door => {
                 info!("Interacted with a door!");
-                door_interaction.send(DoorInteraction(Door::default(), event.0))
+                let door = interactables
+                    .get(event.0)
+                    .ok()
+                    .and_then(|interactable| interactable.door.clone())
+                    .unwrap_or_default();
+                door_interaction.send(DoorInteraction(door, event.0))
             }
         }
     }
 }
 
+// A source that is not reusable is emptied, so saves know it was taken
 pub fn item_interaction(
     mut item_interactions: EventReader<ItemInteraction>,
-    mut query: Query<&mut Inventory>
+    mut query: Query<&mut Inventory>,
+    mut interactables: Query<&mut Interactable>,
 ) {
     for item_interaction in item_interactions.iter() {
         let mut inventory = query.single_mut();
-        for a in 0..inventory.0.len() {
-            if inventory.0[a].is_none() {
-                inventory.0[a] = Some(item_interaction.0.clone());
-                break;
+        if !inventory.give(item_interaction.0.clone()) {
+            info!("Inventory is full, could not take {}", item_interaction.0.id);
+            continue;
+        }
+        if let Ok(mut interactable) = interactables.get_mut(item_interaction.1) {
+            if !interactable.reusable {
+                interactable.item = None;
             }
         }
     }
 }
 
 pub fn puzzle_interaction(
-   // mut puzzle_interaction: EventReader<PuzzleInteraction>
+    mut puzzle_interaction: EventReader<PuzzleInteraction>,
+    mut signals: EventWriter<PuzzleSignal>,
 ) {
-    
+    for interaction in puzzle_interaction.iter() {
+        let switch = &interaction.0;
+        signals.send(PuzzleSignal(switch.puzzle.clone(), PuzzleInput::Activate(switch.element.clone())));
+    }
 }
 
 pub fn dialogue_receive(
     mut person_receive: EventReader<PersonInteraction>,
+    library: Res<DialogueLibrary>,
+    mut runner: ResMut<DialogueRunner>,
+    mut effects: EventWriter<DialogueEffect>,
 ) {
-    for _statement in person_receive.iter() {
-        
+    for person in person_receive.iter() {
+        if runner.is_active() {
+            continue;
+        }
+
+        match &(person.0).0 {
+            Some(start) => {
+                for effect in runner.start(&library, start, Some(person.1)) {
+                    effects.send(effect);
+                }
+            }
+            None => info!("This person has nothing to say"),
+        }
     }
 }
 
-// called on click of choice match what choise write new event
+// Up and Down move between the visible choices, Interact or a click takes the
+// selected one. Interact first skips the typewriter if the text is still showing.
 pub fn dialogue_send(
-    //mut person_send: EventWriter<PersonInteraction>,
-    //mut commands: Commands,
-    //mut query: Query<Option<(Entity, With<DialogueChoice>)>>,
+    action_state: Query<&ActionState<Action>, With<Player>>,
+    inventory: Query<&Inventory>,
+    choice_buttons: Query<(&Interaction, &DialogueChoice), Changed<Interaction>>,
+    library: Res<DialogueLibrary>,
+    flags: Res<GameFlags>,
+    mut runner: ResMut<DialogueRunner>,
+    mut typewriter: ResMut<Typewriter>,
+    mut effects: EventWriter<DialogueEffect>,
 ) {
-    
-    
-    
-    // Person (usize, usize)
-    // (current conversation number, choice)
+    if !runner.is_active() {
+        return;
+    }
+
+    let action_state = action_state.single();
+    let inventory = inventory.single();
+    let choice_count = runner.choices(&library, &flags, inventory).len().min(MAX_CHOICES);
+    let mut choose = action_state.just_pressed(Action::Interact);
 
-    //person_send.send(PersonInteraction(statement.0.clone(), (0, 0)));
+    if action_state.just_pressed(Action::Up) {
+        runner.select_prev(choice_count);
+    }
+
+    if action_state.just_pressed(Action::Down) {
+        runner.select_next(choice_count);
+    }
+
+    for (interaction, choice) in choice_buttons.iter() {
+        match interaction {
+            Interaction::Clicked => {
+                runner.selected = choice.0;
+                choose = true;
+            }
+            Interaction::Hovered => runner.selected = choice.0,
+            Interaction::None => {}
+        }
+    }
+
+    if choose {
+        if !typewriter.finished() {
+            typewriter.skip();
+        } else {
+            for effect in runner.advance(&library, &flags, inventory) {
+                effects.send(effect);
+            }
+        }
+    }
 }
 
+// A door opens once every key it asks for is carried
 pub fn door_interaction(
     mut door_interaction: EventReader<DoorInteraction>,
-    mut query: Query<&mut Inventory>,
-    mut commands: Commands,
+    query: Query<&Inventory>,
     mut interactable: Query<&mut Interactable>
 ) {
     for door in door_interaction.iter() {
-        let inventory = query.single_mut();
-        if door.0.requirements.is_none() {
-           info!("You opened the door!");
-        } else {
-            for requirement in door.0.requirements.as_ref().unwrap().iter() {
-                for slot in inventory.0.iter() {
-                    if slot.is_some() && *requirement == slot.as_ref().unwrap().id {
-                        // door animation, audio
-                        commands.entity(door.1);
-                        if let Ok(mut interactable) = interactable.get_mut(door.1) {
-                            interactable.door = Some(Door {
-                                requirements: None,
-                                reusable: door.0.reusable,
-                                closed: false,
-                            });
-                        }
-                        info!("You opened the door with the key(s)!");
-                    }
-                }
-            }
+        let inventory = query.single();
+        let unlocked = door.0.requirements
+            .as_ref()
+            .map_or(true, |requirements| requirements.iter().all(|id| inventory.contains(id)));
+        if !unlocked {
+            info!("The door is locked");
+            continue;
+        }
+
+        // door animation, audio
+        if let Ok(mut interactable) = interactable.get_mut(door.1) {
+            interactable.door = Some(Door {
+                requirements: None,
+                reusable: door.0.reusable,
+                closed: false,
+            });
+        }
+        match door.0.requirements {
+            Some(_) => info!("You opened the door with the key(s)!"),
+            None => info!("You opened the door!"),
         }
     }
 }
@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::components_events::*;
+use crate::puzzle::PuzzleInput;
+use crate::ysort::NoYSort;
+use crate::GameState;
+
+pub struct TriggerPlugin;
+
+impl Plugin for TriggerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<BodyPartEntered>()
+            .add_event::<BodyPartExited>()
+
+            // triggers and body parts are spawned by LDtk while the game is still Loading
+            .add_system(setup_triggers)
+            .add_system(add_body_part_colliders)
+
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(detect_body_parts)
+                    .with_system(
+                        plates_and_levers
+                        .after(detect_body_parts)
+                    )
+            );
+    }
+}
+
+// half extents, in pixels
+const TRIGGER_SIZE: f32 = 7.;
+const BODY_PART_SIZE: f32 = 6.;
+
+// TRIGGER COMPONENTS
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TriggerKind {
+    // held down while an accepted part stands on it
+    Plate,
+    // pulled each time an accepted part reaches it
+    Lever,
+}
+
+impl Default for TriggerKind {
+    fn default() -> Self {
+        TriggerKind::Plate
+    }
+}
+
+/// A Rapier sensor that notices body parts, placed in LDtk as a `Plate` or a
+/// `Lever`. Only parts listed in `parts` and at least `min_weight` heavy set
+/// it off, and it passes what happens on to the puzzle named by `switch`.
+#[derive(Clone, Debug, Default, Component)]
+pub struct BodyPartTrigger {
+    pub kind: TriggerKind,
+    // empty accepts every part
+    pub parts: Vec<BodyPart>,
+    pub min_weight: u32,
+    pub switch: Option<PuzzleSwitch>,
+    // parts inside the sensor right now
+    pub occupants: Vec<(Entity, BodyPart)>,
+    // plates are active while held down, levers flip with every pull
+    pub active: bool,
+}
+
+impl BodyPartTrigger {
+    pub fn accepts(&self, part: BodyPart) -> bool {
+        (self.parts.is_empty() || self.parts.contains(&part)) && part.weight() >= self.min_weight
+    }
+}
+
+impl From<EntityInstance> for BodyPartTrigger {
+    fn from(entity_instance: EntityInstance) -> Self {
+        let kind = match entity_instance.identifier.as_str() {
+            "Lever" => TriggerKind::Lever,
+            _ => TriggerKind::Plate,
+        };
+
+        // comma separated BodyPart names, levers default to arms only
+        let parts = match field_string(&entity_instance, "Parts") {
+            Some(parts) => parts
+                .split(',')
+                .filter_map(|name| {
+                    let part = BodyPart::from_identifier(name.trim());
+                    if part.is_none() {
+                        warn!("{} {} names unknown body part {}", entity_instance.identifier, entity_instance.iid, name);
+                    }
+                    part
+                })
+                .collect(),
+            None if kind == TriggerKind::Lever => vec![BodyPart::LeftArm, BodyPart::RightArm],
+            None => Vec::new(),
+        };
+
+        let switch = field_string(&entity_instance, "Puzzle").map(|puzzle| PuzzleSwitch {
+            puzzle,
+            element: field_string(&entity_instance, "Element").unwrap_or_default(),
+        });
+
+        BodyPartTrigger {
+            kind,
+            parts,
+            min_weight: field_int(&entity_instance, "Min_weight").unwrap_or(0).max(0) as u32,
+            switch,
+            ..default()
+        }
+    }
+}
+
+// SYSTEMS
+
+fn setup_triggers(
+    mut commands: Commands,
+    triggers: Query<(Entity, &BodyPartTrigger), Added<BodyPartTrigger>>,
+) {
+    for (entity, trigger) in triggers.iter() {
+        commands.entity(entity)
+            .insert(Collider::cuboid(TRIGGER_SIZE, TRIGGER_SIZE))
+            .insert(Sensor)
+            .insert(ActiveEvents::COLLISION_EVENTS)
+            // the body is moved by hand, so it has to be seen as kinematic
+            .insert(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_STATIC);
+        // plates lie flat, whatever stands on them is drawn on top
+        if trigger.kind == TriggerKind::Plate {
+            commands.entity(entity).insert(NoYSort);
+        }
+    }
+}
+
+// Detached parts bring their own rigid body, anything else gets a kinematic one
+fn add_body_part_colliders(
+    mut commands: Commands,
+    body_parts: Query<Entity, (Added<BodyPart>, Without<RigidBody>)>,
+) {
+    for entity in body_parts.iter() {
+        commands.entity(entity)
+            .insert(RigidBody::KinematicPositionBased)
+            .insert(Collider::cuboid(BODY_PART_SIZE, BODY_PART_SIZE));
+    }
+}
+
+// Exits are matched against the trigger's occupants rather than the part
+// entity, which may already be despawned by a merge or a loaded save.
+fn detect_body_parts(
+    mut collisions: EventReader<CollisionEvent>,
+    mut triggers: Query<(Entity, &mut BodyPartTrigger)>,
+    body_parts: Query<&BodyPart>,
+    mut entered: EventWriter<BodyPartEntered>,
+    mut exited: EventWriter<BodyPartExited>,
+) {
+    for collision in collisions.iter() {
+        let (a, b, started) = match collision {
+            CollisionEvent::Started(a, b, _) => (*a, *b, true),
+            CollisionEvent::Stopped(a, b, _) => (*a, *b, false),
+        };
+
+        // rapier does not promise which collider comes first
+        let (trigger_entity, part_entity) = if triggers.get(a).is_ok() { (a, b) } else { (b, a) };
+        let (_, mut trigger) = match triggers.get_mut(trigger_entity) {
+            Ok(trigger) => trigger,
+            Err(_) => continue,
+        };
+
+        if started {
+            let part = match body_parts.get(part_entity) {
+                Ok(part) => *part,
+                Err(_) => continue,
+            };
+            if trigger.accepts(part) {
+                trigger.occupants.push((part_entity, part));
+                entered.send(BodyPartEntered(trigger_entity, part_entity, part));
+            }
+        } else if let Some(index) = trigger.occupants.iter().position(|(entity, _)| *entity == part_entity) {
+            let (_, part) = trigger.occupants.remove(index);
+            exited.send(BodyPartExited(trigger_entity, part_entity, part));
+        }
+    }
+
+    // a despawned part isn't always followed by a Stopped event, so anything
+    // that stopped being a body part has left
+    for (trigger_entity, mut trigger) in triggers.iter_mut() {
+        if trigger.occupants.iter().all(|(entity, _)| body_parts.get(*entity).is_ok()) {
+            continue;
+        }
+
+        let (kept, gone): (Vec<_>, Vec<_>) = trigger.occupants
+            .drain(..)
+            .partition(|(entity, _)| body_parts.get(*entity).is_ok());
+        trigger.occupants = kept;
+        for (part_entity, part) in gone {
+            exited.send(BodyPartExited(trigger_entity, part_entity, part));
+        }
+    }
+}
+
+fn plates_and_levers(
+    mut entered: EventReader<BodyPartEntered>,
+    mut exited: EventReader<BodyPartExited>,
+    mut triggers: Query<(&mut BodyPartTrigger, &mut TextureAtlasSprite)>,
+    mut signals: EventWriter<PuzzleSignal>,
+) {
+    for event in entered.iter() {
+        let (mut trigger, mut sprite) = match triggers.get_mut(event.0) {
+            Ok(trigger) => trigger,
+            Err(_) => continue,
+        };
+
+        match trigger.kind {
+            TriggerKind::Plate => trigger.active = true,
+            TriggerKind::Lever => {
+                trigger.active = !trigger.active;
+                sprite.flip_x = trigger.active;
+            }
+        }
+
+        if let Some(switch) = &trigger.switch {
+            let input = match trigger.kind {
+                TriggerKind::Plate => PuzzleInput::Pressed(switch.element.clone(), event.2),
+                TriggerKind::Lever => PuzzleInput::Activate(switch.element.clone()),
+            };
+            info!("{:?} reached a {:?} of {}", event.2, trigger.kind, switch.puzzle);
+            signals.send(PuzzleSignal(switch.puzzle.clone(), input));
+        }
+    }
+
+    for event in exited.iter() {
+        let (mut trigger, _) = match triggers.get_mut(event.0) {
+            Ok(trigger) => trigger,
+            Err(_) => continue,
+        };
+
+        if trigger.kind != TriggerKind::Plate {
+            continue;
+        }
+        trigger.active = !trigger.occupants.is_empty();
+
+        if let Some(switch) = &trigger.switch {
+            signals.send(PuzzleSignal(switch.puzzle.clone(), PuzzleInput::Released(switch.element.clone(), event.2)));
+        }
+    }
+}
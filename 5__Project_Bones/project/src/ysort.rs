@@ -0,0 +1,104 @@
+use bevy::{
+    prelude::*,
+    transform::TransformSystem,
+};
+use bevy_ecs_ldtk::prelude::*;
+
+use crate::components_events::field_int;
+
+pub struct YSortPlugin;
+
+impl Plugin for YSortPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // after everything has moved, before the z reaches GlobalTransform
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                y_sort.before(TransformSystem::TransformPropagate)
+            );
+    }
+}
+
+/// Sorted sprites start above every LDtk layer.
+pub const Y_SORT_BASE: f32 = 10.;
+/// Z per pixel of height. The floor is about 1200 pixels tall, so sorted
+/// sprites stay between 10 and 12.
+pub const Y_SORT_SCALE: f32 = 0.001;
+
+// Y SORT COMPONENTS
+
+/// Leaves the entity's z alone, for things that lie flat on the floor or
+/// place themselves, like plates and tethers.
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct NoYSort;
+
+/// Where the entity touches the floor, in pixels above its origin. Without
+/// one a sprite stands on the bottom edge of its image.
+///
+/// LDtk entities stand on the bottom of their box rather than their sprite,
+/// so 2 high props drawn past the box still sort by the tile they are placed
+/// on. The optional `Sort_anchor` Int field raises that line by some pixels.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Component)]
+pub struct SortAnchor(pub f32);
+
+impl From<EntityInstance> for SortAnchor {
+    fn from(entity_instance: EntityInstance) -> Self {
+        let raise = field_int(&entity_instance, "Sort_anchor").unwrap_or(0);
+        SortAnchor(raise as f32 - entity_instance.height as f32 / 2.)
+    }
+}
+
+// height of a sprite's bottom edge above its origin
+fn sprite_bottom(anchor: &bevy::sprite::Anchor, height: f32) -> f32 {
+    -(0.5 + anchor.as_vec().y) * height
+}
+
+// SYSTEMS
+
+// The lower a sprite stands on screen the closer it is drawn. Children of
+// LDtk layers only set their local z, so the parent's is taken back out.
+#[allow(clippy::type_complexity)]
+fn y_sort(
+    images: Res<Assets<Image>>,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut sprites: Query<
+        (
+            &mut Transform,
+            &GlobalTransform,
+            Option<&Parent>,
+            Option<&SortAnchor>,
+            Option<(&Sprite, &Handle<Image>)>,
+            Option<(&TextureAtlasSprite, &Handle<TextureAtlas>)>,
+        ),
+        (Or<(With<Sprite>, With<TextureAtlasSprite>)>, Without<NoYSort>),
+    >,
+) {
+    for (mut transform, global_transform, parent, anchor, sprite, atlas_sprite) in sprites.iter_mut() {
+        let foot = match (anchor, sprite, atlas_sprite) {
+            (Some(anchor), _, _) => Some(anchor.0),
+            (None, Some((sprite, image)), _) => sprite.custom_size
+                .map(|size| size.y)
+                .or_else(|| images.get(image).map(|image| image.size().y))
+                .map(|height| sprite_bottom(&sprite.anchor, height)),
+            (None, None, Some((sprite, atlas))) => sprite.custom_size
+                .map(|size| size.y)
+                .or_else(|| atlases.get(atlas)?.textures.get(sprite.index).map(|rect| rect.height()))
+                .map(|height| sprite_bottom(&sprite.anchor, height)),
+            _ => None,
+        };
+        // images still loading sort by their origin until they arrive
+        let foot = foot.unwrap_or(0.) * transform.scale.y;
+
+        // this frame's Transform is newer than GlobalTransform, only the parent's part is taken from it
+        let parent_offset = match parent {
+            Some(_) => global_transform.translation() - transform.translation,
+            None => Vec3::ZERO,
+        };
+
+        let foot_y = parent_offset.y + transform.translation.y + foot;
+        let z = Y_SORT_BASE - foot_y * Y_SORT_SCALE - parent_offset.z;
+        if (transform.translation.z - z).abs() > f32::EPSILON {
+            transform.translation.z = z;
+        }
+    }
+}
@@ -0,0 +1,245 @@
+mod harness;
+
+use bevy::prelude::*;
+
+use harness::Harness;
+use project_bones::{
+    components_events::*,
+    dialogue::{DialogueEffect, DialogueRunner},
+    flags::{Condition, FlagValue, GameFlags},
+    puzzle::{Puzzle, PuzzleKind},
+};
+
+// MOVE_SPEED, in pixels a frame
+const SPEED: f32 = 2.;
+
+#[test]
+fn held_directions_move_the_body() {
+    let mut harness = Harness::new();
+
+    harness.hold(Action::Right, 10);
+    harness.hold(Action::Up, 5);
+    assert_eq!(harness.position(BodyPart::Body), Vec3::new(10. * SPEED, 5. * SPEED, 10.));
+
+    // nothing is held any more
+    harness.run(5);
+    assert_eq!(harness.position(BodyPart::Body), Vec3::new(10. * SPEED, 5. * SPEED, 10.));
+}
+
+#[test]
+fn only_the_selected_part_moves() {
+    let mut harness = Harness::new();
+    {
+        let world = &mut harness.app.world;
+        let mut body_parts = world.query::<&mut BodyParts>().single_mut(world);
+        body_parts.push(BodyPart::LeftArm);
+    }
+    spawn_part(&mut harness, BodyPart::LeftArm, Vec3::new(-30., 0., 10.));
+
+    harness.hold(Action::Right, 10);
+    assert_eq!(harness.position(BodyPart::Body), Vec3::new(0., 0., 10.));
+
+    harness.tap(Action::SelectBody);
+    assert_eq!(harness.body_parts().current(), BodyPart::Body);
+    harness.hold(Action::Right, 10);
+    assert_eq!(harness.position(BodyPart::Body), Vec3::new(10. * SPEED, 0., 10.));
+
+    harness.tap(Action::CycleForward);
+    assert_eq!(harness.body_parts().current(), BodyPart::LeftArm);
+}
+
+#[test]
+fn the_gum_machine_hands_out_gumballs_in_reach() {
+    let mut harness = Harness::new();
+    let gum_machine = harness.spawn_ldtk_entity("Gum_Machine", "gum_machine", Vec2::new(60., 0.));
+    harness.step();
+
+    // too far away
+    harness.tap(Action::Interact);
+    harness.step();
+    assert!(harness.inventory().is_empty());
+    assert!(harness.events(|item: &ItemInteraction| item.0.id.clone()).is_empty());
+
+    harness.hold(Action::Right, 25);
+    harness.tap(Action::Interact);
+    harness.step();
+    assert_eq!(harness.events(|item: &ItemInteraction| item.0.id.clone()), vec!["Gumball".to_string()]);
+    assert_eq!(harness.inventory(), vec!["Gumball".to_string()]);
+    // it never runs out
+    assert!(harness.app.world.get::<Interactable>(gum_machine).unwrap().item.is_some());
+}
+
+#[test]
+fn interactables_wait_for_their_condition() {
+    let mut harness = Harness::new();
+    let locker = harness.spawn_interactable(
+        "locker",
+        Interactable {
+            interaction_type: InteractionType::Item,
+            item: Some(Item { id: "Mop".to_string() }),
+            condition: Some(Condition::parse("flag janitor_asked").unwrap()),
+            ..default()
+        },
+        Vec2::new(10., 0.),
+    );
+    harness.step();
+
+    harness.tap(Action::Interact);
+    harness.step();
+    assert!(harness.inventory().is_empty());
+
+    harness.app.world.resource_mut::<GameFlags>().set("janitor_asked", true);
+    harness.tap(Action::Interact);
+    harness.step();
+    assert_eq!(harness.inventory(), vec!["Mop".to_string()]);
+    // the locker is not reusable, so it is empty now
+    assert!(harness.app.world.get::<Interactable>(locker).unwrap().item.is_none());
+}
+
+#[test]
+fn switches_used_in_order_solve_their_puzzle() {
+    let mut harness = Harness::new();
+    harness.spawn_puzzle(Puzzle {
+        id: "levers".to_string(),
+        kind: PuzzleKind::Sequence {
+            solution: vec!["left".to_string(), "right".to_string()],
+            entered: Vec::new(),
+        },
+        rewards: vec![DialogueEffect::SetFlag("levers_pulled".to_string(), FlagValue::Bool(true))],
+        ..default()
+    });
+    for (element, x) in [("left", 10.), ("right", 70.)] {
+        harness.spawn_interactable(
+            element,
+            Interactable {
+                interaction_type: InteractionType::Puzzle,
+                puzzle: Some(PuzzleSwitch {
+                    puzzle: "levers".to_string(),
+                    element: element.to_string(),
+                }),
+                ..default()
+            },
+            Vec2::new(x, 0.),
+        );
+    }
+    harness.step();
+
+    harness.tap(Action::Interact);
+    harness.step();
+    assert!(harness.events(|solved: &PuzzleSolved| solved.0.clone()).is_empty());
+
+    harness.hold(Action::Right, 30);
+    harness.tap(Action::Interact);
+    harness.step();
+    assert_eq!(harness.events(|solved: &PuzzleSolved| solved.0.clone()), vec!["levers".to_string()]);
+
+    harness.step();
+    assert!(harness.resource::<GameFlags>().is_set("levers_pulled"));
+}
+
+#[test]
+fn the_receptionist_trades_the_office_key_for_a_gumball() {
+    let mut harness = Harness::new();
+    harness.spawn_ldtk_entity("Gum_Machine", "gum_machine", Vec2::new(10., 0.));
+    harness.spawn_interactable(
+        "receptionist",
+        Interactable {
+            interaction_type: InteractionType::Person,
+            person: Some(Person(Some("receptionist.start".to_string()))),
+            ..default()
+        },
+        Vec2::new(100., 0.),
+    );
+    harness.step();
+
+    harness.tap(Action::Interact);
+    harness.hold(Action::Right, 45);
+    harness.tap(Action::Interact);
+    harness.step();
+    assert_eq!(harness.events(|person: &PersonInteraction| person.0.0.clone()), vec![Some("receptionist.start".to_string())]);
+    assert_eq!(harness.resource::<DialogueRunner>().current.as_deref(), Some("receptionist.start"));
+    assert!(harness.resource::<GameFlags>().is_set("met_receptionist"));
+
+    // the gumball is the second choice while one is carried
+    harness.tap(Action::Down);
+    harness.tap(Action::Interact);
+    harness.step();
+    assert_eq!(harness.resource::<DialogueRunner>().current.as_deref(), Some("receptionist.gumball"));
+    assert_eq!(harness.inventory(), vec!["Office_Key".to_string()]);
+    assert!(harness.resource::<GameFlags>().is_set("gave_gumball"));
+
+    harness.tap(Action::Interact);
+    harness.tap(Action::Interact);
+    assert!(!harness.resource::<DialogueRunner>().is_active());
+}
+
+#[test]
+fn the_office_key_opens_the_office_door() {
+    let mut harness = Harness::new();
+    let door = harness.spawn_interactable(
+        "office_door",
+        Interactable {
+            interaction_type: InteractionType::Door,
+            door: Some(Door {
+                requirements: Some(vec!["Office_Key".to_string()]),
+                ..default()
+            }),
+            ..default()
+        },
+        Vec2::new(10., 0.),
+    );
+    harness.step();
+    let closed = |harness: &Harness| {
+        harness.app.world.get::<Interactable>(door).unwrap().door.as_ref().unwrap().closed
+    };
+
+    harness.tap(Action::Interact);
+    harness.step();
+    assert!(closed(&harness));
+
+    {
+        let world = &mut harness.app.world;
+        world.query::<&mut Inventory>().single_mut(world).give(Item { id: "Office_Key".to_string() });
+    }
+    harness.tap(Action::Interact);
+    harness.step();
+    assert!(!closed(&harness));
+}
+
+#[test]
+fn a_door_with_two_locks_needs_both_keys() {
+    let mut harness = Harness::new();
+    let door = harness.spawn_interactable(
+        "vault_door",
+        Interactable {
+            interaction_type: InteractionType::Door,
+            door: Some(Door {
+                requirements: Some(vec!["Office_Key".to_string(), "Vault_Key".to_string()]),
+                ..default()
+            }),
+            ..default()
+        },
+        Vec2::new(10., 0.),
+    );
+    harness.step();
+    let closed = |harness: &Harness| {
+        harness.app.world.get::<Interactable>(door).unwrap().door.as_ref().unwrap().closed
+    };
+
+    for (key, still_closed) in [("Office_Key", true), ("Vault_Key", false)] {
+        {
+            let world = &mut harness.app.world;
+            world.query::<&mut Inventory>().single_mut(world).give(Item { id: key.to_string() });
+        }
+        harness.tap(Action::Interact);
+        harness.step();
+        assert_eq!(closed(&harness), still_closed);
+    }
+}
+
+fn spawn_part(harness: &mut Harness, part: BodyPart, position: Vec3) {
+    harness.app.world
+        .spawn()
+        .insert(part)
+        .insert(Transform::from_translation(position));
+}
@@ -0,0 +1,256 @@
+// Shared by the gameplay tests, not every test file uses every helper
+#![allow(dead_code)]
+
+use std::{env, fs, path::PathBuf, process};
+use bevy::{
+    ecs::event::{Event, Events, ManualEventReader},
+    input::InputPlugin,
+    prelude::*,
+};
+use bevy_ecs_ldtk::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+use project_bones::{
+    components_events::*,
+    dialogue::{apply_dialogue_effects, parse_dialogue, DialogueEffect, DialogueLibrary, DialogueRunner},
+    dialogue_ui::Typewriter,
+    flags::FlagsPlugin,
+    movement::MovementPlugin,
+    puzzle::{Puzzle, PuzzlePlugin},
+    systems::InteractionsPlugin,
+    GameState,
+};
+
+const DIALOGUE_FILE: &str = "assets/data/dialogue/reception.dlg";
+
+// runs between leafwing ticking last frame's presses and the gameplay systems
+const SCRIPTED_INPUT: &str = "scripted_input";
+
+/// A windowless app with input and the player, the body at the origin, in
+/// `state`. Nothing is scripted and no gameplay plugin is added, `Harness`
+/// and the replay tests build on it.
+pub fn game(state: GameState) -> App {
+    let mut app = App::new();
+    app
+        .add_plugins(MinimalPlugins)
+        .add_plugin(InputPlugin)
+        .add_plugin(InputManagerPlugin::<Action>::default())
+        .add_state(state);
+
+    app.world
+        .spawn()
+        .insert_bundle(PlayerParentBundle::default())
+        .insert(ActionState::<Action>::default());
+    app.world
+        .spawn()
+        .insert(BodyPart::Body)
+        .insert(Transform::from_xyz(0., 0., 10.));
+    app
+}
+
+/// A path in the temp directory no other test or test run uses.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("project_bones_{}_{}", name, process::id()))
+}
+
+/// A windowless game with the gameplay plugins, already `Running`, and a
+/// synthetic level that starts with just the player's body at the origin.
+///
+/// Input is scripted by pressing and releasing `Action`s, which reach the
+/// player's `ActionState` at the start of the next `step`. The player has no
+/// `InputMap`, so leafwing only ticks the state and never overwrites it.
+pub struct Harness {
+    pub app: App,
+}
+
+#[derive(Debug, Default)]
+struct ScriptedInput {
+    press: Vec<Action>,
+    release: Vec<Action>,
+}
+
+struct EventCursor<E: Event>(ManualEventReader<E>);
+
+impl Harness {
+    pub fn new() -> Self {
+        let mut library = DialogueLibrary::default();
+        let source = fs::read_to_string(DIALOGUE_FILE).expect("the reception dialogue is readable");
+        for dialogue in parse_dialogue(&source).expect("the reception dialogue parses") {
+            library.insert(dialogue);
+        }
+
+        let mut app = game(GameState::Running);
+        app
+            // what DialoguePlugin would set up, without the asset loader
+            .insert_resource(library)
+            .init_resource::<DialogueRunner>()
+            .init_resource::<Typewriter>()
+            .add_event::<DialogueEffect>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(apply_dialogue_effects)
+            )
+
+            .add_plugin(FlagsPlugin)
+            .add_plugin(MovementPlugin)
+            .add_plugin(InteractionsPlugin)
+            .add_plugin(PuzzlePlugin)
+
+            .init_resource::<ScriptedInput>()
+            .add_stage_after(CoreStage::PreUpdate, SCRIPTED_INPUT, SystemStage::single_threaded())
+            .add_system_to_stage(SCRIPTED_INPUT, apply_scripted_input);
+
+        let mut harness = Harness { app };
+        // enters Running and lets detect_interactables see the level once
+        harness.step();
+        harness
+    }
+
+    // LEVEL
+
+    /// Spawns an interactable the way LDtk would for an entity with this
+    /// identifier and no fields set, like a `Gum_Machine`.
+    pub fn spawn_ldtk_entity(&mut self, identifier: &str, iid: &str, position: Vec2) -> Entity {
+        let instance = EntityInstance {
+            identifier: identifier.to_string(),
+            iid: iid.to_string(),
+            ..default()
+        };
+        let interactable = Interactable::from(instance.clone());
+        self.app.world
+            .spawn()
+            .insert(instance)
+            .insert(interactable)
+            .insert(Transform::from_translation(position.extend(5.)))
+            .id()
+    }
+
+    /// For interactables whose LDtk fields matter, built by hand.
+    pub fn spawn_interactable(&mut self, iid: &str, interactable: Interactable, position: Vec2) -> Entity {
+        self.app.world
+            .spawn()
+            .insert(EntityInstance {
+                iid: iid.to_string(),
+                ..default()
+            })
+            .insert(interactable)
+            .insert(Transform::from_translation(position.extend(5.)))
+            .id()
+    }
+
+    pub fn spawn_puzzle(&mut self, puzzle: Puzzle) -> Entity {
+        self.app.world.spawn().insert(puzzle).id()
+    }
+
+    // INPUT
+
+    /// Held from the next step until released.
+    pub fn press(&mut self, action: Action) {
+        self.app.world.resource_mut::<ScriptedInput>().press.push(action);
+    }
+
+    pub fn release(&mut self, action: Action) {
+        self.app.world.resource_mut::<ScriptedInput>().release.push(action);
+    }
+
+    /// Pressed for exactly one frame.
+    pub fn tap(&mut self, action: Action) {
+        self.press(action);
+        self.step();
+        self.release(action);
+    }
+
+    pub fn hold(&mut self, action: Action, frames: usize) {
+        self.press(action);
+        self.run(frames);
+        self.release(action);
+    }
+
+    // FRAMES
+
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    pub fn run(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
+    // ASSERTIONS
+
+    pub fn position(&mut self, part: BodyPart) -> Vec3 {
+        let world = &mut self.app.world;
+        world
+            .query::<(&BodyPart, &Transform)>()
+            .iter(world)
+            .find(|(listed, _)| **listed == part)
+            .map(|(_, transform)| transform.translation)
+            .unwrap_or_else(|| panic!("{:?} is not in the level", part))
+    }
+
+    pub fn body_parts(&mut self) -> BodyParts {
+        let world = &mut self.app.world;
+        world.query::<&BodyParts>().single(world).clone()
+    }
+
+    pub fn player(&mut self) -> Player {
+        let world = &mut self.app.world;
+        *world.query::<&Player>().single(world)
+    }
+
+    /// Item ids, in slot order.
+    pub fn inventory(&mut self) -> Vec<String> {
+        let world = &mut self.app.world;
+        world
+            .query::<&Inventory>()
+            .single(world)
+            .0
+            .iter()
+            .flatten()
+            .map(|item| item.id.clone())
+            .collect()
+    }
+
+    pub fn resource<R: Send + Sync + 'static>(&self) -> &R {
+        self.app.world.resource::<R>()
+    }
+
+    /// Events of this type sent since the last call, mapped to something
+    /// the test can keep. Bevy drops events two frames after they are sent,
+    /// so read them within a step of the frame that sent them.
+    pub fn events<E: Event, T>(&mut self, map: impl FnMut(&E) -> T) -> Vec<T> {
+        let mut cursor = self.app.world
+            .remove_resource::<EventCursor<E>>()
+            .unwrap_or_else(|| EventCursor(ManualEventReader::default()));
+        let read = cursor.0
+            .iter(self.app.world.resource::<Events<E>>())
+            .map(map)
+            .collect();
+        self.app.world.insert_resource(cursor);
+        read
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Harness::new()
+    }
+}
+
+fn apply_scripted_input(
+    mut script: ResMut<ScriptedInput>,
+    mut action_states: Query<&mut ActionState<Action>, With<Player>>,
+) {
+    for mut action_state in action_states.iter_mut() {
+        for action in script.release.iter() {
+            action_state.release(*action);
+        }
+        for action in script.press.iter() {
+            action_state.press(*action);
+        }
+    }
+    script.press.clear();
+    script.release.clear();
+}
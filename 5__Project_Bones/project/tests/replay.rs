@@ -0,0 +1,216 @@
+mod harness;
+
+use std::fs;
+use bevy::{
+    app::AppExit,
+    prelude::*,
+};
+use leafwing_input_manager::{prelude::*, Actionlike};
+use rand::Rng;
+
+use project_bones::{
+    GameState,
+    camera::Cursor,
+    components_events::*,
+    dialogue::DialogueRunner,
+    movement::MovementPlugin,
+    navigation::NavGrid,
+    npc::{npc_behavior, NpcBehavior, NpcMovement},
+    replay::*,
+};
+
+fn recording_path(name: &str) -> std::path::PathBuf {
+    harness::temp_path(name).with_extension("replay")
+}
+
+fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+    line.split_whitespace().map(str::to_string)
+}
+
+// A new game with the body and one wandering NPC, on a room NPCs can walk.
+// Both start from the main menu like the real game, the replay takes it from there.
+fn game(mode: ReplayMode) -> App {
+    let mut nav_grid = NavGrid::default();
+    nav_grid.add_level(Vec2::splat(-160.), Vec2::splat(320.));
+
+    let mut app = harness::game(GameState::MainMenu);
+    app
+        .add_plugin(ReplayPlugin { mode })
+        .init_resource::<Cursor>()
+        .init_resource::<DialogueRunner>()
+        .insert_resource(nav_grid)
+        .add_plugin(MovementPlugin)
+        .add_system_set(
+            SystemSet::on_update(GameState::Running)
+                .with_system(npc_behavior)
+        );
+
+    app.world
+        .spawn()
+        .insert(NpcBehavior::Wander { radius: 64. })
+        .insert(NpcMovement::default())
+        .insert(Transform::from_xyz(0., 0., 10.))
+        .insert(TextureAtlasSprite::default());
+    app
+}
+
+// the player holds each action for its number of frames, then lets go
+fn record(path: &std::path::Path, seed: u64, script: &[(Action, usize)]) -> App {
+    let mut app = game(ReplayMode::Record { path: path.to_path_buf(), seed });
+    for (action, frames) in script {
+        for frame in 0..*frames {
+            let world = &mut app.world;
+            let mut action_state = world.query::<&mut ActionState<Action>>().single_mut(world);
+            if frame == 0 {
+                action_state.press(*action);
+            }
+            app.update();
+        }
+        let world = &mut app.world;
+        world.query::<&mut ActionState<Action>>().single_mut(world).release(*action);
+    }
+
+    // the checksum is written on the way out
+    app.world.send_event(AppExit);
+    app.update();
+    app
+}
+
+// as many frames as the recording has, and a few to spare for starting up
+fn replay(path: &std::path::Path, frames: usize) -> App {
+    let mut app = game(ReplayMode::Replay(path.to_path_buf()));
+    for _ in 0..frames + 10 {
+        if app.world.resource::<ReplayState>().finished {
+            return app;
+        }
+        app.update();
+    }
+    panic!("the replay of {} frames did not finish", frames);
+}
+
+fn npc_position(app: &mut App) -> Vec3 {
+    let world = &mut app.world;
+    world.query_filtered::<&Transform, With<NpcMovement>>().single(world).translation
+}
+
+fn body_checksum(app: &mut App) -> u64 {
+    let world = &mut app.world;
+    checksum(world.query::<(&BodyPart, &Transform)>().iter(world).map(|(part, transform)| (*part, *transform)))
+}
+
+#[test]
+fn recordings_round_trip_through_the_file() {
+    let path = recording_path("round_trip");
+    let recording = Recording {
+        version: RECORDING_VERSION,
+        seed: 42,
+        timestep: TIMESTEP,
+        ticks: vec![
+            TickInput::default(),
+            TickInput {
+                actions: 1 << Action::Right.index(),
+                aim: Some([0.5, -1.]),
+                cursor: Some([120., 48.]),
+                mouse: 1,
+            },
+        ],
+        checksum: Some(7),
+    };
+
+    write_recording(&path, &recording).unwrap();
+    assert_eq!(read_recording(&path).unwrap(), recording);
+    assert!(recording.ticks[1].pressed(Action::Right));
+    assert!(!recording.ticks[1].pressed(Action::Left));
+
+    let newer = Recording {
+        version: RECORDING_VERSION + 1,
+        ..recording
+    };
+    write_recording(&path, &newer).unwrap();
+    assert!(matches!(read_recording(&path), Err(ReplayError::Version(_))));
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn the_checksum_ignores_query_order_but_not_positions() {
+    let body = (BodyPart::Body, Transform::from_xyz(10., 20., 10.));
+    let arm = (BodyPart::LeftArm, Transform::from_xyz(-30., 0., 10.));
+    let moved_arm = (BodyPart::LeftArm, Transform::from_xyz(-30., 0.5, 10.));
+
+    assert_eq!(checksum([body, arm].into_iter()), checksum([arm, body].into_iter()));
+    assert_ne!(checksum([body, arm].into_iter()), checksum([body, moved_arm].into_iter()));
+}
+
+#[test]
+fn the_mode_comes_from_the_command_line() {
+    assert_eq!(ReplayMode::from_args(args("")), ReplayMode::Off);
+    assert_eq!(
+        ReplayMode::from_args(args("--record run.replay --seed 9")),
+        ReplayMode::Record { path: "run.replay".into(), seed: 9 },
+    );
+    assert_eq!(
+        ReplayMode::from_args(args("--replay run.replay")),
+        ReplayMode::Replay("run.replay".into()),
+    );
+}
+
+#[test]
+fn a_replay_ends_where_its_recording_did() {
+    let path = recording_path("determinism");
+    let script = [(Action::Right, 40), (Action::Up, 25), (Action::Left, 60), (Action::Down, 120)];
+
+    let mut recorded = record(&path, 9, &script);
+    let recording = read_recording(&path).unwrap();
+    assert_eq!(recording.seed, 9);
+    assert_eq!(recording.ticks.len(), 246);
+    assert_eq!(recording.checksum, Some(body_checksum(&mut recorded)));
+    assert!(recording.ticks[0].pressed(Action::Right));
+    assert!(recording.ticks[41].pressed(Action::Up));
+
+    let mut replayed = replay(&path, recording.ticks.len());
+    assert_eq!(replayed.world.resource::<ReplayState>().next, recording.ticks.len());
+    assert_eq!(Some(body_checksum(&mut replayed)), recording.checksum);
+    assert_ne!(body_checksum(&mut replayed), body_checksum(&mut game(ReplayMode::Off)));
+
+    // the NPC wandered off, and took the same random turns both times
+    let wandered = npc_position(&mut recorded);
+    assert_ne!(wandered, Vec3::new(0., 0., 10.));
+    assert_eq!(npc_position(&mut replayed), wandered);
+
+    // drawing as many numbers from the same seed, which another seed doesn't give
+    let mut reseeded = record(&path, 10, &script);
+    assert_eq!(Some(body_checksum(&mut reseeded)), recording.checksum);
+    let next_draw = |app: &mut App| app.world.resource_mut::<GameRng>().0.gen::<u64>();
+    let drawn = next_draw(&mut recorded);
+    assert_eq!(next_draw(&mut replayed), drawn);
+    assert_ne!(next_draw(&mut reseeded), drawn);
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn a_run_that_never_quits_still_leaves_a_replay() {
+    let path = recording_path("crash");
+    let mut crashed = game(ReplayMode::Record { path: path.clone(), seed: 3 });
+    {
+        let world = &mut crashed.world;
+        world.query::<&mut ActionState<Action>>().single_mut(world).press(Action::Right);
+    }
+    for _ in 0..30 {
+        crashed.update();
+    }
+
+    // read while the game is still running, as if it had died here
+    let recording = read_recording(&path).unwrap();
+    assert_eq!(recording.ticks.len(), 30);
+    assert_eq!(recording.checksum, None);
+    assert!(recording.ticks.iter().all(|tick| tick.pressed(Action::Right)));
+
+    let mut replayed = replay(&path, recording.ticks.len());
+    assert_eq!(replayed.world.resource::<ReplayState>().next, 30);
+    assert_eq!(body_checksum(&mut replayed), body_checksum(&mut crashed));
+
+    drop(crashed);
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,316 @@
+mod harness;
+
+use std::fs;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+use project_bones::{
+    components_events::*,
+    flags::{FlagValue, GameFlags},
+    limbs::{BodySheet, Limb},
+    pause::PlayTime,
+    puzzle::{Puzzle, PuzzleKind},
+    quest::{QuestLog, QuestProgress},
+    save::*,
+};
+
+// a fresh directory per test, so tests running side by side keep apart
+fn save_dir(name: &str) -> SaveDir {
+    let dir = harness::temp_path(name);
+    let _ = fs::remove_dir_all(&dir);
+    SaveDir(dir)
+}
+
+fn app(save_dir: SaveDir) -> App {
+    let mut app = App::new();
+    app
+        .add_plugins(MinimalPlugins)
+        .insert_resource(save_dir)
+        .insert_resource(BodySheet(Handle::default()))
+        .init_resource::<ActiveSlot>()
+        .init_resource::<CurrentRoom>()
+        .init_resource::<PlayTime>()
+        .init_resource::<GameFlags>()
+        .init_resource::<QuestLog>()
+        .add_event::<SaveGame>()
+        .add_event::<LoadGame>()
+        .add_system(save_game)
+        .add_system(load_game.after(save_game));
+
+    let mut body_parts = BodyParts::default();
+    body_parts.push(BodyPart::LeftArm);
+    body_parts.select(BodyPart::Body);
+    let mut inventory = Inventory::default();
+    inventory.give(Item { id: "Gumball".to_string() });
+
+    app.world.spawn().insert_bundle(PlayerParentBundle {
+        body_parts,
+        inventory,
+        ..default()
+    });
+    app.world.spawn().insert(BodyPart::Body).insert(Transform::from_xyz(40., 24., 10.));
+    app.world
+        .spawn()
+        .insert(BodyPart::LeftArm)
+        .insert(Limb::new(BodyPart::LeftArm))
+        .insert(Transform::from_xyz(60., 24., 10.));
+
+    app.world
+        .spawn()
+        .insert(EntityInstance {
+            identifier: "WoodDoor".to_string(),
+            iid: "door".to_string(),
+            ..default()
+        })
+        .insert(Interactable {
+            interaction_type: InteractionType::Door,
+            door: Some(Door { closed: false, ..default() }),
+            ..default()
+        });
+    app.world
+        .spawn()
+        .insert(EntityInstance {
+            identifier: "Vendor".to_string(),
+            iid: "vendor".to_string(),
+            ..default()
+        })
+        .insert(Interactable {
+            interaction_type: InteractionType::Item,
+            item: None,
+            ..default()
+        });
+    app.world.spawn().insert(Puzzle {
+        id: "levers".to_string(),
+        kind: PuzzleKind::Sequence {
+            solution: vec!["a".to_string(), "b".to_string()],
+            entered: vec!["a".to_string()],
+        },
+        ..default()
+    });
+
+    app.world.resource_mut::<GameFlags>().set("met_vendor", true);
+    app.world.resource_mut::<GameFlags>().set("coins", 3);
+    app.world.resource_mut::<QuestLog>().0.push(QuestProgress {
+        id: "check_in".to_string(),
+        step: 1,
+        completed: false,
+    });
+    app.world.resource_mut::<CurrentRoom>().0 = Some(2);
+    app.world.resource_mut::<PlayTime>().0 = 90.;
+    app
+}
+
+// Puts everything the save covers back to how a new game starts
+fn scramble(app: &mut App) {
+    let world = &mut app.world;
+    *world.query::<&mut BodyParts>().single_mut(world) = BodyParts::default();
+    *world.query::<&mut Inventory>().single_mut(world) = Inventory::default();
+    for (part, mut transform) in world.query::<(&BodyPart, &mut Transform)>().iter_mut(world) {
+        if *part == BodyPart::Body {
+            transform.translation = Vec3::new(0., 0., 10.);
+        }
+    }
+    for mut interactable in world.query::<&mut Interactable>().iter_mut(world) {
+        if let Some(door) = interactable.door.as_mut() {
+            door.closed = true;
+        }
+        if interactable.interaction_type == InteractionType::Item {
+            interactable.item = Some(Item::default());
+        }
+    }
+    for mut puzzle in world.query::<&mut Puzzle>().iter_mut(world) {
+        puzzle.kind = PuzzleKind::Sequence { solution: Vec::new(), entered: Vec::new() };
+    }
+    world.resource_mut::<GameFlags>().replace([("coins".to_string(), FlagValue::Int(0))]);
+    world.resource_mut::<QuestLog>().0.clear();
+    world.resource_mut::<CurrentRoom>().0 = None;
+    world.resource_mut::<PlayTime>().0 = 0.;
+}
+
+#[test]
+fn save_data_round_trips_through_a_slot() {
+    let dir = save_dir("slot");
+    let mut app = app(dir.clone());
+
+    app.world.send_event(SaveGame(2));
+    app.update();
+
+    let data = read_save(&dir, 2).expect("slot 2 was written");
+    assert_eq!(data.version, SAVE_VERSION);
+    assert_eq!(data.body_parts, vec![BodyPart::Body, BodyPart::LeftArm]);
+    assert_eq!(data.selected, BodyPart::Body);
+    assert_eq!(data.inventory[0], Some("Gumball".to_string()));
+    assert_eq!(data.opened_doors, vec!["door".to_string()]);
+    assert_eq!(data.consumed_items, vec!["vendor".to_string()]);
+    assert_eq!(data.flags.get("met_vendor"), Some(&FlagValue::Bool(true)));
+    assert_eq!(data.flags.get("coins"), Some(&FlagValue::Int(3)));
+    assert_eq!(data.room, Some(2));
+
+    write_save(&dir, 3, &data).unwrap();
+    assert_eq!(read_save(&dir, 3).unwrap(), data);
+    assert_eq!(*app.world.resource::<ActiveSlot>(), ActiveSlot(2));
+}
+
+#[test]
+fn loading_restores_the_world() {
+    let dir = save_dir("world");
+    let mut app = app(dir);
+
+    app.world.send_event(SaveGame(1));
+    app.update();
+    scramble(&mut app);
+    app.world.send_event(LoadGame(1));
+    app.update();
+
+    let world = &mut app.world;
+    let body_parts = world.query::<&BodyParts>().single(world).clone();
+    assert_eq!(body_parts.parts(), &[BodyPart::Body, BodyPart::LeftArm]);
+    assert_eq!(body_parts.current(), BodyPart::Body);
+    assert!(world.query::<&Inventory>().single(world).contains("Gumball"));
+
+    let body = world
+        .query::<(&BodyPart, &Transform)>()
+        .iter(world)
+        .find(|(part, _)| **part == BodyPart::Body)
+        .map(|(_, transform)| transform.translation)
+        .unwrap();
+    assert_eq!(body, Vec3::new(40., 24., 10.));
+
+    // the old limb is replaced by one spawned where it was saved
+    let limbs: Vec<Vec3> = world
+        .query_filtered::<&Transform, With<Limb>>()
+        .iter(world)
+        .map(|transform| transform.translation)
+        .collect();
+    assert_eq!(limbs, vec![Vec3::new(60., 24., 0.)]);
+
+    for interactable in world.query::<&Interactable>().iter(world) {
+        match interactable.interaction_type {
+            InteractionType::Door => assert!(!interactable.door.as_ref().unwrap().closed),
+            InteractionType::Item => assert!(interactable.item.is_none()),
+            _ => {}
+        }
+    }
+    let puzzle = world.query::<&Puzzle>().single(world);
+    assert_eq!(
+        puzzle.kind,
+        PuzzleKind::Sequence {
+            solution: vec!["a".to_string(), "b".to_string()],
+            entered: vec!["a".to_string()],
+        }
+    );
+
+    let flags = world.resource::<GameFlags>();
+    assert!(flags.is_set("met_vendor"));
+    assert_eq!(flags.get("coins"), Some(&FlagValue::Int(3)));
+    let quests = world.resource::<QuestLog>();
+    assert_eq!(quests.get("check_in").map(|progress| progress.step), Some(1));
+    assert!(!quests.is_completed("check_in"));
+    assert_eq!(world.resource::<CurrentRoom>().0, Some(2));
+    assert_eq!(world.resource::<PlayTime>().0, 90.);
+}
+
+#[test]
+fn loading_undoes_what_happened_since_the_save() {
+    let dir = save_dir("undo");
+    let mut app = app(dir);
+
+    // saved with the door still closed and the vendor still stocked
+    for mut interactable in app.world.query::<&mut Interactable>().iter_mut(&mut app.world) {
+        if let Some(door) = interactable.door.as_mut() {
+            door.closed = true;
+        }
+        if interactable.interaction_type == InteractionType::Item {
+            interactable.item = Some(Item::default());
+        }
+    }
+    app.world.send_event(SaveGame(1));
+    app.update();
+
+    // then opened and emptied before loading
+    for mut interactable in app.world.query::<&mut Interactable>().iter_mut(&mut app.world) {
+        if let Some(door) = interactable.door.as_mut() {
+            door.closed = false;
+        }
+        interactable.item = None;
+    }
+    app.world.send_event(LoadGame(1));
+    app.update();
+
+    let world = &mut app.world;
+    for interactable in world.query::<&Interactable>().iter(world) {
+        match interactable.interaction_type {
+            InteractionType::Door => assert!(interactable.door.as_ref().unwrap().closed),
+            InteractionType::Item => assert!(interactable.item.is_some()),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn saves_from_another_version_are_refused() {
+    let dir = save_dir("version");
+    let mut app = app(dir.clone());
+
+    app.world.send_event(SaveGame(1));
+    app.update();
+
+    let path = dir.slot_path(1);
+    let string_data = fs::read_to_string(&path).unwrap();
+    let mut value: serde_json::Value = serde_json::from_str(&string_data).unwrap();
+    value["version"] = serde_json::json!(SAVE_VERSION + 1);
+    fs::write(&path, value.to_string()).unwrap();
+
+    assert!(matches!(read_save(&dir, 1), Err(SaveError::Version(version)) if version == SAVE_VERSION + 1));
+}
+
+#[test]
+fn continue_picks_the_newest_slot() {
+    let dir = save_dir("newest");
+    let mut app = app(dir.clone());
+    assert!(!dir.any_exists());
+
+    app.world.send_event(SaveGame(AUTOSAVE_SLOT));
+    app.update();
+    let mut data = read_save(&dir, AUTOSAVE_SLOT).unwrap();
+    data.saved_at += 60;
+    write_save(&dir, 3, &data).unwrap();
+
+    assert!(dir.any_exists());
+    assert_eq!(dir.newest(), Some(3));
+    // the autosave never becomes the slot the pause menu saves to
+    assert_eq!(*app.world.resource::<ActiveSlot>(), ActiveSlot(1));
+}
+
+#[test]
+fn a_new_game_starts_over_from_the_ldtk_file() {
+    let mut app = app(save_dir("new_game"));
+    app
+        .add_plugin(AssetPlugin)
+        .add_event::<NewGame>()
+        .add_system(new_game);
+
+    // the world left standing by the last session, with its door open
+    let door = app.world.query_filtered::<Entity, With<Interactable>>().iter(&app.world).next().unwrap();
+    let old_world = app.world
+        .spawn()
+        .insert(Handle::<LdtkAsset>::default())
+        .push_children(&[door])
+        .id();
+
+    app.world.send_event(NewGame);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.get_entity(old_world).is_none());
+    assert!(world.get_entity(door).is_none());
+    assert_eq!(world.query_filtered::<(), With<Handle<LdtkAsset>>>().iter(world).count(), 1);
+
+    assert_eq!(world.query::<&Limb>().iter(world).count(), 0);
+    assert_eq!(world.query::<&BodyParts>().single(world).parts(), &[BodyPart::Body]);
+    assert!(world.query::<&Inventory>().single(world).0.iter().all(Option::is_none));
+    assert_eq!(world.resource::<GameFlags>().to_map().len(), 0);
+    assert!(world.resource::<QuestLog>().0.is_empty());
+    assert_eq!(*world.resource::<CurrentRoom>(), CurrentRoom(None));
+    assert_eq!(world.resource::<PlayTime>().0, 0.);
+}